@@ -67,12 +67,9 @@ fn main() {
 // world space cordinates are in terms of 4 voxels per meter with 0, 0
 // in the world lining up with the center of the voxel world and the edge
 // of the world being half of the world size in each direction
-fn setup(
-    mut commands: Commands,
-    mut load_voxel_world: ResMut<LoadVoxelWorld>,
-    asset_server: Res<AssetServer>,
-) {
-    *load_voxel_world = LoadVoxelWorld::File("assets/monu9.vox".to_string());
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // reloads the world whenever monu9.vox changes on disk
+    commands.insert_resource(ActiveVoxelWorld(asset_server.load("monu9.vox")));
 
     let mut portals = vec![None; 2];
     for i in 0..2 {
@@ -315,12 +312,20 @@ fn shoot(
     }
 }
 
-fn update_fire(mut particle_query: Query<(Entity, &mut Particle)>, mut commands: Commands) {
+fn update_fire(
+    mut particle_query: Query<(Entity, &mut Particle)>,
+    material_registry: Res<MaterialRegistry>,
+    mut commands: Commands,
+) {
+    let Some(fire) = material_registry.get(MaterialRegistry::FIRE) else {
+        return;
+    };
     let mut rand = rand::thread_rng();
     for (entity, mut particle) in particle_query.iter_mut() {
-        if particle.material >= 9 && particle.material <= 13 {
+        if fire.contains(particle.material) {
             particle.material += rand.gen_range(0.0..1.02) as u8;
-            if particle.material == 11 {
+            // burnt out past the last stage of fire
+            if !fire.contains(particle.material) {
                 commands.entity(entity).despawn();
             }
         }
@@ -8,7 +8,8 @@ use bevy_egui::{
     EguiContexts, EguiPlugin,
 };
 use bevy_voxel_engine::{
-    DenoiseSettings, Flags, LoadVoxelWorld, RenderGraphSettings, TraceSettings, VoxelPhysics,
+    DenoiseSettings, Flags, LoadVoxelWorld, RenderGraphSettings, SaveVoxelWorld, TraceSettings,
+    VoxelPhysics, VoxelWorldLoadError, VoxelWorldLoadProgress,
 };
 use rand::Rng;
 
@@ -25,6 +26,10 @@ fn ui_system(
     mut contexts: EguiContexts,
     particle_query: Query<Entity, (With<VoxelPhysics>, Without<CharacterEntity>)>,
     mut load_voxel_world: ResMut<LoadVoxelWorld>,
+    mut save_voxel_world: ResMut<SaveVoxelWorld>,
+    mut load_errors: EventReader<VoxelWorldLoadError>,
+    mut last_load_error: Local<Option<String>>,
+    load_progress: Res<VoxelWorldLoadProgress>,
     mut render_graph_settings: ResMut<RenderGraphSettings>,
     mut camera_settings_query: Query<(
         &mut TraceSettings,
@@ -37,6 +42,9 @@ fn ui_system(
     mut character_query: Query<&mut CharacterEntity>,
 ) {
     let mut character = character_query.single_mut();
+    if let Some(error) = load_errors.iter().last() {
+        *last_load_error = Some(error.to_string());
+    }
 
     egui::Window::new("Settings")
         .anchor(egui::Align2::RIGHT_TOP, [-5.0, 5.0])
@@ -46,8 +54,27 @@ fn ui_system(
                 //     .add_filter("Magica Voxel VOX File", &["vox"])
                 //     .pick_file().block_on();
 
-                let path = tinyfiledialogs::open_file_dialog("Select file", "", None);
-                *load_voxel_world = LoadVoxelWorld::File(path.unwrap());
+                if let Some(path) = tinyfiledialogs::open_file_dialog("Select file", "", None) {
+                    *load_voxel_world = LoadVoxelWorld::File(path);
+                    *last_load_error = None;
+                }
+            }
+            if ui.button("Save File").clicked() {
+                if let Some(path) = tinyfiledialogs::save_file_dialog("Save file", "world.vox") {
+                    *save_voxel_world = SaveVoxelWorld::File(path);
+                }
+            }
+            if let Some(error) = last_load_error.as_ref() {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            match *load_progress {
+                VoxelWorldLoadProgress::Parsing => {
+                    ui.label("Loading world...");
+                }
+                VoxelWorldLoadProgress::Uploading(progress) => {
+                    ui.add(egui::ProgressBar::new(progress).show_percentage());
+                }
+                VoxelWorldLoadProgress::Done => {}
             }
             for (i, (mut trace_settings, bloom_settings, tonemapping, fxaa)) in
                 camera_settings_query.iter_mut().enumerate()
@@ -3,8 +3,8 @@ use bevy::{
     prelude::*,
 };
 use bevy_voxel_engine::{
-    BevyVoxelEnginePlugin, BoxCollider, Flags, LoadVoxelWorld, VoxelCameraBundle, VoxelPhysics,
-    VoxelizationBundle, VoxelizationMaterial, VoxelizationMaterialType,
+    BevyVoxelEnginePlugin, BoxCollider, Flags, LoadVoxelWorld, MaterialRegistry, VoxelCameraBundle,
+    VoxelPhysics, VoxelizationBundle, VoxelizationMaterial, VoxelizationMaterialType,
 };
 use character::CharacterEntity;
 
@@ -84,6 +84,7 @@ fn update(
     mut sand_spawner: Query<(&mut Transform, &mut VoxelizationMaterial), With<SandSpawner>>,
     character_query: Query<&Transform, (With<CharacterEntity>, Without<SandSpawner>)>,
     input: Res<Input<MouseButton>>,
+    material_registry: Res<MaterialRegistry>,
 ) {
     let character = character_query.single();
     let (mut sand_spawner, mut sand_material) = sand_spawner.single_mut();
@@ -91,7 +92,8 @@ fn update(
     sand_spawner.translation = character.translation - character.local_z() * 10.0;
 
     if input.pressed(MouseButton::Left) {
-        sand_material.material = VoxelizationMaterialType::Material(8);
+        let water = material_registry.index(MaterialRegistry::WATER).unwrap();
+        sand_material.material = VoxelizationMaterialType::Material(water);
     } else {
         sand_material.material = VoxelizationMaterialType::Material(0);
     }
@@ -0,0 +1,209 @@
+use crate::{load::VoxelAnimation, Flags};
+use bevy::prelude::*;
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<VoxelAnimationFinished>()
+            .add_system(advance_voxel_animations);
+    }
+}
+
+/// What a `VoxelAnimationPlayer` does once it reaches the last frame.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelAnimationMode {
+    /// Starts again from the first frame.
+    #[default]
+    Loop,
+    /// Plays back to the first frame and then forwards again.
+    PingPong,
+    /// Stops on the last frame.
+    Once,
+}
+
+/// Plays a `VoxelAnimation`, drawing its current frame into the world every frame at the full
+/// `Transform` of the entity like a `VoxelModel`.
+#[derive(Component)]
+pub struct VoxelAnimationPlayer {
+    pub animation: Handle<VoxelAnimation>,
+    pub frames_per_second: f32,
+    pub mode: VoxelAnimationMode,
+    /// Material for every voxel of the animation, or 0 to keep the materials it was loaded with.
+    pub material: u8,
+    pub flags: u8,
+    pub paused: bool,
+    /// Frames played since the start of the current cycle.
+    elapsed: f32,
+    finished: bool,
+}
+
+impl VoxelAnimationPlayer {
+    /// A player that starts on the first frame, with `Flags::ANIMATION_FLAG` and the materials of
+    /// the animation.
+    pub fn new(
+        animation: Handle<VoxelAnimation>,
+        frames_per_second: f32,
+        mode: VoxelAnimationMode,
+    ) -> Self {
+        Self {
+            animation,
+            frames_per_second,
+            mode,
+            material: 0,
+            flags: Flags::ANIMATION_FLAG,
+            paused: false,
+            elapsed: 0.0,
+            finished: false,
+        }
+    }
+
+    /// Goes back to the first frame, playing a `VoxelAnimationMode::Once` player again.
+    pub fn restart(&mut self) {
+        self.elapsed = 0.0;
+        self.finished = false;
+    }
+
+    /// Whether a `VoxelAnimationMode::Once` player has reached the end, the others never finish.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The frame to draw of an animation with `frame_count` frames.
+    pub fn frame(&self, frame_count: usize) -> usize {
+        let step = self.elapsed as usize;
+        match self.mode {
+            VoxelAnimationMode::Loop => step % frame_count.max(1),
+            VoxelAnimationMode::PingPong => {
+                let step = step % cycle_length(self.mode, frame_count);
+                if step < frame_count {
+                    step
+                } else {
+                    cycle_length(self.mode, frame_count) - step
+                }
+            }
+            VoxelAnimationMode::Once => step.min(frame_count.saturating_sub(1)),
+        }
+    }
+
+    /// Moves the player on by `frames`, giving how many times it got through an animation with
+    /// `frame_count` frames. A long frame can get through it more than once.
+    fn advance(&mut self, frames: f32, frame_count: usize) -> usize {
+        let cycle = cycle_length(self.mode, frame_count) as f32;
+        self.elapsed += frames;
+        if self.elapsed < cycle {
+            return 0;
+        }
+
+        if self.mode == VoxelAnimationMode::Once {
+            self.finished = true;
+            return 1;
+        }
+        let cycles = (self.elapsed / cycle) as usize;
+        self.elapsed %= cycle;
+        cycles
+    }
+}
+
+/// Sent when a `VoxelAnimationPlayer` gets through its animation, every time it starts again from
+/// the first frame for `Loop` and `PingPong` and once for `Once`.
+#[derive(Clone, Debug)]
+pub struct VoxelAnimationFinished {
+    pub entity: Entity,
+    pub animation: Handle<VoxelAnimation>,
+}
+
+/// Frames in one play through of an animation, a ping pong does not repeat the first and last.
+fn cycle_length(mode: VoxelAnimationMode, frame_count: usize) -> usize {
+    match mode {
+        VoxelAnimationMode::PingPong if frame_count > 1 => 2 * frame_count - 2,
+        _ => frame_count.max(1),
+    }
+}
+
+fn advance_voxel_animations(
+    time: Res<Time>,
+    animations: Res<Assets<VoxelAnimation>>,
+    mut player_query: Query<(Entity, &mut VoxelAnimationPlayer)>,
+    mut finished_events: EventWriter<VoxelAnimationFinished>,
+) {
+    for (entity, mut player) in player_query.iter_mut() {
+        if player.paused || player.finished {
+            continue;
+        }
+        let Some(animation) = animations.get(&player.animation) else {
+            continue;
+        };
+
+        let frames = time.delta_seconds() * player.frames_per_second;
+        let cycles = player.advance(frames, animation.frame_count());
+        for _ in 0..cycles {
+            finished_events.send(VoxelAnimationFinished {
+                entity,
+                animation: player.animation.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_at(mode: VoxelAnimationMode, elapsed: f32) -> VoxelAnimationPlayer {
+        let mut player = VoxelAnimationPlayer::new(Handle::default(), 10.0, mode);
+        player.elapsed = elapsed;
+        player
+    }
+
+    fn frames(mode: VoxelAnimationMode, frame_count: usize, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|step| player_at(mode, step as f32 + 0.5).frame(frame_count))
+            .collect()
+    }
+
+    #[test]
+    fn loops_start_again_from_the_first_frame() {
+        let frames = frames(VoxelAnimationMode::Loop, 3, 7);
+        assert_eq!(frames, [0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn ping_pongs_do_not_repeat_the_ends() {
+        let frames = frames(VoxelAnimationMode::PingPong, 4, 8);
+        assert_eq!(frames, [0, 1, 2, 3, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let frames = frames(VoxelAnimationMode::Once, 3, 6);
+        assert_eq!(frames, [0, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn single_frames_always_show_the_first_frame() {
+        for mode in [
+            VoxelAnimationMode::Loop,
+            VoxelAnimationMode::PingPong,
+            VoxelAnimationMode::Once,
+        ] {
+            assert_eq!(frames(mode, 1, 5), [0; 5]);
+        }
+    }
+
+    #[test]
+    fn long_frames_count_every_cycle() {
+        let mut player = player_at(VoxelAnimationMode::PingPong, 0.0);
+        // a cycle of 4 frames is 6 steps
+        assert_eq!(player.advance(5.0, 4), 0);
+        assert_eq!(player.advance(20.0, 4), 4);
+        assert_eq!(player.frame(4), 1);
+
+        let mut player = player_at(VoxelAnimationMode::Loop, 0.0);
+        assert_eq!(player.advance(3.5, 1), 3);
+
+        let mut player = player_at(VoxelAnimationMode::Once, 0.0);
+        assert_eq!(player.advance(10.0, 3), 1);
+        assert!(player.is_finished());
+    }
+}
@@ -0,0 +1,463 @@
+use crate::{
+    load::GH,
+    voxel_pipeline::{
+        compute::{EditData, MAX_EDITS},
+        voxel_world::VoxelUniforms,
+    },
+    FloatingOrigin, VoxelStreaming, VoxelWorldMirror,
+};
+use bevy::{prelude::*, render::renderer::RenderQueue};
+use std::{collections::VecDeque, sync::atomic::Ordering};
+
+/// Most voxel checks a frame of edits may cost, the volume covered by a batch times its edits.
+/// Edits past this wait for the next frame, a single edit is always applied.
+const EDIT_BUDGET: u64 = 1 << 26;
+
+pub struct EditPlugin;
+
+impl Plugin for EditPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelEdits>()
+            .add_system(extract_voxel_edits.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+/// A permanent change to the voxel world. Positions are voxel coordinates from 0 to the world
+/// size on each axis with y up, like `GH::set_voxel`, and boxes include both corners.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelEdit {
+    FillBox {
+        min: IVec3,
+        max: IVec3,
+        material: u8,
+        flags: u8,
+    },
+    FillSphere {
+        centre: IVec3,
+        radius: f32,
+        material: u8,
+        flags: u8,
+    },
+    /// Swaps every `from` voxel in the box to `to`, keeping its flags.
+    ReplaceMaterial {
+        min: IVec3,
+        max: IVec3,
+        from: u8,
+        to: u8,
+    },
+    SetFlags {
+        min: IVec3,
+        max: IVec3,
+        flags: u8,
+    },
+    ClearFlags {
+        min: IVec3,
+        max: IVec3,
+        flags: u8,
+    },
+}
+
+impl VoxelEdit {
+    /// Corners of the box of voxels the edit can change.
+    pub fn bounds(&self) -> (IVec3, IVec3) {
+        match *self {
+            VoxelEdit::FillBox { min, max, .. }
+            | VoxelEdit::ReplaceMaterial { min, max, .. }
+            | VoxelEdit::SetFlags { min, max, .. }
+            | VoxelEdit::ClearFlags { min, max, .. } => (min.min(max), min.max(max)),
+            VoxelEdit::FillSphere { centre, radius, .. } => {
+                // huge spheres saturate at the edge of the i32 range instead of overflowing
+                let radius = radius.max(0.0).floor() as i64;
+                let corner = |offset: i64| {
+                    IVec3::from_array(centre.to_array().map(|c| {
+                        (c as i64)
+                            .saturating_add(offset)
+                            .clamp(i32::MIN as i64, i32::MAX as i64) as i32
+                    }))
+                };
+                (corner(-radius), corner(radius))
+            }
+        }
+    }
+
+    /// Applies the edit to a world on the cpu, the same way `edit.wgsl` does on the gpu.
+    pub fn apply(&self, gh: &mut GH) {
+        let world_max = IVec3::splat(gh.texture_size as i32 - 1);
+        let (min, max) = self.bounds();
+        let (min, max) = (min.max(IVec3::ZERO), max.min(world_max));
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let pos = IVec3::new(x, y, z);
+                    let (material, flags) = gh.get_voxel(pos.as_uvec3());
+                    let (material, flags) = match *self {
+                        VoxelEdit::FillBox {
+                            material, flags, ..
+                        } => (material, flags),
+                        VoxelEdit::FillSphere {
+                            centre,
+                            radius,
+                            material: new_material,
+                            flags: new_flags,
+                        } => {
+                            // in floats, far away centres of huge spheres overflow an i32
+                            if (pos.as_vec3() - centre.as_vec3()).length() <= radius {
+                                (new_material, new_flags)
+                            } else {
+                                (material, flags)
+                            }
+                        }
+                        VoxelEdit::ReplaceMaterial { from, to, .. } => {
+                            (if material == from { to } else { material }, flags)
+                        }
+                        VoxelEdit::SetFlags {
+                            flags: new_flags, ..
+                        } => (material, flags | new_flags),
+                        VoxelEdit::ClearFlags {
+                            flags: old_flags, ..
+                        } => (material, flags & !old_flags),
+                    };
+                    gh.set_voxel(pos.as_uvec3(), material, flags);
+                }
+            }
+        }
+    }
+
+    fn push_to(&self, data: &mut Vec<u32>) {
+        let (min, max) = self.bounds();
+        let (edit_type, a, b, radius, centre) = match *self {
+            VoxelEdit::FillBox {
+                material, flags, ..
+            } => (0, material, flags, 0.0, IVec3::ZERO),
+            VoxelEdit::FillSphere {
+                centre,
+                radius,
+                material,
+                flags,
+            } => (1, material, flags, radius, centre),
+            VoxelEdit::ReplaceMaterial { from, to, .. } => (2, from, to, 0.0, IVec3::ZERO),
+            VoxelEdit::SetFlags { flags, .. } => (3, flags, 0, 0.0, IVec3::ZERO),
+            VoxelEdit::ClearFlags { flags, .. } => (4, flags, 0, 0.0, IVec3::ZERO),
+        };
+        data.push(edit_type);
+        data.extend(min.to_array().map(bytemuck::cast::<i32, u32>));
+        data.extend(max.to_array().map(bytemuck::cast::<i32, u32>));
+        data.push(a as u32);
+        data.push(b as u32);
+        data.push(bytemuck::cast(radius));
+        data.extend(centre.to_array().map(bytemuck::cast::<i32, u32>));
+    }
+}
+
+/// Queue of `VoxelEdit`s that are written into the voxel world on the gpu in the order they
+/// were added. Unlike `Particle`s and `Box`es the voxels stay until they are edited again and
+/// the grid hierarchy picks them up when it is rebuilt that frame.
+#[derive(Resource, Default)]
+pub struct VoxelEdits {
+    edits: VecDeque<VoxelEdit>,
+}
+
+impl VoxelEdits {
+    pub fn push(&mut self, edit: VoxelEdit) -> &mut Self {
+        self.edits.push_back(edit);
+        self
+    }
+
+    pub fn set_voxel(&mut self, pos: IVec3, material: u8, flags: u8) -> &mut Self {
+        self.fill_box(pos, pos, material, flags)
+    }
+
+    pub fn fill_box(&mut self, min: IVec3, max: IVec3, material: u8, flags: u8) -> &mut Self {
+        self.push(VoxelEdit::FillBox {
+            min,
+            max,
+            material,
+            flags,
+        })
+    }
+
+    pub fn fill_sphere(
+        &mut self,
+        centre: IVec3,
+        radius: f32,
+        material: u8,
+        flags: u8,
+    ) -> &mut Self {
+        self.push(VoxelEdit::FillSphere {
+            centre,
+            radius,
+            material,
+            flags,
+        })
+    }
+
+    pub fn replace_material(&mut self, min: IVec3, max: IVec3, from: u8, to: u8) -> &mut Self {
+        self.push(VoxelEdit::ReplaceMaterial { min, max, from, to })
+    }
+
+    pub fn set_flags(&mut self, min: IVec3, max: IVec3, flags: u8) -> &mut Self {
+        self.push(VoxelEdit::SetFlags { min, max, flags })
+    }
+
+    pub fn clear_flags(&mut self, min: IVec3, max: IVec3, flags: u8) -> &mut Self {
+        self.push(VoxelEdit::ClearFlags { min, max, flags })
+    }
+
+    /// Edits that have not been sent to the gpu yet.
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Takes every queued edit, oldest first.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = VoxelEdit> + '_ {
+        self.edits.drain(..)
+    }
+}
+
+/// Takes the next batch of edits off the queue and writes it to the edit buffer, the batch is
+/// applied in a single dispatch over the box that all of its edits fit in. Nothing is taken until
+/// the edit pipeline has compiled.
+pub fn extract_voxel_edits(
+    mut voxel_edits: ResMut<VoxelEdits>,
+    mut edit_data: ResMut<EditData>,
+    mut mirror: Option<ResMut<VoxelWorldMirror>>,
+    mut streaming: Option<ResMut<VoxelStreaming>>,
+    floating_origin: Res<FloatingOrigin>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    render_queue: Res<RenderQueue>,
+) {
+    if !edit_data.pipeline_ready.load(Ordering::Relaxed) {
+        edit_data.dispatch_size = UVec3::ZERO;
+        return;
+    }
+
+    let world_max = IVec3::splat(voxel_uniforms.texture_size as i32 - 1);
+    let mut batch_min = IVec3::splat(i32::MAX);
+    let mut batch_max = IVec3::splat(i32::MIN);
+    let mut data = vec![0; 4];
+    let mut edit_count = 0;
+
+    while let Some(edit) = voxel_edits.edits.front() {
+        let (min, max) = edit.bounds();
+        let (min, max) = (min.max(IVec3::ZERO), max.min(world_max));
+        if min.cmpgt(max).any() {
+            // entirely outside of the world
+            voxel_edits.edits.pop_front();
+            continue;
+        }
+
+        let new_min = batch_min.min(min);
+        let new_max = batch_max.max(max);
+        let volume = (new_max - new_min + IVec3::ONE).as_uvec3();
+        let cost = volume.x as u64 * volume.y as u64 * volume.z as u64 * (edit_count + 1);
+        if edit_count > 0 && (cost > EDIT_BUDGET || edit_count as usize == MAX_EDITS) {
+            break;
+        }
+
+        edit.push_to(&mut data);
+        if let Some(mirror) = mirror.as_mut() {
+            edit.apply(&mut mirror.gh);
+        }
+        if let Some(streaming) = streaming.as_mut() {
+            streaming.mark_dirty(
+                floating_origin.to_global(min),
+                floating_origin.to_global(max),
+            );
+        }
+        batch_min = new_min;
+        batch_max = new_max;
+        edit_count += 1;
+        voxel_edits.edits.pop_front();
+    }
+
+    if edit_count == 0 {
+        edit_data.dispatch_size = UVec3::ZERO;
+        return;
+    }
+
+    data[0] = edit_count as u32;
+    data[1..4].copy_from_slice(&batch_min.to_array().map(bytemuck::cast::<i32, u32>));
+    render_queue.write_buffer(&edit_data.edit_buffer, 0, bytemuck::cast_slice(&data));
+
+    let size = (batch_max - batch_min + IVec3::ONE).as_uvec3();
+    edit_data.dispatch_size = (size + UVec3::splat(3)) / 4;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Flags;
+
+    fn voxels(gh: &GH) -> Vec<(UVec3, u8, u8)> {
+        let mut voxels: Vec<_> = gh.voxels().collect();
+        voxels.sort_by_key(|(pos, _, _)| gh.index(*pos));
+        voxels
+    }
+
+    #[test]
+    fn fill_box_includes_both_corners() {
+        let mut gh = GH::empty(32);
+        let edit = VoxelEdit::FillBox {
+            min: IVec3::new(3, 2, 1),
+            max: IVec3::new(1, 2, 2),
+            material: 5,
+            flags: Flags::COLLISION_FLAG,
+        };
+        edit.apply(&mut gh);
+        assert_eq!(gh.voxels().count(), 6);
+        assert_eq!(
+            gh.get_voxel(UVec3::new(1, 2, 1)),
+            (5, Flags::COLLISION_FLAG)
+        );
+        assert_eq!(
+            gh.get_voxel(UVec3::new(3, 2, 2)),
+            (5, Flags::COLLISION_FLAG)
+        );
+        assert_eq!(gh.get_voxel(UVec3::new(0, 2, 1)), (0, 0));
+    }
+
+    #[test]
+    fn fill_sphere_includes_its_surface() {
+        let mut gh = GH::empty(32);
+        let centre = IVec3::splat(10);
+        let edit = VoxelEdit::FillSphere {
+            centre,
+            radius: 2.0,
+            material: 9,
+            flags: Flags::NONE,
+        };
+        edit.apply(&mut gh);
+        assert_eq!(gh.get_voxel(UVec3::new(12, 10, 10)).0, 9);
+        assert_eq!(gh.get_voxel(UVec3::new(10, 8, 10)).0, 9);
+        assert_eq!(gh.get_voxel(UVec3::new(11, 11, 10)).0, 9);
+        assert_eq!(gh.get_voxel(UVec3::new(11, 11, 11)).0, 9);
+        assert_eq!(gh.get_voxel(UVec3::new(12, 11, 10)).0, 0);
+        assert_eq!(gh.get_voxel(UVec3::new(13, 10, 10)).0, 0);
+        for (pos, _, _) in gh.voxels() {
+            assert!((pos.as_ivec3() - centre).as_vec3().length() <= 2.0);
+        }
+    }
+
+    #[test]
+    fn replace_material_keeps_flags() {
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 1, 1), 3, Flags::SAND_FLAG);
+        gh.set_voxel(UVec3::new(2, 1, 1), 4, Flags::SAND_FLAG);
+        gh.set_voxel(UVec3::new(9, 1, 1), 3, Flags::NONE);
+        let edit = VoxelEdit::ReplaceMaterial {
+            min: IVec3::ZERO,
+            max: IVec3::splat(4),
+            from: 3,
+            to: 7,
+        };
+        edit.apply(&mut gh);
+        assert_eq!(
+            voxels(&gh),
+            [
+                (UVec3::new(1, 1, 1), 7, Flags::SAND_FLAG),
+                (UVec3::new(2, 1, 1), 4, Flags::SAND_FLAG),
+                (UVec3::new(9, 1, 1), 3, Flags::NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_and_clear_flags_keep_materials() {
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 1, 1), 3, Flags::COLLISION_FLAG);
+        gh.set_voxel(UVec3::new(2, 1, 1), 4, Flags::SAND_FLAG);
+        let (min, max) = (IVec3::ZERO, IVec3::splat(1));
+        VoxelEdit::SetFlags {
+            min,
+            max,
+            flags: Flags::SAND_FLAG,
+        }
+        .apply(&mut gh);
+        assert_eq!(
+            gh.get_voxel(UVec3::new(1, 1, 1)),
+            (3, Flags::COLLISION_FLAG | Flags::SAND_FLAG)
+        );
+
+        let max = IVec3::new(2, 1, 1);
+        VoxelEdit::ClearFlags {
+            min,
+            max,
+            flags: Flags::SAND_FLAG,
+        }
+        .apply(&mut gh);
+        assert_eq!(
+            voxels(&gh),
+            [
+                (UVec3::new(1, 1, 1), 3, Flags::COLLISION_FLAG),
+                (UVec3::new(2, 1, 1), 4, Flags::NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_are_clipped_to_the_world() {
+        let mut gh = GH::empty(32);
+        VoxelEdit::FillBox {
+            min: IVec3::new(-10, 31, 30),
+            max: IVec3::new(0, 40, 100),
+            material: 1,
+            flags: Flags::NONE,
+        }
+        .apply(&mut gh);
+        assert_eq!(gh.voxels().count(), 2);
+
+        // entirely outside, with bounds that saturate
+        let mut gh = GH::empty(32);
+        for edit in [
+            VoxelEdit::FillBox {
+                min: IVec3::splat(32),
+                max: IVec3::splat(i32::MAX),
+                material: 1,
+                flags: Flags::NONE,
+            },
+            VoxelEdit::FillSphere {
+                centre: IVec3::splat(i32::MIN),
+                radius: 2e9,
+                material: 1,
+                flags: Flags::NONE,
+            },
+            VoxelEdit::FillSphere {
+                centre: IVec3::splat(i32::MAX),
+                radius: 3e9,
+                material: 1,
+                flags: Flags::NONE,
+            },
+        ] {
+            edit.apply(&mut gh);
+        }
+        assert_eq!(gh.voxels().count(), 0);
+
+        let edit = VoxelEdit::FillSphere {
+            centre: IVec3::splat(16),
+            radius: f32::MAX,
+            material: 1,
+            flags: Flags::NONE,
+        };
+        assert_eq!(
+            edit.bounds(),
+            (IVec3::splat(i32::MIN), IVec3::splat(i32::MAX))
+        );
+        edit.apply(&mut gh);
+        assert_eq!(gh.voxels().count(), 32 * 32 * 32);
+
+        // far enough away for the distance to the world to overflow an i32
+        let mut gh = GH::empty(32);
+        VoxelEdit::FillSphere {
+            centre: IVec3::new(i32::MIN, 0, 0),
+            radius: 2f32.powi(31) + 1024.0,
+            material: 1,
+            flags: Flags::NONE,
+        }
+        .apply(&mut gh);
+        assert_eq!(gh.voxels().count(), 32 * 32 * 32);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{
+    animation::AnimationPlugin,
+    load::{
+        spawn_load_task, ActiveVoxelWorld, VoxelAnimation, VoxelWorldAsset, VoxelWorldAssetLoader,
+        GH,
+    },
+    streaming::{
+        copy_region, finish_shift, shift_world, spawn_saves, start_stream_job, take_saves,
+        StreamJob,
+    },
+    FloatingOrigin, LoadVoxelWorld, MaterialRegistry, ResizeVoxelWorld, SaveVoxelWorld, VoxelEdits,
+    VoxelPalette, VoxelQueries, VoxelQueryEvent, VoxelStreaming, VoxelStreamingFocus,
+    VoxelWorldLoadError, VoxelWorldLoadProgress, VoxelWorldLoadSettings, VoxelWorldMirror,
+    VoxelWorldShifted,
+};
+use bevy::{prelude::*, tasks::Task};
+use futures_lite::future;
+
+/// Runs the voxel world on the cpu without a `RenderDevice`, for servers and tests. Worlds are
+/// loaded into the `VoxelWorldMirror`, and `VoxelEdits` and `VoxelQueries` are answered from it
+/// every frame. Nothing is rendered and there is no gpu physics. `VoxelAnimationPlayer`s advance
+/// and send their events, but their frames are not drawn into the mirror.
+pub struct BevyVoxelEngineHeadlessPlugin;
+
+impl Plugin for BevyVoxelEngineHeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LoadVoxelWorld::None)
+            .insert_resource(HeadlessLoad::None)
+            .init_resource::<VoxelWorldLoadProgress>()
+            .insert_resource(SaveVoxelWorld::None)
+            .init_resource::<VoxelWorldLoadSettings>()
+            .init_resource::<VoxelWorldMirror>()
+            .init_resource::<VoxelPalette>()
+            .init_resource::<MaterialRegistry>()
+            .init_resource::<VoxelEdits>()
+            .init_resource::<VoxelQueries>()
+            .init_resource::<FloatingOrigin>()
+            .init_resource::<StreamJob>()
+            .add_event::<VoxelWorldLoadError>()
+            .add_event::<VoxelQueryEvent>()
+            .add_event::<ResizeVoxelWorld>()
+            .add_event::<VoxelWorldShifted>()
+            .add_systems(
+                (
+                    load_voxel_world,
+                    finish_voxel_world_load,
+                    update_voxel_palette,
+                    resize_voxel_world,
+                    stream_voxel_world,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                (apply_voxel_edits, answer_voxel_queries, save_voxel_world)
+                    .chain()
+                    .in_base_set(CoreSet::PostUpdate),
+            );
+
+        // voxel world assets and animations need the asset plugin, which a minimal app does not
+        // have
+        if app.world.contains_resource::<AssetServer>() {
+            app.add_asset::<VoxelWorldAsset>()
+                .add_asset::<VoxelAnimation>()
+                .init_asset_loader::<VoxelWorldAssetLoader>()
+                .add_plugin(AnimationPlugin)
+                .add_system(activate_voxel_world_asset.before(finish_voxel_world_load));
+        }
+    }
+}
+
+#[derive(Resource)]
+enum HeadlessLoad {
+    Parsing(Task<Result<GH, VoxelWorldLoadError>>),
+    None,
+}
+
+fn load_voxel_world(
+    mut load_voxel_world: ResMut<LoadVoxelWorld>,
+    mut headless_load: ResMut<HeadlessLoad>,
+    mut load_progress: ResMut<VoxelWorldLoadProgress>,
+    load_settings: Res<VoxelWorldLoadSettings>,
+) {
+    let request = std::mem::replace(load_voxel_world.as_mut(), LoadVoxelWorld::None);
+    let Some(task) = spawn_load_task(request, load_settings.clone()) else {
+        return;
+    };
+
+    // a new request replaces any load that is still in progress
+    *headless_load = HeadlessLoad::Parsing(task);
+    *load_progress = VoxelWorldLoadProgress::Parsing;
+}
+
+fn finish_voxel_world_load(
+    mut headless_load: ResMut<HeadlessLoad>,
+    mut load_progress: ResMut<VoxelWorldLoadProgress>,
+    mut mirror: ResMut<VoxelWorldMirror>,
+    mut palette: ResMut<VoxelPalette>,
+    mut load_errors: EventWriter<VoxelWorldLoadError>,
+) {
+    let HeadlessLoad::Parsing(task) = headless_load.as_mut() else {
+        return;
+    };
+    let Some(result) = future::block_on(future::poll_once(task)) else {
+        return;
+    };
+
+    // keep the previous world if loading failed
+    match result {
+        Ok(gh) => {
+            *palette = VoxelPalette::from_gh(&gh);
+            mirror.gh = gh;
+        }
+        Err(error) => {
+            error!("{}", error);
+            load_errors.send(error);
+        }
+    }
+    *headless_load = HeadlessLoad::None;
+    *load_progress = VoxelWorldLoadProgress::Done;
+}
+
+fn activate_voxel_world_asset(
+    active_voxel_world: Option<Res<ActiveVoxelWorld>>,
+    voxel_world_assets: Res<Assets<VoxelWorldAsset>>,
+    mut asset_events: EventReader<AssetEvent<VoxelWorldAsset>>,
+    mut mirror: ResMut<VoxelWorldMirror>,
+    mut palette: ResMut<VoxelPalette>,
+    mut pending: Local<bool>,
+) {
+    let Some(active_voxel_world) = active_voxel_world else {
+        asset_events.clear();
+        return;
+    };
+
+    if active_voxel_world.is_changed() {
+        *pending = true;
+    }
+    for event in asset_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == active_voxel_world.0 =>
+            {
+                *pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    if *pending {
+        if let Some(asset) = voxel_world_assets.get(&active_voxel_world.0) {
+            *palette = VoxelPalette::from_gh(&asset.gh);
+            mirror.gh = asset.gh.as_ref().clone();
+            *pending = false;
+        }
+    }
+}
+
+fn update_voxel_palette(palette: Res<VoxelPalette>, mut mirror: ResMut<VoxelWorldMirror>) {
+    if palette.is_changed() {
+        palette.write_to(&mut mirror.gh);
+    }
+}
+
+fn resize_voxel_world(
+    mut resize_events: EventReader<ResizeVoxelWorld>,
+    mut mirror: ResMut<VoxelWorldMirror>,
+    streaming: Option<Res<VoxelStreaming>>,
+    mut load_errors: EventWriter<VoxelWorldLoadError>,
+    mut floating_origin: ResMut<FloatingOrigin>,
+    mut transforms: Query<&mut Transform, Without<Parent>>,
+) {
+    let Some(resize) = resize_events.iter().last() else {
+        return;
+    };
+    if let Err(error) = GH::check_size(resize.new_size) {
+        error!("{}", error);
+        load_errors.send(error);
+        return;
+    }
+    let old_size = mirror.texture_size();
+    if resize.new_size == old_size {
+        return;
+    }
+
+    let (src, dst, _) = resize.anchor.resize_region(old_size, resize.new_size);
+    let shift = src.as_ivec3() - dst.as_ivec3();
+    if let Some(Err(error)) = streaming.map(|streaming| streaming.store.check_shift(shift)) {
+        error!("{}", error);
+        load_errors.send(error);
+        return;
+    }
+
+    mirror.gh = mirror.gh.resized(resize.new_size, resize.anchor);
+    floating_origin.offset += shift;
+    let translation = resize.anchor.resize_translation(old_size, resize.new_size);
+    for mut transform in transforms.iter_mut() {
+        transform.translation += translation;
+    }
+}
+
+/// Loads and saves the regions of the mirror and moves it to follow the `VoxelStreamingFocus`,
+/// see `VoxelStreaming`.
+#[allow(clippy::too_many_arguments)]
+fn stream_voxel_world(
+    mut streaming: Option<ResMut<VoxelStreaming>>,
+    mut stream_job: ResMut<StreamJob>,
+    mut floating_origin: ResMut<FloatingOrigin>,
+    mut mirror: ResMut<VoxelWorldMirror>,
+    load_progress: Res<VoxelWorldLoadProgress>,
+    focus: Query<&GlobalTransform, With<VoxelStreamingFocus>>,
+    mut transforms: Query<&mut Transform, Without<Parent>>,
+    mut shift_events: EventWriter<VoxelWorldShifted>,
+) {
+    // saves are finished even if the world is replaced in the meantime
+    if let StreamJob::Saving(task) = stream_job.as_mut() {
+        let Some(failed) = future::block_on(future::poll_once(task)) else {
+            return;
+        };
+        if let Some(streaming) = streaming.as_mut() {
+            streaming.mark_unsaved(failed);
+        }
+        *stream_job = StreamJob::None;
+        return;
+    }
+
+    // a world that is being loaded replaces the one being streamed
+    let Some(mut streaming) = streaming else {
+        *stream_job = StreamJob::None;
+        return;
+    };
+    if *load_progress != VoxelWorldLoadProgress::Done {
+        *stream_job = StreamJob::None;
+        return;
+    }
+
+    let texture_size = mirror.texture_size();
+    match stream_job.as_mut() {
+        StreamJob::None => {
+            if let Err(error) = streaming.store.check_region_size(texture_size) {
+                if streaming.is_changed() {
+                    error!("{}", error);
+                }
+                return;
+            }
+            let focus = focus.get_single().ok().map(|focus| focus.translation());
+            if let Some(job) =
+                start_stream_job(&mut streaming, &floating_origin, focus, texture_size)
+            {
+                *stream_job = job;
+            }
+        }
+        StreamJob::Loading {
+            shift,
+            texture_size: job_size,
+            task,
+        } => {
+            // the world was resized in the meantime
+            if *job_size != texture_size {
+                *stream_job = StreamJob::None;
+                return;
+            }
+            let Some(regions) = future::block_on(future::poll_once(task)) else {
+                return;
+            };
+            let shift = *shift;
+            let saves = take_saves(
+                &mut streaming,
+                &floating_origin,
+                shift,
+                &regions,
+                texture_size,
+            );
+            let region_size = streaming.store.region_size;
+            let saves = saves
+                .into_iter()
+                .map(|(corner, region)| (region, copy_region(&mirror.gh, corner, region_size)))
+                .collect();
+
+            if shift != IVec3::ZERO || !regions.is_empty() {
+                shift_world(&mut mirror.gh, shift, &regions);
+                finish_shift(
+                    shift,
+                    &mut floating_origin,
+                    transforms.iter_mut(),
+                    &mut shift_events,
+                );
+            }
+            *stream_job = spawn_saves(&streaming.store, saves);
+        }
+        // only the gpu has to read regions back
+        StreamJob::Reading { .. } | StreamJob::Saving(_) => {}
+    }
+}
+
+fn apply_voxel_edits(
+    mut voxel_edits: ResMut<VoxelEdits>,
+    mut mirror: ResMut<VoxelWorldMirror>,
+    mut streaming: Option<ResMut<VoxelStreaming>>,
+    floating_origin: Res<FloatingOrigin>,
+) {
+    let world_max = IVec3::splat(mirror.texture_size() as i32 - 1);
+    for edit in voxel_edits.drain() {
+        edit.apply(&mut mirror.gh);
+
+        let (min, max) = edit.bounds();
+        let (min, max) = (min.max(IVec3::ZERO), max.min(world_max));
+        if let Some(streaming) = streaming.as_mut().filter(|_| min.cmple(max).all()) {
+            streaming.mark_dirty(
+                floating_origin.to_global(min),
+                floating_origin.to_global(max),
+            );
+        }
+    }
+}
+
+fn answer_voxel_queries(
+    mut voxel_queries: ResMut<VoxelQueries>,
+    mirror: Res<VoxelWorldMirror>,
+    mut query_events: EventWriter<VoxelQueryEvent>,
+) {
+    for (id, query) in voxel_queries.drain() {
+        let result = query.run(&mirror.gh);
+        query_events.send(VoxelQueryEvent { id, query, result });
+    }
+}
+
+fn save_voxel_world(mut save_voxel_world: ResMut<SaveVoxelWorld>, mirror: Res<VoxelWorldMirror>) {
+    if let SaveVoxelWorld::File(path) = save_voxel_world.as_ref() {
+        match std::fs::write(path, mirror.gh.to_file(path)) {
+            Ok(()) => info!("Saved voxel world to {}", path),
+            Err(error) => error!("Failed to save voxel world to {}: {}", path, error),
+        }
+        *save_voxel_world = SaveVoxelWorld::None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Flags, VoxelQuery, VoxelQueryResult};
+    use bevy::ecs::event::ManualEventReader;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(BevyVoxelEngineHeadlessPlugin)
+            .insert_resource(LoadVoxelWorld::Empty(64));
+
+        // the world is built on the task pool
+        for _ in 0..1000 {
+            app.update();
+            if *app.world.resource::<VoxelWorldLoadProgress>() == VoxelWorldLoadProgress::Done {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(app.world.resource::<VoxelWorldMirror>().texture_size(), 64);
+        app
+    }
+
+    #[test]
+    fn queries_see_edits_from_the_same_frame() {
+        let mut app = app();
+        let mut reader = ManualEventReader::<VoxelQueryEvent>::default();
+
+        let mut edits = app.world.resource_mut::<VoxelEdits>();
+        edits
+            .fill_box(
+                IVec3::new(1, 2, 3),
+                IVec3::new(2, 2, 3),
+                7,
+                Flags::COLLISION_FLAG,
+            )
+            .set_flags(IVec3::new(2, 2, 3), IVec3::new(2, 2, 3), Flags::SAND_FLAG);
+        let mut queries = app.world.resource_mut::<VoxelQueries>();
+        let point = queries.point(IVec3::new(2, 2, 3));
+        let outside = queries.point(IVec3::new(64, 0, 0));
+        let ray = queries.ray(Vec3::new(0.5, 2.5, 3.5), Vec3::X, 10.0);
+        app.update();
+
+        let mirror = app.world.resource::<VoxelWorldMirror>();
+        assert_eq!(
+            mirror.get_voxel(IVec3::new(1, 2, 3)),
+            Some((7, Flags::COLLISION_FLAG))
+        );
+        assert_eq!(
+            mirror.get_voxel(IVec3::new(2, 2, 3)),
+            Some((7, Flags::COLLISION_FLAG | Flags::SAND_FLAG))
+        );
+        assert_eq!(mirror.get_voxel(IVec3::new(3, 2, 3)), Some((0, 0)));
+        assert_eq!(mirror.gh.voxels().count(), 2);
+
+        let events = app.world.resource::<Events<VoxelQueryEvent>>();
+        let events: Vec<_> = reader.iter(events).cloned().collect();
+        assert_eq!(events.len(), 3);
+        for event in &events {
+            match &event.result {
+                VoxelQueryResult::Point(sample) if event.id == point => {
+                    assert_eq!(event.query, VoxelQuery::Point(IVec3::new(2, 2, 3)));
+                    assert_eq!(sample.material, 7);
+                    assert_eq!(sample.flags, Flags::COLLISION_FLAG | Flags::SAND_FLAG);
+                }
+                VoxelQueryResult::Point(sample) if event.id == outside => {
+                    assert_eq!(sample.material, 0);
+                }
+                VoxelQueryResult::Ray(hit) if event.id == ray => {
+                    let hit = hit.expect("the ray hits the box");
+                    assert_eq!(hit.sample.pos, IVec3::new(1, 2, 3));
+                    assert_eq!(hit.normal, IVec3::NEG_X);
+                }
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+
+        // answered once
+        app.update();
+        let events = app.world.resource::<Events<VoxelQueryEvent>>();
+        assert_eq!(reader.iter(events).count(), 0);
+    }
+
+    #[test]
+    fn edits_outside_of_the_world_are_ignored() {
+        let mut app = app();
+        app.world.resource_mut::<VoxelEdits>().fill_sphere(
+            IVec3::splat(-100),
+            50.0,
+            1,
+            Flags::NONE,
+        );
+        app.update();
+        assert!(app.world.resource::<VoxelEdits>().is_empty());
+        assert_eq!(
+            app.world.resource::<VoxelWorldMirror>().gh.voxels().count(),
+            0
+        );
+    }
+}
@@ -1,18 +1,48 @@
+use animation::AnimationPlugin;
+pub use animation::{VoxelAnimationFinished, VoxelAnimationMode, VoxelAnimationPlayer};
 use bevy::{
     core_pipeline::tonemapping::Tonemapping,
     prelude::*,
     render::{camera::CameraRenderGraph, primitives::Frustum, view::VisibleEntities},
+    utils::HashMap,
 };
+use edit::EditPlugin;
+pub use edit::{VoxelEdit, VoxelEdits};
+pub use headless::BevyVoxelEngineHeadlessPlugin;
+pub use load::{
+    ActiveVoxelWorld, Anchor, BakeFill, Caves, FlatRooms, Heightmap, HeightmapColours,
+    LayeredTerrain, MaterialProperties, PaletteFormat, QuantizationReport, RegionStore,
+    SchematicBlock, SchematicBlocks, TerrainLayer, VoxelAnimation, VoxelWorldAsset,
+    VoxelWorldGenerator, VoxelWorldLoadError, BRICK_SIZE, GH, MAX_TEXTURE_SIZE, MIN_TEXTURE_SIZE,
+    REGION_EXTENSION, SNAPSHOT_EXTENSION,
+};
+pub use material::{MaterialRegistry, RegisteredMaterial};
+pub use mirror::VoxelWorldMirror;
+pub use palette::VoxelPalette;
 use physics::PhysicsPlugin;
 pub use physics::VOXELS_PER_METER;
+use query::QueryPlugin;
+pub use query::{
+    VoxelQueries, VoxelQuery, VoxelQueryEvent, VoxelQueryId, VoxelQueryResult, VoxelRayHit,
+    VoxelSample,
+};
+pub use streaming::{FloatingOrigin, VoxelStreaming, VoxelStreamingFocus, VoxelWorldShifted};
 use voxel_pipeline::RenderPlugin;
 pub use voxel_pipeline::{
     denoise::DenoiseSettings, trace::TraceSettings, voxelization::VoxelizationMaterial,
     voxelization::VoxelizationMaterialType, RenderGraphSettings,
 };
 
+mod animation;
+mod edit;
+mod headless;
 mod load;
+mod material;
+mod mirror;
+mod palette;
 mod physics;
+mod query;
+mod streaming;
 mod voxel_pipeline;
 
 #[derive(Component)]
@@ -39,6 +69,19 @@ pub struct Box {
     pub half_size: IVec3,
 }
 
+/// A voxel model drawn into the world every frame at the full `Transform` of its entity, including
+/// rotation and non-uniform scale. At a scale of one a voxel of the model is a voxel of the world,
+/// and the model turns around the center of the world it was loaded into, which is the center of
+/// the model with the default `Anchor`.
+#[derive(Component)]
+pub struct VoxelModel {
+    pub model: Handle<VoxelWorldAsset>,
+    /// Material for every voxel of the model, or 0 to keep the materials it was loaded with, which
+    /// only look right if the model uses the palette of the world.
+    pub material: u8,
+    pub flags: u8,
+}
+
 #[derive(Component)]
 pub struct VoxelPhysics {
     pub velocity: Vec3,
@@ -158,6 +201,9 @@ impl Plugin for BevyVoxelEnginePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(Msaa::Off)
             .add_plugin(PhysicsPlugin)
+            .add_plugin(AnimationPlugin)
+            .add_plugin(EditPlugin)
+            .add_plugin(QueryPlugin)
             .add_plugin(RenderPlugin);
     }
 }
@@ -166,9 +212,57 @@ impl Plugin for BevyVoxelEnginePlugin {
 pub enum LoadVoxelWorld {
     Empty(u32),
     File(String),
+    Heightmap(Heightmap),
+    Generate {
+        generator: std::boxed::Box<dyn VoxelWorldGenerator>,
+        seed: u64,
+    },
     None,
 }
 
+/// Event that resizes the current voxel world without reloading it. The voxels are copied into the
+/// new world at `anchor`, and cut off if it is smaller. Entities without a parent are moved so they
+/// stay on the same voxels. Only the last resize sent in a frame is applied. With `VoxelStreaming`
+/// the world can only move by whole regions, other resizes are rejected.
+#[derive(Clone, Copy, Debug)]
+pub struct ResizeVoxelWorld {
+    /// A power of two between `MIN_TEXTURE_SIZE` and `MAX_TEXTURE_SIZE`.
+    pub new_size: u32,
+    pub anchor: Anchor,
+}
+
+/// How far along the current `LoadVoxelWorld` or `ActiveVoxelWorld` load is. The previous world
+/// stays visible until the load is `Done`.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq)]
+pub enum VoxelWorldLoadProgress {
+    #[default]
+    Done,
+    /// The file is being read and parsed on the `AsyncComputeTaskPool`.
+    Parsing,
+    /// The fraction of the world that has been uploaded to the gpu.
+    Uploading(f32),
+}
+
+/// Saves the current voxel world at the end of the frame. Paths ending in `SNAPSHOT_EXTENSION`
+/// are saved as a lossless snapshot that keeps voxel flags, anything else as a magica voxel file.
+#[derive(Resource)]
+pub enum SaveVoxelWorld {
+    File(String),
+    None,
+}
+
+/// Settings used whenever a world is loaded from a file.
+#[derive(Resource, Default, Clone)]
+pub struct VoxelWorldLoadSettings {
+    /// Where models that are smaller than the world are placed.
+    pub anchor: Anchor,
+    /// Flags given to voxels in magica voxel layers with a matching name, voxels in other layers
+    /// get `Flags::COLLISION_FLAG`.
+    pub layer_flags: HashMap<String, u8>,
+    /// Voxels used for the blocks of minecraft schematics.
+    pub schematic_blocks: SchematicBlocks,
+}
+
 #[allow(non_snake_case, dead_code)]
 pub mod Flags {
     pub const AUTOMATA_FLAG: u8 = 128; // 0b10000000
@@ -0,0 +1,97 @@
+use super::{GH, SNAPSHOT_EXTENSION};
+use crate::VoxelWorldLoadSettings;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use std::sync::Arc;
+
+/// A voxel world loaded through the `AssetServer`, insert an `ActiveVoxelWorld` to display it.
+#[derive(TypeUuid, Clone)]
+#[uuid = "3e4185e1-fb09-4f17-b5fc-b8bb4aef5b97"]
+pub struct VoxelWorldAsset {
+    pub(crate) gh: Arc<GH>,
+}
+
+impl VoxelWorldAsset {
+    pub fn texture_size(&self) -> u32 {
+        self.gh.texture_size
+    }
+}
+
+/// Every model of a magica voxel file as the frames of an animation, played with a
+/// `VoxelAnimationPlayer`. It is loaded with the `animation` label of the file, for example
+/// `asset_server.load("fan.vox#animation")`.
+#[derive(TypeUuid, Clone)]
+#[uuid = "2ed6f2e7-bfcc-452f-a772-4544498de115"]
+pub struct VoxelAnimation {
+    pub(crate) frames: Vec<Arc<GH>>,
+}
+
+impl VoxelAnimation {
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// The voxel world asset that is currently loaded into the voxel texture. The world is uploaded
+/// once the asset has loaded and again every time it is modified, so it hot reloads when the
+/// `AssetServer` watches for changes.
+#[derive(Resource, Clone)]
+pub struct ActiveVoxelWorld(pub Handle<VoxelWorldAsset>);
+
+/// Loads every world format `GH::from_file` understands with the `VoxelWorldLoadSettings` that were
+/// present when the plugin was added. Magica voxel files also get a `VoxelAnimation`.
+pub struct VoxelWorldAssetLoader {
+    settings: VoxelWorldLoadSettings,
+}
+
+impl FromWorld for VoxelWorldAssetLoader {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            settings: world
+                .get_resource::<VoxelWorldLoadSettings>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl AssetLoader for VoxelWorldAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let gh = if bytes.starts_with(b"VOX ") {
+                let (gh, frames) = GH::from_vox_with_frames(bytes, &self.settings)?;
+                load_context.set_labeled_asset(
+                    "animation",
+                    LoadedAsset::new(VoxelAnimation {
+                        frames: frames.into_iter().map(Arc::new).collect(),
+                    }),
+                );
+                gh
+            } else {
+                GH::from_file(bytes, &self.settings)?
+            };
+            load_context.set_default_asset(LoadedAsset::new(VoxelWorldAsset { gh: Arc::new(gh) }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[
+            "vox",
+            SNAPSHOT_EXTENSION,
+            "schem",
+            "nbt",
+            "qb",
+            "gox",
+            "binvox",
+        ]
+    }
+}
@@ -0,0 +1,369 @@
+use super::{heightmap::pixel, quantize::median_cut, VoxelWorldLoadError, GH};
+use crate::{
+    Flags, MaterialRegistry, VoxelizationMaterial, VoxelizationMaterialType, VOXELS_PER_METER,
+};
+use bevy::{
+    prelude::*,
+    render::{mesh::VertexAttributeValues, render_resource::PrimitiveTopology},
+};
+use std::collections::VecDeque;
+
+/// How much of a mesh `GH::bake_mesh` turns into voxels.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BakeFill {
+    /// Only the voxels that the triangles pass through.
+    #[default]
+    Surface,
+    /// The surface and everything it encloses, the mesh has to be closed and fit in the world.
+    Solid,
+}
+
+// states of the cells around the mesh while filling
+const EMPTY: u8 = 0;
+const SURFACE: u8 = 1;
+const OUTSIDE: u8 = 2;
+
+impl GH {
+    /// Voxelizes a mesh into the world on the cpu, placed like a `VoxelizationBundle` with the
+    /// same `transform`. Unlike the gpu voxelization the voxels stay, so the animation flag of
+    /// `material` is dropped and any voxels the mesh covers are replaced.
+    ///
+    /// `VoxelizationMaterialType::Texture` samples the colours of `texture` at the mesh uvs and
+    /// adds them to palette entries that no voxel uses yet and that the default `MaterialRegistry`
+    /// does not reserve for the automata.
+    pub fn bake_mesh(
+        &mut self,
+        mesh: &Mesh,
+        transform: &Transform,
+        material: &VoxelizationMaterial,
+        texture: Option<&Image>,
+        fill: BakeFill,
+    ) -> Result<(), VoxelWorldLoadError> {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return Err("Only triangle list meshes can be baked".into());
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return Err("Mesh has no positions".into());
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+            _ => None,
+        };
+        let texture = match &material.material {
+            VoxelizationMaterialType::Texture(_) => {
+                let texture = texture.ok_or("Texture material baked without its image")?;
+                let uvs = uvs.ok_or("Texture material baked onto a mesh without uvs")?;
+                Some((texture, uvs))
+            }
+            VoxelizationMaterialType::Material(_) => None,
+        };
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if indices.iter().any(|index| *index >= positions.len()) {
+            return Err("Mesh index out of range".into());
+        }
+
+        let matrix = transform.compute_matrix();
+        let half_size = Vec3::splat(self.texture_size as f32 / 2.0);
+        let voxel_positions: Vec<Vec3> = positions
+            .iter()
+            .map(|p| matrix.transform_point3(Vec3::from(*p)) * VOXELS_PER_METER + half_size)
+            .collect();
+
+        // cells around the part of the mesh inside the world with a border of one for the fill
+        let size = self.texture_size as i32;
+        let mut min = IVec3::splat(i32::MAX);
+        let mut max = IVec3::splat(i32::MIN);
+        for pos in &voxel_positions {
+            min = min.min(pos.floor().as_ivec3());
+            max = max.max(pos.floor().as_ivec3());
+        }
+        if voxel_positions.is_empty() {
+            return Ok(());
+        }
+        let min = min.clamp(IVec3::ZERO, IVec3::splat(size - 1)) - IVec3::ONE;
+        let max = max.clamp(IVec3::ZERO, IVec3::splat(size - 1)) + IVec3::ONE;
+        let grid = Grid::new(min, max);
+        let mut cells = vec![EMPTY; grid.len()];
+        let mut colours = Vec::new();
+        if texture.is_some() {
+            colours = vec![[0; 3]; grid.len()];
+        }
+
+        for triangle in indices.chunks_exact(3) {
+            let corners = [
+                voxel_positions[triangle[0]],
+                voxel_positions[triangle[1]],
+                voxel_positions[triangle[2]],
+            ];
+            let low = corners[0]
+                .min(corners[1])
+                .min(corners[2])
+                .floor()
+                .as_ivec3();
+            let high = corners[0]
+                .max(corners[1])
+                .max(corners[2])
+                .floor()
+                .as_ivec3();
+            let low = low.max(IVec3::ZERO);
+            let high = high.min(IVec3::splat(size - 1));
+            for x in low.x..=high.x {
+                for y in low.y..=high.y {
+                    for z in low.z..=high.z {
+                        let voxel = IVec3::new(x, y, z);
+                        if !overlaps(corners, voxel.as_vec3()) {
+                            continue;
+                        }
+                        let cell = grid.index(voxel);
+                        cells[cell] = SURFACE;
+                        if let Some((image, uvs)) = texture {
+                            let weights = barycentric(corners, voxel.as_vec3() + 0.5);
+                            let uv = Vec2::from(uvs[triangle[0]]) * weights.x
+                                + Vec2::from(uvs[triangle[1]]) * weights.y
+                                + Vec2::from(uvs[triangle[2]]) * weights.z;
+                            colours[cell] = sample(image, uv);
+                        }
+                    }
+                }
+            }
+        }
+
+        if fill == BakeFill::Solid {
+            // everything the outside can not reach through the surface is inside
+            let mut queue = VecDeque::from([min]);
+            cells[grid.index(min)] = OUTSIDE;
+            while let Some(pos) = queue.pop_front() {
+                for offset in [IVec3::X, IVec3::Y, IVec3::Z] {
+                    for next in [pos + offset, pos - offset] {
+                        if next.cmplt(min).any() || next.cmpgt(max).any() {
+                            continue;
+                        }
+                        let cell = grid.index(next);
+                        if cells[cell] == EMPTY {
+                            cells[cell] = OUTSIDE;
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut voxels = Vec::new();
+        for x in min.x + 1..max.x {
+            for y in min.y + 1..max.y {
+                // inside voxels take the colour of the surface before them along z
+                let mut last_colour = [0; 3];
+                for z in min.z + 1..max.z {
+                    let pos = IVec3::new(x, y, z);
+                    let cell = grid.index(pos);
+                    let state = cells[cell];
+                    if state == SURFACE && texture.is_some() {
+                        last_colour = colours[cell];
+                    }
+                    if state == SURFACE || (fill == BakeFill::Solid && state == EMPTY) {
+                        voxels.push((pos.as_uvec3(), last_colour));
+                    }
+                }
+            }
+        }
+
+        let flags = material.flags & !Flags::ANIMATION_FLAG;
+        match material.material {
+            VoxelizationMaterialType::Material(material) => {
+                for (pos, _) in voxels {
+                    self.set_voxel(pos, material, flags);
+                }
+            }
+            VoxelizationMaterialType::Texture(_) => {
+                let mut used = [false; 256];
+                for (_, material, _) in self.voxels() {
+                    used[material as usize] = true;
+                }
+                for (_, material) in MaterialRegistry::default().iter() {
+                    for index in material.indices() {
+                        used[index as usize] = true;
+                    }
+                }
+                let free: Vec<u8> = (1..=255).filter(|m| !used[*m as usize]).collect();
+                if free.is_empty() && !voxels.is_empty() {
+                    return Err("No free palette entries left for the texture colours".into());
+                }
+
+                let colours: Vec<[u8; 3]> = voxels.iter().map(|(_, colour)| *colour).collect();
+                let (palette, indices, report) = median_cut(&colours, free.len());
+                info!("{}", report);
+                for (colour, material) in palette.iter().zip(&free) {
+                    let colour = Vec3::new(colour[0] as f32, colour[1] as f32, colour[2] as f32);
+                    self.pallete[*material as usize] =
+                        (colour / 255.0).powf(2.2).extend(0.0).to_array();
+                }
+                for ((pos, _), index) in voxels.into_iter().zip(indices) {
+                    self.set_voxel(pos, free[index as usize], flags);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bakes a wavefront `.obj` file like `GH::bake_mesh`.
+    pub fn bake_obj(
+        &mut self,
+        obj: &[u8],
+        transform: &Transform,
+        material: &VoxelizationMaterial,
+        texture: Option<&Image>,
+        fill: BakeFill,
+    ) -> Result<(), VoxelWorldLoadError> {
+        let mesh = bevy_obj::load_obj_from_bytes(obj)
+            .map_err(|error| format!("Failed to parse obj file: {}", error))?;
+        self.bake_mesh(&mesh, transform, material, texture, fill)
+    }
+}
+
+/// Cells of the box from `min` to `max` inclusive.
+struct Grid {
+    min: IVec3,
+    size: IVec3,
+}
+
+impl Grid {
+    fn new(min: IVec3, max: IVec3) -> Self {
+        Self {
+            min,
+            size: max - min + IVec3::ONE,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.size.x as usize * self.size.y as usize * self.size.z as usize
+    }
+
+    fn index(&self, pos: IVec3) -> usize {
+        let pos = (pos - self.min).as_uvec3();
+        let size = self.size.as_uvec3();
+        (pos.x as usize * size.y as usize + pos.y as usize) * size.z as usize + pos.z as usize
+    }
+}
+
+/// Separating axis test between a triangle and the voxel with its corner at `voxel`.
+fn overlaps(corners: [Vec3; 3], voxel: Vec3) -> bool {
+    let centre = voxel + 0.5;
+    let v = corners.map(|corner| corner - centre);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+    let separated = |axis: Vec3| {
+        let projected = v.map(|v| v.dot(axis));
+        let radius = 0.5 * axis.abs().dot(Vec3::ONE);
+        let low = projected[0].min(projected[1]).min(projected[2]);
+        let high = projected[0].max(projected[1]).max(projected[2]);
+        low > radius || high < -radius
+    };
+
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        if separated(axis) {
+            return false;
+        }
+        for edge in edges {
+            if separated(axis.cross(edge)) {
+                return false;
+            }
+        }
+    }
+    !separated(edges[0].cross(edges[1]))
+}
+
+/// Weights of the corners for the point on the triangle closest to `point`.
+fn barycentric(corners: [Vec3; 3], point: Vec3) -> Vec3 {
+    let e0 = corners[1] - corners[0];
+    let e1 = corners[2] - corners[0];
+    let p = point - corners[0];
+    let (d00, d01, d11) = (e0.dot(e0), e0.dot(e1), e1.dot(e1));
+    let denominator = d00 * d11 - d01 * d01;
+    if denominator.abs() < f32::EPSILON {
+        return Vec3::new(1.0, 0.0, 0.0);
+    }
+    let v = (d11 * p.dot(e0) - d01 * p.dot(e1)) / denominator;
+    let w = (d00 * p.dot(e1) - d01 * p.dot(e0)) / denominator;
+    let weights = Vec3::new(1.0 - v - w, v, w).max(Vec3::ZERO);
+    weights / weights.dot(Vec3::ONE)
+}
+
+/// Srgb colour of the nearest texel at `uv`, repeating outside of 0 to 1 like the gpu sampler.
+fn sample(image: &Image, uv: Vec2) -> [u8; 3] {
+    let width = image.texture_descriptor.size.width as usize;
+    let height = image.texture_descriptor.size.height as usize;
+    let uv = uv - uv.floor();
+    let x = ((uv.x * width as f32) as usize).min(width - 1);
+    let y = ((uv.y * height as f32) as usize).min(height - 1);
+    let colour = (pixel(image, x, y).truncate() * 255.0).round();
+    [colour.x as u8, colour.y as u8, colour.z as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::{
+        mesh::Indices,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    };
+
+    #[test]
+    fn texture_colours_skip_registered_materials() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let corners = [[-3.5, -3.5], [3.5, -3.5], [3.5, 3.5], [-3.5, 3.5]];
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            corners.map(|[x, y]| [x, y, 0.0f32]).to_vec(),
+        );
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            vec![[0.0f32, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        );
+        mesh.set_indices(Some(Indices::U32(vec![0, 1, 2, 0, 2, 3])));
+
+        // more distinct colours than there are palette entries
+        let data = (0..16 * 16)
+            .flat_map(|i| [(i % 16 * 16) as u8, (i / 16 * 16) as u8, (i * 7) as u8, 255])
+            .collect();
+        let image = Image::new(
+            Extent3d {
+                width: 16,
+                height: 16,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+        );
+        let material = VoxelizationMaterial {
+            material: VoxelizationMaterialType::Texture(Handle::default()),
+            flags: Flags::COLLISION_FLAG,
+        };
+
+        let mut gh = GH::empty(32);
+        gh.bake_mesh(
+            &mesh,
+            &Transform::IDENTITY,
+            &material,
+            Some(&image),
+            BakeFill::Surface,
+        )
+        .unwrap();
+
+        let registry = MaterialRegistry::default();
+        assert!(gh.voxels().count() > 0);
+        for (_, material, _) in gh.voxels() {
+            assert!(
+                registry.find(material).is_none(),
+                "{} is reserved",
+                material
+            );
+        }
+    }
+}
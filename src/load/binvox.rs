@@ -0,0 +1,124 @@
+use super::{reader::Reader, QuantizationReport, VoxelWorldLoadError, GH, MAX_TEXTURE_SIZE};
+use crate::VoxelWorldLoadSettings;
+use bevy::prelude::*;
+
+pub const BINVOX_MAGIC: &[u8; 7] = b"#binvox";
+
+// binvox only stores occupancy so every voxel gets the same colour
+const BINVOX_COLOUR: [u8; 3] = [180, 180, 180];
+
+impl GH {
+    /// Loads a `.binvox` occupancy grid from a mesh voxelizer.
+    pub fn from_binvox(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<(GH, QuantizationReport), VoxelWorldLoadError> {
+        let mut reader = Reader::new(file, "binvox file");
+        if !reader.line()?.starts_with("#binvox") {
+            return Err("Not a binvox file".into());
+        }
+
+        let mut size = None;
+        loop {
+            let line = reader.line()?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("dim") => {
+                    let mut dim = [0; 3];
+                    for axis in dim.iter_mut() {
+                        *axis = words
+                            .next()
+                            .and_then(|word| word.parse::<u32>().ok())
+                            .ok_or_else(|| format!("Invalid binvox dimensions: {}", line))?;
+                    }
+                    // depth, height, width with y running along the width
+                    size = Some(UVec3::new(dim[0], dim[2], dim[1]));
+                }
+                Some("data") => break,
+                // translate and scale place the grid in mesh space, which a world does not have
+                _ => {}
+            }
+        }
+        let size = size.ok_or("Binvox file has no dimensions")?;
+        if size.cmpgt(UVec3::splat(MAX_TEXTURE_SIZE)).any() {
+            return Err(VoxelWorldLoadError::ModelTooLarge(size));
+        }
+
+        // runs of value, count with y changing fastest, then z, then x
+        let total = size.x as usize * size.y as usize * size.z as usize;
+        let (height, width) = (size.z as usize, size.y as usize);
+        let mut voxels = Vec::new();
+        let mut index = 0;
+        while index < total {
+            let value = reader.u8()?;
+            let count = reader.u8()? as usize;
+            if index + count > total {
+                return Err("Binvox data is longer than its dimensions".into());
+            }
+            if value != 0 {
+                for i in index..index + count {
+                    let x = i / (width * height);
+                    let z = i / width % height;
+                    let y = i % width;
+                    voxels.push((IVec3::new(x as i32, y as i32, z as i32), BINVOX_COLOUR));
+                }
+            }
+            index += count;
+        }
+
+        GH::from_coloured_voxels(&voxels, settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::Anchor;
+
+    fn file(dim: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!("#binvox 1\ndim {}\ntranslate 0 0 0\nscale 1\ndata\n", dim);
+        [header.as_bytes(), data].concat()
+    }
+
+    fn settings() -> VoxelWorldLoadSettings {
+        VoxelWorldLoadSettings {
+            anchor: Anchor::Corner,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn loads_runs() {
+        // y changes fastest, then z, then x. the empty bottom layer is cropped away
+        let file = file("2 2 2", &[0, 1, 1, 1, 0, 5, 1, 1]);
+        let (gh, _) = GH::from_binvox(&file, &settings()).unwrap();
+        let mut voxels: Vec<_> = gh.voxels().map(|(pos, _, _)| pos).collect();
+        voxels.sort_by_key(|pos| pos.to_array());
+        assert_eq!(voxels, [UVec3::new(0, 0, 0), UVec3::new(1, 0, 1)]);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let file = file("2 2 2", &[0, 1, 1, 1, 0, 5, 1, 1]);
+        for length in 0..file.len() {
+            assert!(
+                GH::from_binvox(&file[..length], &settings()).is_err(),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        assert!(GH::from_binvox(b"#binvox 1\ndata\n\x01\x08", &settings()).is_err());
+        assert!(GH::from_binvox(&file("2 2", &[1, 8]), &settings()).is_err());
+        assert!(GH::from_binvox(&file("2 2 -2", &[1, 8]), &settings()).is_err());
+        assert!(matches!(
+            GH::from_binvox(&file("5000 1 1", &[1, 255]), &settings()),
+            Err(VoxelWorldLoadError::ModelTooLarge(_))
+        ));
+        // more voxels than the dimensions hold
+        assert!(GH::from_binvox(&file("2 2 2", &[1, 9]), &settings()).is_err());
+    }
+}
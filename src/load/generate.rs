@@ -0,0 +1,500 @@
+use super::{VoxelWorldLoadError, GH};
+use crate::Flags;
+use bevy::prelude::*;
+
+/// Fills a world on the cpu, run one with `LoadVoxelWorld::Generate`. The same generator has to
+/// produce the same world every time it is run with the same seed.
+pub trait VoxelWorldGenerator: Send + Sync {
+    /// Size of the world, a power of two between `MIN_TEXTURE_SIZE` and `MAX_TEXTURE_SIZE`.
+    fn size(&self) -> u32;
+
+    /// Fills `gh`, an empty region of an endless world with its corner at `origin`, including its
+    /// palette. Only the region is generated, so `VoxelStreaming` can fill regions of worlds far
+    /// larger than `size()`.
+    fn generate_region(&self, gh: &mut GH, origin: IVec3, seed: u64);
+
+    /// Fills `gh`, an empty world of `size()`, which is the region at the origin.
+    fn generate(&self, gh: &mut GH, seed: u64) {
+        self.generate_region(gh, IVec3::ZERO, seed);
+    }
+}
+
+impl GH {
+    pub fn from_generator(
+        generator: &dyn VoxelWorldGenerator,
+        seed: u64,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        let size = generator.size();
+        GH::check_size(size)?;
+
+        let mut gh = GH::empty(size);
+        generator.generate(&mut gh, seed);
+        Ok(gh)
+    }
+}
+
+/// Solid rock carved out by 3d noise.
+#[derive(Clone, Debug)]
+pub struct Caves {
+    pub size: u32,
+    /// Rough size of the caves in voxels.
+    pub scale: f32,
+    /// Fraction of the world that is left open, between 0 and 1.
+    pub openness: f32,
+}
+
+impl Default for Caves {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            scale: 48.0,
+            openness: 0.5,
+        }
+    }
+}
+
+impl VoxelWorldGenerator for Caves {
+    fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn generate_region(&self, gh: &mut GH, origin: IVec3, seed: u64) {
+        gh.pallete[1] = srgb(0.45, 0.43, 0.41);
+        gh.pallete[2] = srgb(0.33, 0.31, 0.30);
+        gh.pallete[3] = srgb(0.58, 0.45, 0.32);
+
+        let noise = Noise::new(seed);
+        let veins = Noise::new(seed.wrapping_add(1));
+        let size = gh.texture_size;
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let pos = UVec3::new(x, y, z);
+                    let sample = (origin + pos.as_ivec3()).as_vec3() / self.scale;
+                    if noise.fbm(sample, 3) < self.openness {
+                        continue;
+                    }
+
+                    let material = match veins.fbm(sample * 2.0, 2) {
+                        value if value > 0.7 => 3,
+                        value if value > 0.45 => 2,
+                        _ => 1,
+                    };
+                    gh.set_voxel(pos, material, Flags::COLLISION_FLAG);
+                }
+            }
+        }
+    }
+}
+
+/// One band of `LayeredTerrain`, from the surface down.
+#[derive(Clone, Debug)]
+pub struct TerrainLayer {
+    /// Thickness in voxels, the last layer fills the rest of the column.
+    pub thickness: u32,
+    pub colour: Color,
+}
+
+/// Rolling hills made of layers of material.
+#[derive(Clone, Debug)]
+pub struct LayeredTerrain {
+    pub size: u32,
+    /// Rough width of the hills in voxels.
+    pub scale: f32,
+    /// Average surface height as a fraction of the world size.
+    pub height: f32,
+    /// How far the surface moves above and below `height` as a fraction of the world size.
+    pub amplitude: f32,
+    /// At most 255 layers, the first one is at the surface.
+    pub layers: Vec<TerrainLayer>,
+}
+
+impl Default for LayeredTerrain {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            scale: 96.0,
+            height: 0.3,
+            amplitude: 0.15,
+            layers: vec![
+                TerrainLayer {
+                    thickness: 1,
+                    colour: Color::rgb(0.36, 0.6, 0.24),
+                },
+                TerrainLayer {
+                    thickness: 4,
+                    colour: Color::rgb(0.47, 0.33, 0.22),
+                },
+                TerrainLayer {
+                    thickness: 0,
+                    colour: Color::rgb(0.45, 0.43, 0.41),
+                },
+            ],
+        }
+    }
+}
+
+impl VoxelWorldGenerator for LayeredTerrain {
+    fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Below the bottom of the world is solid.
+    fn generate_region(&self, gh: &mut GH, origin: IVec3, seed: u64) {
+        let layers = &self.layers[..self.layers.len().min(255)];
+        if layers.is_empty() {
+            return;
+        }
+        for (i, layer) in layers.iter().enumerate() {
+            let [r, g, b, _] = layer.colour.as_rgba_f32();
+            gh.pallete[i + 1] = srgb(r, g, b);
+        }
+
+        let noise = Noise::new(seed);
+        let size = self.size as f32;
+        let region_size = gh.texture_size;
+        for x in 0..region_size {
+            for z in 0..region_size {
+                let column = origin + IVec3::new(x as i32, 0, z as i32);
+                let sample = Vec3::new(column.x as f32, 0.0, column.z as f32) / self.scale;
+                let offset = (noise.fbm(sample, 4) * 2.0 - 1.0) * self.amplitude;
+                let height = ((self.height + offset) * size).clamp(0.0, size) as i32;
+
+                let top = (height - origin.y).clamp(0, region_size as i32) as u32;
+                for y in 0..top {
+                    let depth = (height - 1 - origin.y - y as i32) as u32;
+                    let layer = layer_at(layers, depth);
+                    gh.set_voxel(UVec3::new(x, y, z), layer as u8 + 1, Flags::COLLISION_FLAG);
+                }
+            }
+        }
+    }
+}
+
+/// Index of the layer `depth` voxels below the surface.
+fn layer_at(layers: &[TerrainLayer], depth: u32) -> usize {
+    let mut top = 0;
+    for (i, layer) in layers.iter().enumerate() {
+        top += layer.thickness;
+        if depth < top {
+            return i;
+        }
+    }
+    layers.len() - 1
+}
+
+/// A flat floor split into a grid of rooms with a doorway in every wall, useful for testing.
+#[derive(Clone, Debug)]
+pub struct FlatRooms {
+    pub size: u32,
+    /// Width of each room including one of its walls.
+    pub room_size: u32,
+    pub wall_height: u32,
+    pub door_width: u32,
+    pub door_height: u32,
+}
+
+impl Default for FlatRooms {
+    fn default() -> Self {
+        Self {
+            size: 128,
+            room_size: 32,
+            wall_height: 16,
+            door_width: 4,
+            door_height: 10,
+        }
+    }
+}
+
+impl VoxelWorldGenerator for FlatRooms {
+    fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn generate_region(&self, gh: &mut GH, origin: IVec3, seed: u64) {
+        gh.pallete[1] = srgb(0.6, 0.6, 0.6);
+        gh.pallete[2] = srgb(0.85, 0.85, 0.8);
+
+        let room_size = self.room_size.max(self.door_width + 2) as i32;
+        let wall_height = self.wall_height.min(self.size - 1) as i32;
+        let region_size = gh.texture_size as i32;
+        // the floor and walls only cover heights 0 to wall_height
+        let bottom = (-origin.y).max(0);
+        let top = (wall_height + 1 - origin.y).min(region_size);
+        if bottom >= top {
+            return;
+        }
+
+        for x in 0..region_size {
+            for z in 0..region_size {
+                let (gx, gz) = (origin.x + x, origin.z + z);
+                let mut set_voxels = |from: i32, to: i32, material: u8| {
+                    for y in (from - origin.y).max(bottom)..(to - origin.y).min(top) {
+                        let pos = IVec3::new(x, y, z).as_uvec3();
+                        gh.set_voxel(pos, material, Flags::COLLISION_FLAG);
+                    }
+                };
+                set_voxels(0, 1, 1);
+
+                let wall_x = gx.rem_euclid(room_size) == 0;
+                let wall_z = gz.rem_euclid(room_size) == 0;
+                if !wall_x && !wall_z {
+                    continue;
+                }
+
+                // every wall between two corners gets one doorway at a random position
+                let mut door_top = 0;
+                if wall_x != wall_z {
+                    let (wall, along) = if wall_x { (gx, gz) } else { (gz, gx) };
+                    let segment = IVec3::new(
+                        wall.div_euclid(room_size),
+                        along.div_euclid(room_size),
+                        wall_x as i32,
+                    );
+                    let door_start =
+                        1 + hash(seed, segment) % (room_size as u32 - self.door_width - 1);
+                    let along = along.rem_euclid(room_size) as u32;
+                    if (door_start..door_start + self.door_width).contains(&along) {
+                        door_top = self.door_height as i32;
+                    }
+                }
+
+                set_voxels(1 + door_top, wall_height + 1, 2);
+            }
+        }
+    }
+}
+
+fn srgb(r: f32, g: f32, b: f32) -> [f32; 4] {
+    let colour = Vec3::new(r, g, b).powf(2.2);
+    [colour.x, colour.y, colour.z, 0.0]
+}
+
+/// `xxhash32_base` from common.wgsl mixed with a seed.
+fn hash(seed: u64, p: IVec3) -> u32 {
+    const PRIME32_2: u32 = 2246822519;
+    const PRIME32_3: u32 = 3266489917;
+    const PRIME32_4: u32 = 668265263;
+    const PRIME32_5: u32 = 374761393;
+
+    let p = p.as_uvec3();
+    let mut h32 =
+        p.z.wrapping_add(PRIME32_5)
+            .wrapping_add(p.x.wrapping_mul(PRIME32_3))
+            .wrapping_add(seed as u32 ^ (seed >> 32) as u32);
+    h32 = PRIME32_4.wrapping_mul(h32.rotate_left(17));
+    h32 = h32.wrapping_add(p.y.wrapping_mul(PRIME32_3));
+    h32 = PRIME32_4.wrapping_mul(h32.rotate_left(17));
+    h32 = PRIME32_2.wrapping_mul(h32 ^ (h32 >> 15));
+    h32 = PRIME32_3.wrapping_mul(h32 ^ (h32 >> 13));
+    h32 ^ (h32 >> 16)
+}
+
+struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Smoothly interpolated random values on integer positions, between 0 and 1.
+    fn value(&self, pos: Vec3) -> f32 {
+        let cell = pos.floor();
+        let t = pos - cell;
+        let t = t * t * (3.0 - 2.0 * t);
+        let cell = cell.as_ivec3();
+
+        let corner = |offset: IVec3| hash(self.seed, cell + offset) as f32 / u32::MAX as f32;
+        let x00 = lerp(
+            corner(IVec3::new(0, 0, 0)),
+            corner(IVec3::new(1, 0, 0)),
+            t.x,
+        );
+        let x10 = lerp(
+            corner(IVec3::new(0, 1, 0)),
+            corner(IVec3::new(1, 1, 0)),
+            t.x,
+        );
+        let x01 = lerp(
+            corner(IVec3::new(0, 0, 1)),
+            corner(IVec3::new(1, 0, 1)),
+            t.x,
+        );
+        let x11 = lerp(
+            corner(IVec3::new(0, 1, 1)),
+            corner(IVec3::new(1, 1, 1)),
+            t.x,
+        );
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+    }
+
+    /// Octaves of value noise added together, between 0 and 1.
+    fn fbm(&self, pos: Vec3, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut weight = 0.0;
+        for octave in 0..octaves {
+            total += self.value(pos * (1 << octave) as f32) * amplitude;
+            weight += amplitude;
+            amplitude *= 0.5;
+        }
+        total / weight
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxels(gh: &GH) -> Vec<(UVec3, u8, u8)> {
+        let mut voxels: Vec<_> = gh.voxels().collect();
+        voxels.sort_by_key(|(pos, _, _)| gh.index(*pos));
+        voxels
+    }
+
+    fn generate(generator: &dyn VoxelWorldGenerator, seed: u64) -> GH {
+        GH::from_generator(generator, seed).unwrap()
+    }
+
+    fn assert_deterministic(generator: &dyn VoxelWorldGenerator) {
+        let first = generate(generator, 7);
+        assert_eq!(voxels(&first), voxels(&generate(generator, 7)));
+        assert_ne!(voxels(&first), voxels(&generate(generator, 8)));
+    }
+
+    /// The region at `origin` has the same voxels as that part of the whole world.
+    fn assert_region_matches(generator: &dyn VoxelWorldGenerator, origin: IVec3) {
+        let world = generate(generator, 3);
+        let mut region = GH::empty(32);
+        generator.generate_region(&mut region, origin, 3);
+        for (pos, material, flags) in region.voxels() {
+            let pos = (origin + pos.as_ivec3()).as_uvec3();
+            assert_eq!(world.get_voxel(pos), (material, flags));
+        }
+        for (pos, material, flags) in world.voxels() {
+            let local = pos.as_ivec3() - origin;
+            if local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(32)).all() {
+                assert_eq!(region.get_voxel(local.as_uvec3()), (material, flags));
+            }
+        }
+    }
+
+    fn caves() -> Caves {
+        Caves {
+            size: 64,
+            scale: 12.0,
+            ..default()
+        }
+    }
+
+    fn terrain() -> LayeredTerrain {
+        LayeredTerrain {
+            size: 64,
+            scale: 24.0,
+            ..default()
+        }
+    }
+
+    fn rooms() -> FlatRooms {
+        FlatRooms {
+            size: 64,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn generators_are_deterministic() {
+        assert_deterministic(&caves());
+        assert_deterministic(&terrain());
+        assert_deterministic(&rooms());
+    }
+
+    #[test]
+    fn regions_match_the_world() {
+        for origin in [IVec3::ZERO, IVec3::new(32, 0, 32), IVec3::new(0, 32, 32)] {
+            assert_region_matches(&caves(), origin);
+            assert_region_matches(&terrain(), origin);
+            assert_region_matches(&rooms(), origin);
+        }
+    }
+
+    #[test]
+    fn caves_leave_openness_open() {
+        let gh = generate(&caves(), 1);
+        let solid = gh.voxels().count() as f32 / 64f32.powi(3);
+        assert!(
+            (0.2..0.8).contains(&solid),
+            "{} of the caves is solid",
+            solid
+        );
+        assert!(gh
+            .voxels()
+            .all(|(_, material, flags)| (1..=3).contains(&material)
+                && flags == Flags::COLLISION_FLAG));
+    }
+
+    #[test]
+    fn terrain_columns_are_layered_from_the_bottom() {
+        let generator = terrain();
+        let gh = generate(&generator, 1);
+        for x in 0..64 {
+            for z in 0..64 {
+                let column: Vec<u8> = (0..64)
+                    .map(|y| gh.get_voxel(UVec3::new(x, y, z)).0)
+                    .collect();
+                let height = column.iter().take_while(|material| **material != 0).count();
+                assert!(column[height..].iter().all(|material| *material == 0));
+                assert!(height > 0);
+
+                // grass on top, then 4 of dirt and stone below that
+                let depth = |depth: usize| column[height - 1 - depth];
+                assert_eq!(depth(0), 1);
+                for i in 1..5.min(height) {
+                    assert_eq!(depth(i), 2);
+                }
+                for i in 5..height {
+                    assert_eq!(depth(i), 3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_have_a_floor_walls_and_doorways() {
+        let generator = rooms();
+        let gh = generate(&generator, 1);
+        for x in 0..64 {
+            for z in 0..64 {
+                assert_eq!(gh.get_voxel(UVec3::new(x, 0, z)).0, 1);
+            }
+        }
+
+        // every wall between two corners is solid at the top and has one doorway
+        let room_size = generator.room_size;
+        for wall in (0..64).step_by(room_size as usize) {
+            for segment in 0..64 / room_size {
+                let along = (segment * room_size + 1)..((segment + 1) * room_size);
+                let open: Vec<u32> = along
+                    .clone()
+                    .filter(|along| gh.get_voxel(UVec3::new(wall, 1, *along)).0 == 0)
+                    .collect();
+                assert_eq!(open.len() as u32, generator.door_width);
+                assert!(open.windows(2).all(|pair| pair[1] == pair[0] + 1));
+                for along in along {
+                    let top = UVec3::new(wall, generator.wall_height, along);
+                    assert_eq!(gh.get_voxel(top).0, 2);
+                }
+            }
+        }
+        assert_eq!(
+            gh.get_voxel(UVec3::new(0, generator.wall_height + 1, 0)).0,
+            0
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use super::{reader::Reader, QuantizationReport, VoxelWorldLoadError, GH};
+use crate::VoxelWorldLoadSettings;
+use bevy::{
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageType},
+};
+
+pub const GOX_MAGIC: &[u8; 4] = b"GOX ";
+
+// blocks are 16 voxels wide and stored as 64x64 pngs
+const BLOCK_SIZE: i32 = 16;
+
+impl GH {
+    /// Loads a goxel `.gox` file, merging every visible layer.
+    pub fn from_gox(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<(GH, QuantizationReport), VoxelWorldLoadError> {
+        let mut reader = Reader::new(file, "goxel file");
+        if reader.take(4)? != GOX_MAGIC {
+            return Err("Not a goxel file".into());
+        }
+        let version = reader.u32()?;
+        if version != 2 {
+            return Err(format!("Unsupported goxel version {}", version).into());
+        }
+
+        let mut blocks = Vec::new();
+        let mut voxels = Vec::new();
+        while !reader.is_empty() {
+            let chunk_type = reader.take(4)?;
+            let length = reader.u32()? as usize;
+            let mut chunk = Reader::new(reader.take(length)?, "goxel chunk");
+            let _crc = reader.u32()?;
+
+            match chunk_type {
+                b"BL16" => {
+                    let image = Image::from_buffer(
+                        chunk.bytes,
+                        ImageType::Extension("png"),
+                        CompressedImageFormats::NONE,
+                        true,
+                    )
+                    .map_err(|error| format!("Failed to decode goxel block: {}", error))?;
+                    if image.data.len() < (BLOCK_SIZE * BLOCK_SIZE * BLOCK_SIZE * 4) as usize {
+                        return Err("Goxel block image is too small".into());
+                    }
+                    blocks.push(image.data);
+                }
+                b"LAYR" => {
+                    let block_count = chunk.u32()?;
+                    let mut layer_blocks = Vec::new();
+                    for _ in 0..block_count {
+                        let index = chunk.u32()? as usize;
+                        let origin = IVec3::new(chunk.i32()?, chunk.i32()?, chunk.i32()?);
+                        let _ = chunk.u32()?;
+                        // flipping x and adding the voxels of the block have to stay in range
+                        if (0..3).any(|axis| {
+                            origin[axis] == i32::MIN || origin[axis] > i32::MAX - BLOCK_SIZE
+                        }) {
+                            return Err("Goxel block position is out of range".into());
+                        }
+                        layer_blocks.push((index, origin));
+                    }
+
+                    let mut visible = true;
+                    loop {
+                        let key_length = chunk.u32()? as usize;
+                        if key_length == 0 {
+                            break;
+                        }
+                        let key = chunk.take(key_length)?;
+                        let value_length = chunk.u32()? as usize;
+                        let value = chunk.take(value_length)?;
+                        if key == b"visible" && value.first() == Some(&0) {
+                            visible = false;
+                        }
+                    }
+                    if !visible {
+                        continue;
+                    }
+
+                    for (index, origin) in layer_blocks {
+                        let data = blocks
+                            .get(index)
+                            .ok_or_else(|| format!("Goxel layer uses missing block {}", index))?;
+                        for (i, rgba) in data.chunks_exact(4).take(4096).enumerate() {
+                            if rgba[3] == 0 {
+                                continue;
+                            }
+                            let i = i as i32;
+                            let local = IVec3::new(
+                                i % BLOCK_SIZE,
+                                i / BLOCK_SIZE % BLOCK_SIZE,
+                                i / (BLOCK_SIZE * BLOCK_SIZE),
+                            );
+                            // goxel is z up, flip x to keep the handedness like magica voxel
+                            let pos = origin + local;
+                            let pos = IVec3::new(-pos.x, pos.z, pos.y);
+                            voxels.push((pos, [rgba[0], rgba[1], rgba[2]]));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        GH::from_coloured_voxels(&voxels, settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{palette::encode_png, Anchor};
+
+    fn chunk(file: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        file.extend(kind);
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file.extend(0u32.to_le_bytes());
+    }
+
+    fn layer(origins: &[[i32; 3]], visible: bool) -> Vec<u8> {
+        let mut data = (origins.len() as u32).to_le_bytes().to_vec();
+        for origin in origins {
+            data.extend(0u32.to_le_bytes());
+            for value in origin {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(0u32.to_le_bytes());
+        }
+        data.extend(7u32.to_le_bytes());
+        data.extend(b"visible");
+        data.extend(1u32.to_le_bytes());
+        data.push(visible as u8);
+        data.extend(0u32.to_le_bytes());
+        data
+    }
+
+    /// A file with one filled block, used by every layer.
+    fn gox(layers: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = GOX_MAGIC.to_vec();
+        file.extend(2u32.to_le_bytes());
+        chunk(
+            &mut file,
+            b"BL16",
+            &encode_png(&[[40, 80, 120]; 64 * 64], 64),
+        );
+        for data in layers {
+            chunk(&mut file, b"LAYR", data);
+        }
+        file
+    }
+
+    fn settings() -> VoxelWorldLoadSettings {
+        VoxelWorldLoadSettings {
+            anchor: Anchor::Corner,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn loads_visible_layers() {
+        let file = gox(&[
+            layer(&[[0, 0, 0], [16, 0, 0]], true),
+            layer(&[[0, 0, 64]], false),
+        ]);
+        let (gh, report) = GH::from_gox(&file, &settings()).unwrap();
+        assert_eq!(gh.voxels().count(), 2 * 4096);
+        assert_eq!(report.source_colours, 1);
+        // the hidden layer would have made the world taller
+        assert_eq!(gh.texture_size, 32);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let file = gox(&[layer(&[[0, 0, 0]], true)]);
+        for length in [3, 6, 12, 100, file.len() - 40, file.len() - 1] {
+            assert!(
+                GH::from_gox(&file[..length], &settings()).is_err(),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        let mut file = gox(&[]);
+        file[4] = 3;
+        assert!(GH::from_gox(&file, &settings()).is_err());
+
+        // a chunk longer than the file
+        let mut file = gox(&[]);
+        file.extend(b"LAYR");
+        file.extend(u32::MAX.to_le_bytes());
+        assert!(GH::from_gox(&file, &settings()).is_err());
+
+        let file = gox(&[layer(&[[i32::MAX - 8, 0, 0]], true)]);
+        assert!(GH::from_gox(&file, &settings()).is_err());
+        let file = gox(&[layer(&[[i32::MIN, 0, 0]], true)]);
+        assert!(GH::from_gox(&file, &settings()).is_err());
+        let file = gox(&[layer(&[[-4096, 0, 0], [4096, 0, 0]], true)]);
+        assert!(matches!(
+            GH::from_gox(&file, &settings()),
+            Err(VoxelWorldLoadError::ModelTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use super::{
+    quantize::{median_cut, srgb_pallete},
+    read_file, VoxelWorldLoadError, GH,
+};
+use crate::Flags;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::TextureFormat,
+        texture::{CompressedImageFormats, ImageType},
+    },
+};
+
+/// How the optional second image of a `Heightmap` colours the terrain.
+#[derive(Clone, Debug)]
+pub enum HeightmapColours<T = String> {
+    /// Columns are shaded by their height with a gray palette.
+    None,
+    /// Colours of the image are reduced to at most 255 palette entries with a median cut.
+    Colour(T),
+    /// The red channel of the image is the material of each column, 0 leaves a hole. The colours
+    /// of the image are not kept, materials get the gray shades of `None` until the palette is
+    /// replaced.
+    Material(T),
+}
+
+/// Terrain built from a grayscale png heightmap, load it with `LoadVoxelWorld::Heightmap`.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    /// Brighter pixels make taller columns.
+    pub path: String,
+    pub colours: HeightmapColours,
+    /// Size of the world, the images are stretched to cover it.
+    pub size: u32,
+    /// Height of a white pixel as a fraction of the world size.
+    pub vertical_scale: f32,
+}
+
+impl Heightmap {
+    /// Reads the images from disk and builds the world.
+    pub fn load(&self) -> Result<GH, VoxelWorldLoadError> {
+        let heightmap = read_file(&self.path)?;
+        let colours = match &self.colours {
+            HeightmapColours::None => HeightmapColours::None,
+            HeightmapColours::Colour(path) => HeightmapColours::Colour(read_file(path)?),
+            HeightmapColours::Material(path) => HeightmapColours::Material(read_file(path)?),
+        };
+        let colours = match &colours {
+            HeightmapColours::None => HeightmapColours::None,
+            HeightmapColours::Colour(bytes) => HeightmapColours::Colour(bytes.as_slice()),
+            HeightmapColours::Material(bytes) => HeightmapColours::Material(bytes.as_slice()),
+        };
+        GH::from_heightmap(&heightmap, colours, self.size, self.vertical_scale)
+    }
+}
+
+impl GH {
+    /// Builds a world of filled columns from png images, every voxel gets
+    /// `Flags::COLLISION_FLAG`.
+    pub fn from_heightmap(
+        heightmap: &[u8],
+        colours: HeightmapColours<&[u8]>,
+        texture_size: u32,
+        vertical_scale: f32,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        GH::check_size(texture_size)?;
+        let heightmap = Pixels::from_png(heightmap)?;
+
+        let mut gh = GH::empty(texture_size);
+        for i in 1..256 {
+            let shade = (i as f32 / 255.0).powf(2.2);
+            gh.pallete[i] = [shade, shade, shade, 0.0];
+        }
+
+        let size = texture_size as usize;
+        let mut heights = vec![0; size * size];
+        for z in 0..size {
+            for x in 0..size {
+                let value = heightmap.sample(x, z, size).x;
+                let height = (value * vertical_scale * texture_size as f32).round();
+                heights[z * size + x] = (height.max(0.0) as u32).min(texture_size);
+            }
+        }
+
+        let materials = match colours {
+            HeightmapColours::None => heights
+                .iter()
+                .map(|height| (1 + height * 254 / texture_size) as u8)
+                .collect(),
+            HeightmapColours::Material(image) => {
+                let image = Pixels::from_png(image)?;
+                let mut materials = vec![0; size * size];
+                for z in 0..size {
+                    for x in 0..size {
+                        materials[z * size + x] =
+                            (image.sample(x, z, size).x * 255.0).round() as u8;
+                    }
+                }
+                materials
+            }
+            HeightmapColours::Colour(image) => {
+                let image = Pixels::from_png(image)?;
+                let mut colours = Vec::with_capacity(size * size);
+                for z in 0..size {
+                    for x in 0..size {
+                        let colour = (image.sample(x, z, size).truncate() * 255.0).round();
+                        colours.push([colour.x as u8, colour.y as u8, colour.z as u8]);
+                    }
+                }
+                let (palette, indices, report) = median_cut(&colours, 255);
+                info!("{}", report);
+                gh.pallete = srgb_pallete(&palette);
+                indices.into_iter().map(|index| index + 1).collect()
+            }
+        };
+
+        for z in 0..size {
+            for x in 0..size {
+                let material = materials[z * size + x];
+                if material == 0 {
+                    continue;
+                }
+                for y in 0..heights[z * size + x] {
+                    gh.set_voxel(
+                        UVec3::new(x as u32, y, z as u32),
+                        material,
+                        Flags::COLLISION_FLAG,
+                    );
+                }
+            }
+        }
+
+        Ok(gh)
+    }
+}
+
+/// A decoded image that can be sampled in any of the formats bevy decodes pngs to.
+struct Pixels {
+    image: Image,
+}
+
+impl Pixels {
+    fn from_png(bytes: &[u8]) -> Result<Self, VoxelWorldLoadError> {
+        let image = Image::from_buffer(
+            bytes,
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            true,
+        )
+        .map_err(|error| format!("Failed to decode image: {}", error))?;
+        Ok(Self { image })
+    }
+
+    /// Nearest pixel to voxel column `x`, `z` when the image is stretched over `size` voxels,
+    /// with every channel between 0 and 1.
+    fn sample(&self, x: usize, z: usize, size: usize) -> Vec4 {
+        let width = self.image.texture_descriptor.size.width as usize;
+        let height = self.image.texture_descriptor.size.height as usize;
+        pixel(&self.image, x * width / size, z * height / size)
+    }
+}
+
+/// The pixel at `x`, `y` of a decoded image with every channel between 0 and 1.
+pub(super) fn pixel(image: &Image, x: usize, y: usize) -> Vec4 {
+    let width = image.texture_descriptor.size.width as usize;
+    let index = y * width + x;
+    let data = &image.data;
+    let u16_at = |i: usize| u16::from_ne_bytes([data[i], data[i + 1]]) as f32 / 65535.0;
+    match image.texture_descriptor.format {
+        TextureFormat::R16Uint => Vec4::splat(u16_at(index * 2)).truncate().extend(1.0),
+        TextureFormat::Rg16Uint => Vec4::splat(u16_at(index * 4)).truncate().extend(1.0),
+        TextureFormat::Rgba16Uint => Vec4::new(
+            u16_at(index * 8),
+            u16_at(index * 8 + 2),
+            u16_at(index * 8 + 4),
+            u16_at(index * 8 + 6),
+        ),
+        TextureFormat::Rgba32Float => {
+            let f32_at =
+                |i: usize| f32::from_ne_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            Vec4::new(
+                f32_at(index * 16),
+                f32_at(index * 16 + 4),
+                f32_at(index * 16 + 8),
+                f32_at(index * 16 + 12),
+            )
+            .clamp(Vec4::ZERO, Vec4::ONE)
+        }
+        // every 8 bit format is expanded to rgba
+        _ => {
+            Vec4::new(
+                data[index * 4] as f32,
+                data[index * 4 + 1] as f32,
+                data[index * 4 + 2] as f32,
+                data[index * 4 + 3] as f32,
+            ) / 255.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::palette::encode_png;
+
+    /// A 2x2 image, the top row covers the columns with low z.
+    fn image(pixels: [[u8; 3]; 4]) -> Vec<u8> {
+        encode_png(&pixels, 2)
+    }
+
+    fn column_height(gh: &GH, x: u32, z: u32) -> u32 {
+        (0..gh.texture_size)
+            .take_while(|y| gh.get_voxel(UVec3::new(x, *y, z)).0 != 0)
+            .count() as u32
+    }
+
+    #[test]
+    fn heights_follow_the_brightness() {
+        let heightmap = image([[0; 3], [255; 3], [128; 3], [64; 3]]);
+        let gh = GH::from_heightmap(&heightmap, HeightmapColours::None, 32, 0.5).unwrap();
+
+        // each pixel covers 16x16 columns
+        assert_eq!(column_height(&gh, 0, 0), 0);
+        assert_eq!(column_height(&gh, 31, 0), 16);
+        assert_eq!(column_height(&gh, 0, 31), 8);
+        assert_eq!(column_height(&gh, 20, 20), 4);
+        assert_eq!(column_height(&gh, 15, 15), 0);
+
+        // shaded by height
+        let (material, flags) = gh.get_voxel(UVec3::new(31, 0, 0));
+        assert_eq!(material as u32, 1 + 16 * 254 / 32);
+        assert_eq!(flags, Flags::COLLISION_FLAG);
+        assert_eq!(gh.get_voxel(UVec3::new(31, 16, 0)).0, 0);
+    }
+
+    #[test]
+    fn material_map_picks_materials() {
+        let heightmap = image([[255; 3]; 4]);
+        let materials = image([[3, 200, 40], [0, 255, 255], [7, 0, 0], [255, 0, 0]]);
+        let gh = GH::from_heightmap(&heightmap, HeightmapColours::Material(&materials), 32, 0.25)
+            .unwrap();
+
+        assert_eq!(gh.get_voxel(UVec3::new(0, 7, 0)).0, 3);
+        assert_eq!(column_height(&gh, 0, 0), 8);
+        // 0 leaves a hole
+        assert_eq!(column_height(&gh, 31, 0), 0);
+        assert_eq!(gh.get_voxel(UVec3::new(0, 0, 31)).0, 7);
+        assert_eq!(gh.get_voxel(UVec3::new(31, 0, 31)).0, 255);
+
+        // the colours of the map are not used
+        let shade = (3.0f32 / 255.0).powf(2.2);
+        assert_eq!(gh.pallete[3], [shade, shade, shade, 0.0]);
+    }
+
+    #[test]
+    fn invalid_images_are_errors() {
+        let heightmap = image([[255; 3]; 4]);
+        assert!(GH::from_heightmap(&heightmap[..20], HeightmapColours::None, 32, 1.0).is_err());
+        assert!(
+            GH::from_heightmap(&heightmap, HeightmapColours::Material(&[1, 2, 3]), 32, 1.0)
+                .is_err()
+        );
+        assert!(GH::from_heightmap(&heightmap, HeightmapColours::None, 33, 1.0).is_err());
+    }
+}
@@ -0,0 +1,1128 @@
+use crate::{Flags, LoadVoxelWorld, VoxelWorldLoadSettings, VOXELS_PER_METER};
+pub use asset::{ActiveVoxelWorld, VoxelAnimation, VoxelWorldAsset, VoxelWorldAssetLoader};
+pub use bake::BakeFill;
+use bevy::{
+    prelude::*,
+    render::render_resource::ShaderType,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+pub use generate::{Caves, FlatRooms, LayeredTerrain, TerrainLayer, VoxelWorldGenerator};
+pub use heightmap::{Heightmap, HeightmapColours};
+pub use palette::PaletteFormat;
+pub use quantize::QuantizationReport;
+pub use region::{RegionStore, REGION_EXTENSION};
+pub use schematic::{SchematicBlock, SchematicBlocks};
+pub use snapshot::SNAPSHOT_EXTENSION;
+
+mod asset;
+mod bake;
+mod binvox;
+mod generate;
+mod goxel;
+mod heightmap;
+mod nbt;
+mod palette;
+mod quantize;
+mod qubicle;
+mod reader;
+mod region;
+mod schematic;
+mod snapshot;
+
+/// Smallest world, big enough that resizing a world only ever moves whole bricks. Worlds of 8 and
+/// 16 voxels worked before voxels were stored in bricks and are now rejected with
+/// `VoxelWorldLoadError::InvalidSize`, load a world of this size instead.
+pub const MIN_TEXTURE_SIZE: u32 = 32;
+/// Largest world. Only bricks with voxels in them are stored, so this is past what fits in a
+/// single 3d texture.
+pub const MAX_TEXTURE_SIZE: u32 = 4096;
+/// Side length of the bricks voxels are stored in, see `GH::brick_grid`.
+pub const BRICK_SIZE: u32 = 16;
+/// Voxels in a brick.
+pub const BRICK_VOXELS: usize = (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize;
+
+/// Sent when a `LoadVoxelWorld` request fails, the previous world stays loaded.
+#[derive(Debug)]
+pub enum VoxelWorldLoadError {
+    /// There is no file at the path.
+    NotFound(String),
+    /// The file exists but could not be read.
+    Io(String, std::io::Error),
+    /// The file is not a valid world file.
+    Malformed(String),
+    /// The model does not fit in a world of `MAX_TEXTURE_SIZE` once padded to a cube.
+    ModelTooLarge(UVec3),
+    /// Worlds have to be a power of two between `MIN_TEXTURE_SIZE` and `MAX_TEXTURE_SIZE`.
+    InvalidSize(u32),
+    /// The world has more bricks than the gpu can fit in a brick atlas, followed by how many fit.
+    TooManyBricks(u32, u32),
+    /// A resize would move a streamed world by an offset that is not a whole number of regions,
+    /// followed by the region size.
+    UnalignedResize(IVec3, u32),
+}
+
+impl std::fmt::Display for VoxelWorldLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoxelWorldLoadError::NotFound(path) => write!(f, "No voxel world found at {}", path),
+            VoxelWorldLoadError::Io(path, error) => write!(f, "Failed to read {}: {}", path, error),
+            VoxelWorldLoadError::Malformed(error) => write!(f, "Malformed voxel world: {}", error),
+            VoxelWorldLoadError::ModelTooLarge(size) => write!(
+                f,
+                "Voxel model of size {} does not fit in a world of {}",
+                size, MAX_TEXTURE_SIZE
+            ),
+            VoxelWorldLoadError::InvalidSize(size)
+                if size.is_power_of_two() && *size < MIN_TEXTURE_SIZE =>
+            {
+                write!(
+                    f,
+                    "Voxel worlds smaller than {} are no longer supported, got {}",
+                    MIN_TEXTURE_SIZE, size
+                )
+            }
+            VoxelWorldLoadError::InvalidSize(size) => write!(
+                f,
+                "Voxel world size must be a power of two between {} and {}, got {}",
+                MIN_TEXTURE_SIZE, MAX_TEXTURE_SIZE, size
+            ),
+            VoxelWorldLoadError::TooManyBricks(bricks, capacity) => write!(
+                f,
+                "Voxel world has {} bricks but only {} fit on the gpu",
+                bricks, capacity
+            ),
+            VoxelWorldLoadError::UnalignedResize(offset, region_size) => write!(
+                f,
+                "Resizing would move the streamed voxel world by {}, which is not a multiple of \
+                 the region size {}",
+                offset, region_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VoxelWorldLoadError {}
+
+fn log_quantization((gh, report): (GH, QuantizationReport)) -> GH {
+    info!("{}", report);
+    gh
+}
+
+/// Reads a whole file, telling missing files apart from other io errors.
+pub fn read_file(path: &str) -> Result<Vec<u8>, VoxelWorldLoadError> {
+    std::fs::read(path).map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => VoxelWorldLoadError::NotFound(path.to_string()),
+        _ => VoxelWorldLoadError::Io(path.to_string(), error),
+    })
+}
+
+/// Starts building the world a `LoadVoxelWorld` asks for on the `AsyncComputeTaskPool`.
+pub(crate) fn spawn_load_task(
+    request: LoadVoxelWorld,
+    settings: VoxelWorldLoadSettings,
+) -> Option<Task<Result<GH, VoxelWorldLoadError>>> {
+    let task_pool = AsyncComputeTaskPool::get();
+    let task = match request {
+        LoadVoxelWorld::Empty(size) => {
+            task_pool.spawn(async move { GH::check_size(size).map(|_| GH::empty(size)) })
+        }
+        LoadVoxelWorld::File(path) => {
+            task_pool.spawn(async move { GH::from_file(&read_file(&path)?, &settings) })
+        }
+        LoadVoxelWorld::Heightmap(heightmap) => task_pool.spawn(async move { heightmap.load() }),
+        LoadVoxelWorld::Generate { generator, seed } => {
+            task_pool.spawn(async move { GH::from_generator(generator.as_ref(), seed) })
+        }
+        LoadVoxelWorld::None => return None,
+    };
+    Some(task)
+}
+
+impl From<String> for VoxelWorldLoadError {
+    fn from(error: String) -> Self {
+        VoxelWorldLoadError::Malformed(error)
+    }
+}
+
+impl From<&str> for VoxelWorldLoadError {
+    fn from(error: &str) -> Self {
+        VoxelWorldLoadError::Malformed(error.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct GH {
+    pub levels: [u32; 8],
+    pub texture_size: u32,
+    /// An entry for every brick of `BRICK_SIZE` voxels cubed in the world, in the same order as
+    /// voxels in `GH::index`. Bricks of air are 0, others are 1 + their place in `bricks`.
+    pub brick_grid: Vec<u32>,
+    /// `BRICK_VOXELS` voxels for every brick that has been written to, in the order of
+    /// `GH::brick_voxel_index`. Voxels are material | flags << 8.
+    pub bricks: Vec<u16>,
+    pub pallete: Pallete,
+    pub materials: [MaterialProperties; 256],
+}
+
+#[derive(Clone, Deref, DerefMut)]
+pub struct Pallete([[f32; 4]; 256]);
+
+/// Physical properties of a material that the palette colour does not carry.
+#[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+pub struct MaterialProperties {
+    pub roughness: f32,
+    pub metalness: f32,
+    /// 0 is opaque and 1 lets all light through.
+    pub transparency: f32,
+    /// Strength of the light given off, the colour of the light is in the palette.
+    pub emission: f32,
+}
+
+impl Default for MaterialProperties {
+    fn default() -> Self {
+        Self {
+            roughness: 1.0,
+            metalness: 0.0,
+            transparency: 0.0,
+            emission: 0.0,
+        }
+    }
+}
+
+/// Where a model is placed when it is padded into a larger power of two world.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    /// Centered on every axis.
+    #[default]
+    Center,
+    /// Centered horizontally and resting on the bottom of the world.
+    Floor,
+    /// Placed in the corner at the world origin.
+    Corner,
+}
+
+impl Anchor {
+    /// Offset of a model of `size` inside a world of `texture_size`, in voxel space (y up).
+    pub fn offset(&self, size: UVec3, texture_size: u32) -> UVec3 {
+        let free = UVec3::splat(texture_size) - size;
+        match self {
+            Anchor::Center => free / 2,
+            Anchor::Floor => UVec3::new(free.x / 2, 0, free.z / 2),
+            Anchor::Corner => UVec3::ZERO,
+        }
+    }
+
+    /// Where the contents of a world of `old_size` go when it is resized to `new_size`: the corner
+    /// copied from, the corner copied to and the size of the copied cube. Worlds that shrink keep
+    /// the part of the old world the anchor would have placed the new one at.
+    pub(crate) fn resize_region(&self, old_size: u32, new_size: u32) -> (UVec3, UVec3, u32) {
+        if new_size >= old_size {
+            let dst = self.offset(UVec3::splat(old_size), new_size);
+            (UVec3::ZERO, dst, old_size)
+        } else {
+            let src = self.offset(UVec3::splat(new_size), old_size);
+            (src, UVec3::ZERO, new_size)
+        }
+    }
+
+    /// How far things in the world have to move to stay on the same voxels after a resize, since
+    /// `world_to_voxel` keeps the center of the world at the origin.
+    pub(crate) fn resize_translation(&self, old_size: u32, new_size: u32) -> Vec3 {
+        let (src, dst, _) = self.resize_region(old_size, new_size);
+        let center = old_size as f32 / 2.0 - new_size as f32 / 2.0;
+        (dst.as_vec3() - src.as_vec3() + center) / VOXELS_PER_METER
+    }
+}
+
+impl GH {
+    /// `texture_size` must be a power of two and at least `MIN_TEXTURE_SIZE`.
+    pub fn empty(texture_size: u32) -> Self {
+        assert!(
+            texture_size.is_power_of_two() && texture_size >= MIN_TEXTURE_SIZE,
+            "Voxel world size must be a power of two and at least {}, got {}",
+            MIN_TEXTURE_SIZE,
+            texture_size
+        );
+
+        let brick_grid_size = texture_size / BRICK_SIZE;
+        Self {
+            levels: Self::levels_for_size(texture_size),
+            texture_size,
+            brick_grid: vec![0; brick_grid_size.pow(3) as usize],
+            bricks: Vec::new(),
+            pallete: Pallete([[0.0; 4]; 256]),
+            materials: [MaterialProperties::default(); 256],
+        }
+    }
+
+    pub fn check_size(texture_size: u32) -> Result<(), VoxelWorldLoadError> {
+        if texture_size.is_power_of_two()
+            && (MIN_TEXTURE_SIZE..=MAX_TEXTURE_SIZE).contains(&texture_size)
+        {
+            Ok(())
+        } else {
+            Err(VoxelWorldLoadError::InvalidSize(texture_size))
+        }
+    }
+
+    /// The smallest valid world size that fits a model of `size`.
+    pub fn size_to_fit(size: UVec3) -> Result<u32, VoxelWorldLoadError> {
+        if size.max_element() > MAX_TEXTURE_SIZE {
+            return Err(VoxelWorldLoadError::ModelTooLarge(size));
+        }
+        Ok(size.max_element().next_power_of_two().max(MIN_TEXTURE_SIZE))
+    }
+
+    /// Side lengths of the levels of the grid hierarchy of a world, unused levels are 0. The
+    /// finest level has a cell for every brick.
+    pub fn levels_for_size(texture_size: u32) -> [u32; 8] {
+        let mut levels = [0; 8];
+        let i = (texture_size / BRICK_SIZE)
+            .trailing_zeros()
+            .saturating_sub(2);
+        for i in 0..i {
+            levels[i as usize] = 1 << (i + 3);
+        }
+        levels
+    }
+
+    pub fn get_offsets_from_levels(levels: &[u32; 8]) -> [u32; 8] {
+        let mut offsets = [0; 8];
+        let mut last = 0;
+        for i in 0..8 {
+            offsets[i] = last;
+            last = last + levels[i] * levels[i] * levels[i];
+        }
+        offsets
+    }
+
+    pub fn get_offsets(&self) -> [u32; 8] {
+        Self::get_offsets_from_levels(&self.levels)
+    }
+
+    pub fn get_buffer_size_from_levels(levels: &[u32; 8]) -> usize {
+        let mut length = 0;
+        for i in 0..8 {
+            length += levels[i] * levels[i] * levels[i];
+        }
+        length as usize / 8
+    }
+
+    pub fn get_buffer_size(&self) -> usize {
+        Self::get_buffer_size_from_levels(&self.levels)
+    }
+
+    /// Index of the voxel at `pos` when the voxels of the world are laid out in a line, x first.
+    pub fn index(&self, pos: UVec3) -> usize {
+        let size = self.texture_size as usize;
+        pos.x as usize * size * size + pos.y as usize * size + pos.z as usize
+    }
+
+    /// Bricks along each side of the world.
+    pub fn brick_grid_size(&self) -> u32 {
+        self.texture_size / BRICK_SIZE
+    }
+
+    /// Index into `brick_grid` of the brick at `brick`, in bricks.
+    pub fn brick_index(&self, brick: UVec3) -> usize {
+        let size = self.brick_grid_size() as usize;
+        brick.x as usize * size * size + brick.y as usize * size + brick.z as usize
+    }
+
+    /// Index of the voxel at `pos` into the voxels of its brick.
+    pub fn brick_voxel_index(pos: UVec3) -> usize {
+        let pos = pos % BRICK_SIZE;
+        (pos.x * BRICK_SIZE * BRICK_SIZE + pos.y * BRICK_SIZE + pos.z) as usize
+    }
+
+    /// How many bricks have voxels stored.
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len() / BRICK_VOXELS
+    }
+
+    /// The voxels of the brick `brick_grid` points to with `entry`.
+    pub fn brick(&self, entry: u32) -> &[u16] {
+        let start = (entry as usize - 1) * BRICK_VOXELS;
+        &self.bricks[start..start + BRICK_VOXELS]
+    }
+
+    /// Every voxel that is not air, with its material and flags, a brick at a time.
+    pub fn voxels(&self) -> impl Iterator<Item = (UVec3, u8, u8)> + '_ {
+        let size = self.brick_grid_size();
+        self.brick_grid
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| **entry != 0)
+            .flat_map(move |(index, entry)| {
+                let index = index as u32;
+                let origin = UVec3::new(index / (size * size), index / size % size, index % size)
+                    * BRICK_SIZE;
+                self.brick(*entry)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, voxel)| **voxel != 0)
+                    .map(move |(i, voxel)| {
+                        let i = i as u32;
+                        let local = UVec3::new(
+                            i / (BRICK_SIZE * BRICK_SIZE),
+                            i / BRICK_SIZE % BRICK_SIZE,
+                            i % BRICK_SIZE,
+                        );
+                        (origin + local, *voxel as u8, (*voxel >> 8) as u8)
+                    })
+            })
+    }
+
+    /// A copy of the world at `new_size` with its contents placed by `anchor`, see
+    /// `ResizeVoxelWorld`.
+    pub fn resized(&self, new_size: u32, anchor: Anchor) -> Self {
+        let mut gh = Self::empty(new_size);
+        gh.pallete = self.pallete.clone();
+        gh.materials = self.materials;
+
+        let (src, dst, size) = anchor.resize_region(self.texture_size, new_size);
+        gh.copy_bricks(self, src, dst, UVec3::splat(size));
+        gh
+    }
+
+    /// Copies the box of `size` voxels at `src` in `other` to `dst` in this world, replacing what
+    /// was there. Corners and size have to be whole bricks.
+    pub(crate) fn copy_bricks(&mut self, other: &GH, src: UVec3, dst: UVec3, size: UVec3) {
+        let (src, dst, size) = (src / BRICK_SIZE, dst / BRICK_SIZE, size / BRICK_SIZE);
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let brick = UVec3::new(x, y, z);
+                    let from = other.brick_grid[other.brick_index(src + brick)];
+                    let index = self.brick_index(dst + brick);
+                    if from == 0 {
+                        // the voxels of a brick that is replaced with air stay unused
+                        self.brick_grid[index] = 0;
+                        continue;
+                    }
+
+                    let mut entry = self.brick_grid[index];
+                    if entry == 0 {
+                        self.bricks.resize(self.bricks.len() + BRICK_VOXELS, 0);
+                        entry = self.brick_count() as u32;
+                        self.brick_grid[index] = entry;
+                    }
+                    let start = (entry as usize - 1) * BRICK_VOXELS;
+                    self.bricks[start..start + BRICK_VOXELS].copy_from_slice(other.brick(from));
+                }
+            }
+        }
+    }
+
+    /// Material and flags of the voxel at `pos`.
+    pub fn get_voxel(&self, pos: UVec3) -> (u8, u8) {
+        let entry = self.brick_grid[self.brick_index(pos / BRICK_SIZE)];
+        if entry == 0 {
+            return (0, 0);
+        }
+        let voxel = self.brick(entry)[Self::brick_voxel_index(pos)];
+        (voxel as u8, (voxel >> 8) as u8)
+    }
+
+    /// Bricks are added the first time anything but air is written to them.
+    pub fn set_voxel(&mut self, pos: UVec3, material: u8, flags: u8) {
+        let brick_index = self.brick_index(pos / BRICK_SIZE);
+        let mut entry = self.brick_grid[brick_index];
+        if entry == 0 {
+            if material == 0 && flags == 0 {
+                return;
+            }
+            self.bricks.resize(self.bricks.len() + BRICK_VOXELS, 0);
+            entry = self.brick_count() as u32;
+            self.brick_grid[brick_index] = entry;
+        }
+
+        let start = (entry as usize - 1) * BRICK_VOXELS;
+        self.bricks[start + Self::brick_voxel_index(pos)] = material as u16 | (flags as u16) << 8;
+    }
+
+    /// Loads any supported world file, the format is picked from the file header.
+    pub fn from_file(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        if file.starts_with(snapshot::SNAPSHOT_MAGIC) {
+            GH::from_snapshot(file)
+        } else if file.starts_with(&[0x1f, 0x8b]) || file.starts_with(&[0x0a]) {
+            // gzipped or uncompressed nbt
+            GH::from_schematic(file, settings)
+        } else if file.starts_with(qubicle::QB_MAGIC) {
+            GH::from_qb(file, settings).map(log_quantization)
+        } else if file.starts_with(goxel::GOX_MAGIC) {
+            GH::from_gox(file, settings).map(log_quantization)
+        } else if file.starts_with(binvox::BINVOX_MAGIC) {
+            GH::from_binvox(file, settings).map(|(gh, _)| gh)
+        } else {
+            GH::from_vox(file, settings)
+        }
+    }
+
+    /// Encodes the world for saving to `path`, snapshots are used for the `SNAPSHOT_EXTENSION`
+    /// and magica voxel files for everything else.
+    pub fn to_file(&self, path: &str) -> Vec<u8> {
+        let is_snapshot = std::path::Path::new(path)
+            .extension()
+            .map_or(false, |extension| extension == SNAPSHOT_EXTENSION);
+        if is_snapshot {
+            self.to_snapshot()
+        } else {
+            self.to_vox()
+        }
+    }
+
+    pub fn from_vox(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        vox_scene(&load_vox(file)?, settings)
+    }
+
+    /// Every model of a magica voxel file as a world of its own, in the order they are stored,
+    /// which is the order of the frames of an animation. The scene graph is ignored.
+    pub fn from_vox_frames(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<Vec<GH>, VoxelWorldLoadError> {
+        vox_frames(&load_vox(file)?, settings)
+    }
+
+    /// `from_vox` and `from_vox_frames` together, parsing the file once.
+    pub fn from_vox_with_frames(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<(GH, Vec<GH>), VoxelWorldLoadError> {
+        let vox = load_vox(file)?;
+        Ok((vox_scene(&vox, settings)?, vox_frames(&vox, settings)?))
+    }
+}
+
+fn load_vox(file: &[u8]) -> Result<dot_vox::DotVoxData, VoxelWorldLoadError> {
+    let vox = dot_vox::load_bytes(file)?;
+    if vox.models.is_empty() {
+        return Err("Voxel file contains no models!".into());
+    }
+    Ok(vox)
+}
+
+fn vox_scene(
+    vox: &dot_vox::DotVoxData,
+    settings: &VoxelWorldLoadSettings,
+) -> Result<GH, VoxelWorldLoadError> {
+    // merge every shape in the scene graph, files without one only contain a single model
+    let mut scene = VoxScene::new();
+    if vox.scenes.is_empty() {
+        scene.push_model(
+            &vox.models[0],
+            VoxTransform::centered(&vox.models[0]),
+            Flags::COLLISION_FLAG,
+        );
+    } else {
+        let transform = VoxTransform {
+            rotation: Mat3::IDENTITY,
+            translation: Vec3::ZERO,
+        };
+        scene.push_node(vox, 0, transform, Flags::COLLISION_FLAG, settings);
+    }
+    scene.into_gh(vox, settings)
+}
+
+fn vox_frames(
+    vox: &dot_vox::DotVoxData,
+    settings: &VoxelWorldLoadSettings,
+) -> Result<Vec<GH>, VoxelWorldLoadError> {
+    vox.models
+        .iter()
+        .map(|model| {
+            let mut scene = VoxScene::new();
+            scene.push_model(model, VoxTransform::centered(model), Flags::COLLISION_FLAG);
+            scene.into_gh(vox, settings)
+        })
+        .collect()
+}
+
+fn vox_pallete(vox: &dot_vox::DotVoxData) -> Result<Pallete, VoxelWorldLoadError> {
+    let mut pallete = Pallete([[0.0; 4]; 256]);
+    for (i, colour) in vox.palette.iter().take(256).enumerate() {
+        let mut material = Vec4::new(
+            colour.r as f32 / 255.0,
+            colour.g as f32 / 255.0,
+            colour.b as f32 / 255.0,
+            0.0,
+        );
+        material = material.powf(2.2);
+
+        if let Some(vox_material) = vox.materials.get(i) {
+            if vox_material.material_type() == Some("_emit") {
+                let emission = vox_material
+                    .emission()
+                    .ok_or_else(|| format!("Emissive material {} has no _emit value", i))?;
+                material *= 1.0 + emission;
+                if let Some(flux) = vox_material.radiant_flux() {
+                    material = material.powf(flux);
+                }
+                material.w = 1.0;
+            }
+        }
+
+        pallete[i] = material.to_array();
+    }
+    Ok(pallete)
+}
+
+fn vox_material_properties(vox: &dot_vox::DotVoxData) -> [MaterialProperties; 256] {
+    let mut materials = [MaterialProperties::default(); 256];
+    for (i, vox_material) in vox.materials.iter().take(256).enumerate() {
+        let material_type = vox_material.material_type().unwrap_or("_diffuse");
+        let material = &mut materials[i];
+        if material_type != "_diffuse" {
+            if let Some(roughness) = vox_material.roughness() {
+                material.roughness = roughness;
+            }
+        }
+        if matches!(material_type, "_metal" | "_blend") {
+            material.metalness = vox_material.metalness().unwrap_or(0.0);
+        }
+        if matches!(material_type, "_glass" | "_blend" | "_cloud") {
+            // older files only store the transparency in _alpha
+            material.transparency = vox_material
+                .transparency()
+                .or_else(|| vox_material.opacity())
+                .unwrap_or(0.0);
+        }
+        if matches!(material_type, "_emit" | "_blend") {
+            material.emission = vox_material.emission().unwrap_or(0.0);
+        }
+    }
+    materials
+}
+
+#[derive(Clone, Copy)]
+struct VoxTransform {
+    rotation: Mat3,
+    translation: Vec3,
+}
+
+impl VoxTransform {
+    /// Places a model on its own with its corner at the origin.
+    fn centered(model: &dot_vox::Model) -> Self {
+        Self {
+            rotation: Mat3::IDENTITY,
+            translation: (vox_model_size(model) / 2.0).floor(),
+        }
+    }
+}
+
+fn vox_model_size(model: &dot_vox::Model) -> Vec3 {
+    Vec3::new(
+        model.size.x as f32,
+        model.size.y as f32,
+        model.size.z as f32,
+    )
+}
+
+/// Every voxel in a magica voxel scene in scene space along with the bounds of the models.
+struct VoxScene {
+    voxels: Vec<(IVec3, u8, u8)>,
+    min: IVec3,
+    max: IVec3,
+}
+
+impl VoxScene {
+    fn new() -> Self {
+        Self {
+            voxels: Vec::new(),
+            min: IVec3::splat(i32::MAX),
+            max: IVec3::splat(i32::MIN),
+        }
+    }
+
+    /// Places the scene in the smallest world it fits in, with the palette and materials of `vox`.
+    fn into_gh(
+        mut self,
+        vox: &dot_vox::DotVoxData,
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        if self.min.cmpgt(self.max).any() {
+            self.min = IVec3::ZERO;
+            self.max = IVec3::ZERO;
+        }
+        let min = self.min;
+
+        // magica voxel is z up so swap y and z to get the size in voxel space
+        let model_size = (self.max - min + IVec3::ONE).as_uvec3();
+        let model_size = UVec3::new(model_size.x, model_size.z, model_size.y);
+        let size = GH::size_to_fit(model_size)?;
+        let offset = settings.anchor.offset(model_size, size);
+
+        let mut gh = GH::empty(size);
+        gh.pallete = vox_pallete(vox)?;
+        gh.materials = vox_material_properties(vox);
+
+        for (pos, material, flags) in self.voxels {
+            let pos = (pos - min).as_uvec3();
+            let pos = UVec3::new(model_size.x - 1 - pos.x, pos.z, pos.y);
+
+            gh.set_voxel(offset + pos, material, flags);
+        }
+
+        Ok(gh)
+    }
+
+    /// Walks the scene graph from `node` and pushes every visible model.
+    fn push_node(
+        &mut self,
+        vox: &dot_vox::DotVoxData,
+        node: u32,
+        transform: VoxTransform,
+        flags: u8,
+        settings: &VoxelWorldLoadSettings,
+    ) {
+        match vox.scenes.get(node as usize) {
+            Some(dot_vox::SceneNode::Transform {
+                attributes,
+                frames,
+                child,
+                layer_id,
+            }) => {
+                if attributes
+                    .get("_hidden")
+                    .map_or(false, |hidden| hidden == "1")
+                {
+                    return;
+                }
+
+                let mut flags = flags;
+                if let Some(layer) = vox.layers.get(*layer_id as usize) {
+                    if layer.hidden() {
+                        return;
+                    }
+                    if let Some(layer_flags) = layer
+                        .name()
+                        .and_then(|name| settings.layer_flags.get(&name))
+                    {
+                        flags = *layer_flags;
+                    }
+                }
+
+                let mut local = VoxTransform {
+                    rotation: Mat3::IDENTITY,
+                    translation: Vec3::ZERO,
+                };
+                if let Some(frame) = frames.first() {
+                    if let Some(rotation) = frame.orientation() {
+                        local.rotation = Mat3::from_cols_array_2d(&rotation.to_cols_array_2d());
+                    }
+                    if let Some(position) = frame.position() {
+                        local.translation =
+                            Vec3::new(position.x as f32, position.y as f32, position.z as f32);
+                    }
+                }
+
+                let transform = VoxTransform {
+                    rotation: transform.rotation * local.rotation,
+                    translation: transform.rotation * local.translation + transform.translation,
+                };
+                self.push_node(vox, *child, transform, flags, settings);
+            }
+            Some(dot_vox::SceneNode::Group { children, .. }) => {
+                for child in children {
+                    self.push_node(vox, *child, transform, flags, settings);
+                }
+            }
+            Some(dot_vox::SceneNode::Shape { models, .. }) => {
+                for shape_model in models {
+                    if let Some(model) = vox.models.get(shape_model.model_id as usize) {
+                        self.push_model(model, transform, flags);
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn push_model(&mut self, model: &dot_vox::Model, transform: VoxTransform, flags: u8) {
+        // models are rotated around their center
+        let half_size = vox_model_size(model) / 2.0;
+        let to_scene = |pos: Vec3| {
+            let pos = transform.rotation * (pos + 0.5 - half_size) + transform.translation;
+            pos.floor().as_ivec3()
+        };
+
+        let first = to_scene(Vec3::ZERO);
+        let last = to_scene(vox_model_size(model) - 1.0);
+        self.min = self.min.min(first.min(last));
+        self.max = self.max.max(first.max(last));
+
+        for voxel in &model.voxels {
+            let pos = to_scene(Vec3::new(voxel.x as f32, voxel.y as f32, voxel.z as f32));
+            self.voxels.push((pos, voxel.i, flags));
+        }
+    }
+}
+
+/// Largest model magica voxel supports on each axis.
+const MAX_VOX_MODEL_SIZE: u32 = 256;
+
+impl GH {
+    /// Writes the world as a magica voxel file that `GH::from_vox` loads back into the same world.
+    /// Worlds bigger than 256 are split into multiple models placed with the scene graph.
+    /// Animation and portal voxels are skipped since they are recreated every frame.
+    pub fn to_vox(&self) -> Vec<u8> {
+        let size = self.texture_size;
+        let model_size = size.min(MAX_VOX_MODEL_SIZE);
+        let models_per_axis = size / model_size;
+
+        let mut models = vec![Vec::new(); models_per_axis.pow(3) as usize];
+        let model_index = |pos: UVec3| {
+            let pos = pos / model_size;
+            (pos.x * models_per_axis * models_per_axis + pos.y * models_per_axis + pos.z) as usize
+        };
+
+        let mut skipped = 0;
+        for (pos, material, flags) in self.voxels() {
+            if flags & (Flags::ANIMATION_FLAG | Flags::PORTAL_FLAG) != 0 {
+                continue;
+            }
+            // magica voxel only has 255 colours
+            if material == 255 {
+                skipped += 1;
+                continue;
+            }
+
+            // undo the axis swizzle in from_vox
+            let pos = UVec3::new(size - 1 - pos.x, pos.z, pos.y);
+            let local = pos % model_size;
+            models[model_index(pos)].extend([
+                local.x as u8,
+                local.y as u8,
+                local.z as u8,
+                material + 1,
+            ]);
+        }
+        if skipped > 0 {
+            warn!(
+                "{} voxels with material 255 can not be saved to a .vox file",
+                skipped
+            );
+        }
+
+        let mut main = Vec::new();
+        for voxels in &models {
+            let mut chunk = Vec::new();
+            push_u32(&mut chunk, model_size);
+            push_u32(&mut chunk, model_size);
+            push_u32(&mut chunk, model_size);
+            push_vox_chunk(&mut main, b"SIZE", &chunk);
+
+            let mut chunk = Vec::new();
+            push_u32(&mut chunk, voxels.len() as u32 / 4);
+            chunk.extend(voxels);
+            push_vox_chunk(&mut main, b"XYZI", &chunk);
+        }
+
+        // scene graph: root transform -> group -> a transform and shape for every model
+        let model_count = models.len() as u32;
+        push_vox_transform(&mut main, 0, 1, IVec3::ZERO);
+        let mut chunk = Vec::new();
+        push_u32(&mut chunk, 1);
+        push_vox_dict(&mut chunk, &[]);
+        push_u32(&mut chunk, model_count);
+        for i in 0..model_count {
+            push_u32(&mut chunk, 2 + i * 2);
+        }
+        push_vox_chunk(&mut main, b"nGRP", &chunk);
+        for i in 0..model_count {
+            let pos = UVec3::new(
+                i / (models_per_axis * models_per_axis),
+                i / models_per_axis % models_per_axis,
+                i % models_per_axis,
+            );
+            // models are placed by their center
+            let translation = (pos * model_size + model_size / 2).as_ivec3();
+            push_vox_transform(&mut main, 2 + i * 2, 3 + i * 2, translation);
+
+            let mut chunk = Vec::new();
+            push_u32(&mut chunk, 3 + i * 2);
+            push_vox_dict(&mut chunk, &[]);
+            push_u32(&mut chunk, 1);
+            push_u32(&mut chunk, i);
+            push_vox_dict(&mut chunk, &[]);
+            push_vox_chunk(&mut main, b"nSHP", &chunk);
+        }
+
+        // palette and materials, undoing the conversion in vox_pallete
+        let mut rgba = Vec::new();
+        let mut materials = Vec::new();
+        for i in 0..256 {
+            let material = Vec4::from_array(self.pallete[i]);
+            let (colour, emission) = if material.w != 0.0 {
+                split_emission(material.truncate())
+            } else {
+                (material.truncate().powf(1.0 / 2.2) * 255.0, 0.0)
+            };
+            rgba.extend([
+                colour.x.round() as u8,
+                colour.y.round() as u8,
+                colour.z.round() as u8,
+                255,
+            ]);
+
+            let properties = self.materials[i];
+            let mut dict = Vec::new();
+            if material.w != 0.0 {
+                // the table keeps the exact _emit value from the original file
+                let emission = if properties.emission > 0.0 {
+                    properties.emission
+                } else {
+                    emission
+                };
+                dict.push(("_type", "_emit".to_string()));
+                dict.push(("_emit", emission.to_string()));
+            } else if properties.metalness > 0.0 && properties.transparency > 0.0 {
+                dict.push(("_type", "_blend".to_string()));
+            } else if properties.metalness > 0.0 {
+                dict.push(("_type", "_metal".to_string()));
+            } else if properties.transparency > 0.0 {
+                dict.push(("_type", "_glass".to_string()));
+            } else {
+                dict.push(("_type", "_diffuse".to_string()));
+            }
+            if dict[0].1 != "_diffuse" {
+                dict.push(("_rough", properties.roughness.to_string()));
+            }
+            if properties.metalness > 0.0 {
+                dict.push(("_metal", properties.metalness.to_string()));
+            }
+            if properties.transparency > 0.0 {
+                dict.push(("_trans", properties.transparency.to_string()));
+                dict.push(("_alpha", properties.transparency.to_string()));
+            }
+
+            let mut chunk = Vec::new();
+            push_u32(&mut chunk, i as u32 + 1);
+            push_vox_dict(&mut chunk, &dict);
+            push_vox_chunk(&mut materials, b"MATL", &chunk);
+        }
+        push_vox_chunk(&mut main, b"RGBA", &rgba);
+        main.extend(materials);
+
+        let mut file = b"VOX ".to_vec();
+        push_u32(&mut file, 150);
+        file.extend(b"MAIN");
+        push_u32(&mut file, 0);
+        push_u32(&mut file, main.len() as u32);
+        file.extend(main);
+        file
+    }
+}
+
+/// Splits an emissive linear colour back into the srgb colour and `_emit` value used by magica
+/// voxel. Every brightness of the brightest channel is tried so colours from a .vox file come back
+/// exactly, otherwise the colour is normalized.
+fn split_emission(colour: Vec3) -> (Vec3, f32) {
+    let max = colour.max_element();
+    if max <= 0.0 {
+        return (Vec3::ZERO, 0.0);
+    }
+
+    for brightest in (1..=255).rev() {
+        let scale = max / (brightest as f32 / 255.0).powf(2.2);
+        if scale < 1.0 {
+            break;
+        }
+        let srgb = (colour / scale).powf(1.0 / 2.2) * 255.0;
+        if (srgb - srgb.round()).abs().max_element() < 0.01 {
+            return (srgb, scale - 1.0);
+        }
+    }
+
+    let scale = max.max(1.0);
+    ((colour / scale).powf(1.0 / 2.2) * 255.0, scale - 1.0)
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend(value.to_le_bytes());
+}
+
+fn push_vox_chunk(buffer: &mut Vec<u8>, id: &[u8; 4], chunk: &[u8]) {
+    buffer.extend(id);
+    push_u32(buffer, chunk.len() as u32);
+    push_u32(buffer, 0);
+    buffer.extend(chunk);
+}
+
+fn push_vox_dict(buffer: &mut Vec<u8>, dict: &[(&str, String)]) {
+    push_u32(buffer, dict.len() as u32);
+    for (key, value) in dict {
+        push_u32(buffer, key.len() as u32);
+        buffer.extend(key.as_bytes());
+        push_u32(buffer, value.len() as u32);
+        buffer.extend(value.as_bytes());
+    }
+}
+
+fn push_vox_transform(buffer: &mut Vec<u8>, id: u32, child: u32, translation: IVec3) {
+    let mut chunk = Vec::new();
+    push_u32(&mut chunk, id);
+    push_vox_dict(&mut chunk, &[]);
+    push_u32(&mut chunk, child);
+    push_u32(&mut chunk, u32::MAX); // reserved
+    push_u32(&mut chunk, u32::MAX); // no layer
+    push_u32(&mut chunk, 1);
+    let translation = format!("{} {} {}", translation.x, translation.y, translation.z);
+    push_vox_dict(&mut chunk, &[("_t", translation)]);
+    push_vox_chunk(buffer, b"nTRN", &chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worlds_smaller_than_a_brick_are_rejected() {
+        for size in [8, 16] {
+            let error = GH::check_size(size).unwrap_err();
+            assert!(matches!(error, VoxelWorldLoadError::InvalidSize(8 | 16)));
+            assert!(error.to_string().contains("no longer supported"));
+        }
+        assert!(GH::check_size(MIN_TEXTURE_SIZE).is_ok());
+        assert!(GH::check_size(48).is_err());
+        assert_eq!(
+            GH::size_to_fit(UVec3::new(8, 1, 1)).unwrap(),
+            MIN_TEXTURE_SIZE
+        );
+    }
+
+    #[test]
+    fn set_voxel_adds_bricks_only_for_solid_voxels() {
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 2, 3), 0, 0);
+        assert_eq!(gh.brick_count(), 0);
+
+        gh.set_voxel(UVec3::new(1, 2, 3), 7, 16);
+        gh.set_voxel(UVec3::new(15, 15, 15), 8, 0);
+        assert_eq!(gh.brick_count(), 1);
+        gh.set_voxel(UVec3::new(16, 0, 31), 9, 128);
+        assert_eq!(gh.brick_count(), 2);
+
+        assert_eq!(gh.get_voxel(UVec3::new(1, 2, 3)), (7, 16));
+        assert_eq!(gh.get_voxel(UVec3::new(15, 15, 15)), (8, 0));
+        assert_eq!(gh.get_voxel(UVec3::new(16, 0, 31)), (9, 128));
+        // air in a brick with voxels and in a brick without any
+        assert_eq!(gh.get_voxel(UVec3::new(0, 0, 0)), (0, 0));
+        assert_eq!(gh.get_voxel(UVec3::new(31, 31, 0)), (0, 0));
+
+        // clearing a voxel keeps its brick
+        gh.set_voxel(UVec3::new(1, 2, 3), 0, 0);
+        assert_eq!(gh.get_voxel(UVec3::new(1, 2, 3)), (0, 0));
+        assert_eq!(gh.brick_count(), 2);
+    }
+
+    #[test]
+    fn voxels_lists_every_solid_voxel_by_brick() {
+        let mut gh = GH::empty(32);
+        let placed = [
+            (UVec3::new(17, 1, 1), 3, 0),
+            (UVec3::new(0, 0, 1), 1, 16),
+            (UVec3::new(2, 0, 0), 2, 8),
+            (UVec3::new(31, 31, 31), 4, 0),
+        ];
+        for (pos, material, flags) in placed {
+            gh.set_voxel(pos, material, flags);
+        }
+        gh.set_voxel(UVec3::new(5, 5, 5), 6, 0);
+        gh.set_voxel(UVec3::new(5, 5, 5), 0, 0);
+
+        // bricks in grid order, then voxels in brick order
+        assert_eq!(
+            gh.voxels().collect::<Vec<_>>(),
+            vec![placed[1], placed[2], placed[0], placed[3]]
+        );
+    }
+
+    #[test]
+    fn copy_bricks_moves_whole_bricks() {
+        let mut other = GH::empty(64);
+        other.set_voxel(UVec3::new(16, 17, 18), 5, 16);
+        other.set_voxel(UVec3::new(40, 40, 40), 6, 0);
+
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 1, 1), 9, 0);
+        gh.set_voxel(UVec3::new(17, 17, 17), 9, 0);
+        gh.copy_bricks(&other, UVec3::splat(16), UVec3::ZERO, UVec3::splat(32));
+
+        assert_eq!(gh.get_voxel(UVec3::new(0, 1, 2)), (5, 16));
+        assert_eq!(gh.get_voxel(UVec3::new(24, 24, 24)), (6, 0));
+        // replaced by the copy, including with air
+        assert_eq!(gh.get_voxel(UVec3::new(1, 1, 1)), (0, 0));
+        assert_eq!(gh.get_voxel(UVec3::new(17, 17, 17)), (0, 0));
+        assert_eq!(gh.voxels().count(), 2);
+    }
+
+    #[test]
+    fn resized_keeps_voxels_at_the_anchor() {
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 2, 3), 7, 16);
+
+        let grown = gh.resized(64, Anchor::Center);
+        assert_eq!(grown.get_voxel(UVec3::new(17, 18, 19)), (7, 16));
+        let shrunk = grown.resized(32, Anchor::Center);
+        assert_eq!(
+            shrunk.voxels().collect::<Vec<_>>(),
+            gh.voxels().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn vox_files_round_trip_across_models() {
+        let mut gh = GH::empty(512);
+        gh.pallete[0] = [0.25, 0.5, 0.75, 0.0];
+        // voxels in the opposite corners keep the file the size of the world, the others are in
+        // models of their own
+        let placed = [
+            (UVec3::new(0, 0, 0), 0),
+            (UVec3::new(511, 511, 511), 1),
+            (UVec3::new(300, 20, 40), 7),
+            (UVec3::new(10, 400, 260), 254),
+            (UVec3::new(256, 256, 255), 3),
+        ];
+        for (pos, material) in placed {
+            gh.set_voxel(pos, material, Flags::COLLISION_FLAG);
+        }
+        // magica voxel only has 255 colours
+        gh.set_voxel(UVec3::new(100, 100, 100), 255, Flags::COLLISION_FLAG);
+
+        let file = gh.to_vox();
+        let vox = dot_vox::load_bytes(&file).unwrap();
+        assert_eq!(vox.models.len(), 8);
+        assert_eq!(
+            vox.models
+                .iter()
+                .filter(|model| !model.voxels.is_empty())
+                .count(),
+            5
+        );
+
+        // colours are stored one up from the material, as index 0 is empty in magica voxel, and
+        // come back as the same material
+        let loaded = GH::from_vox(&file, &VoxelWorldLoadSettings::default()).unwrap();
+        assert_eq!(loaded.texture_size, 512);
+        let mut voxels: Vec<_> = loaded.voxels().collect();
+        voxels.sort_by_key(|(pos, _, _)| loaded.index(*pos));
+        let mut expected: Vec<_> = placed
+            .iter()
+            .map(|(pos, material)| (*pos, *material, Flags::COLLISION_FLAG))
+            .collect();
+        expected.sort_by_key(|(pos, _, _)| gh.index(*pos));
+        assert_eq!(voxels, expected);
+        // colours are stored as 8 bit srgb
+        for (loaded, saved) in loaded.pallete[0].iter().zip(gh.pallete[0]).take(3) {
+            assert!((loaded - saved).abs() < 0.01);
+        }
+    }
+}
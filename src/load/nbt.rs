@@ -0,0 +1,300 @@
+use bevy::utils::HashMap;
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+/// A decoded minecraft NBT tag.
+#[derive(Debug)]
+pub enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(HashMap<String, Nbt>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    /// Reads a gzipped or uncompressed NBT file, returning the name and value of the root tag.
+    pub fn from_file(file: &[u8]) -> Result<(String, Nbt), String> {
+        let mut decompressed = Vec::new();
+        let bytes = if file.starts_with(&[0x1f, 0x8b]) {
+            GzDecoder::new(file)
+                .read_to_end(&mut decompressed)
+                .map_err(|error| format!("Failed to decompress NBT: {}", error))?;
+            &decompressed
+        } else {
+            file
+        };
+
+        let mut reader = Reader(bytes);
+        let tag = reader.u8()?;
+        if tag != 10 {
+            return Err("The root NBT tag is not a compound".to_string());
+        }
+        let name = reader.string()?;
+        let root = reader.payload(tag, 0)?;
+        Ok((name, root))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(compound) => compound.get(key),
+            _ => None,
+        }
+    }
+
+    /// Any integer tag widened to an i64.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Nbt::Byte(value) => Some(*value as i64),
+            Nbt::Short(value) => Some(*value as i64),
+            Nbt::Int(value) => Some(*value as i64),
+            Nbt::Long(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Nbt::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Nbt]> {
+        match self {
+            Nbt::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Nbt>> {
+        match self {
+            Nbt::Compound(compound) => Some(compound),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[u8]> {
+        match self {
+            Nbt::ByteArray(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+/// Nesting limit so corrupt files cannot overflow the stack.
+const MAX_DEPTH: u32 = 512;
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < length {
+            return Err("Unexpected end of NBT data".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        let length = self.i32()?;
+        usize::try_from(length).map_err(|_| format!("Negative NBT length {}", length))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = u16::from_be_bytes(self.array()?) as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn payload(&mut self, tag: u8, depth: u32) -> Result<Nbt, String> {
+        if depth > MAX_DEPTH {
+            return Err("NBT data is nested too deeply".to_string());
+        }
+
+        Ok(match tag {
+            1 => Nbt::Byte(self.u8()? as i8),
+            2 => Nbt::Short(i16::from_be_bytes(self.array()?)),
+            3 => Nbt::Int(self.i32()?),
+            4 => Nbt::Long(self.i64()?),
+            5 => Nbt::Float(f32::from_be_bytes(self.array()?)),
+            6 => Nbt::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let length = self.length()?;
+                Nbt::ByteArray(self.take(length)?.to_vec())
+            }
+            8 => Nbt::String(self.string()?),
+            9 => {
+                let tag = self.u8()?;
+                let length = self.length()?;
+                let mut list = Vec::with_capacity(length.min(self.0.len()));
+                for _ in 0..length {
+                    list.push(self.payload(tag, depth + 1)?);
+                }
+                Nbt::List(list)
+            }
+            10 => {
+                let mut compound = HashMap::new();
+                loop {
+                    let tag = self.u8()?;
+                    if tag == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    compound.insert(name, self.payload(tag, depth + 1)?);
+                }
+                Nbt::Compound(compound)
+            }
+            11 => {
+                let length = self.length()?;
+                let mut array = Vec::with_capacity(length.min(self.0.len() / 4));
+                for _ in 0..length {
+                    array.push(self.i32()?);
+                }
+                Nbt::IntArray(array)
+            }
+            12 => {
+                let length = self.length()?;
+                let mut array = Vec::with_capacity(length.min(self.0.len() / 8));
+                for _ in 0..length {
+                    array.push(self.i64()?);
+                }
+                Nbt::LongArray(array)
+            }
+            tag => return Err(format!("Unknown NBT tag {}", tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    /// A named tag as it is stored in a compound.
+    fn named(tag: u8, name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        bytes.extend((name.len() as u16).to_be_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn file(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = named(10, "root", payload);
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn reads_every_tag() {
+        let mut list = vec![3];
+        list.extend(2i32.to_be_bytes());
+        list.extend(5i32.to_be_bytes());
+        list.extend((-6i32).to_be_bytes());
+        let mut nested = named(8, "name", &[0, 5, b's', b't', b'o', b'n', b'e']);
+        nested.push(0);
+
+        let mut payload = named(1, "byte", &[0xFF]);
+        payload.extend(named(2, "short", &300i16.to_be_bytes()));
+        payload.extend(named(4, "long", &(-7i64).to_be_bytes()));
+        payload.extend(named(5, "float", &1.5f32.to_be_bytes()));
+        payload.extend(named(7, "bytes", &[0, 0, 0, 2, 9, 8]));
+        payload.extend(named(9, "list", &list));
+        payload.extend(named(10, "compound", &nested));
+        payload.extend(named(
+            12,
+            "longs",
+            &[[0, 0, 0, 1].as_slice(), &[1; 8]].concat(),
+        ));
+
+        let (name, root) = Nbt::from_file(&file(&payload)).unwrap();
+        assert_eq!(name, "root");
+        assert_eq!(root.get("byte").and_then(Nbt::as_int), Some(-1));
+        assert_eq!(root.get("short").and_then(Nbt::as_int), Some(300));
+        assert_eq!(root.get("long").and_then(Nbt::as_int), Some(-7));
+        assert!(matches!(root.get("float"), Some(Nbt::Float(value)) if *value == 1.5));
+        assert_eq!(
+            root.get("bytes").and_then(Nbt::as_byte_array),
+            Some([9, 8].as_slice())
+        );
+        let list = root.get("list").and_then(Nbt::as_list).unwrap();
+        let list: Vec<_> = list.iter().filter_map(Nbt::as_int).collect();
+        assert_eq!(list, [5, -6]);
+        let nested = root.get("compound").unwrap();
+        assert_eq!(nested.get("name").and_then(Nbt::as_str), Some("stone"));
+        assert!(matches!(root.get("longs"), Some(Nbt::LongArray(longs)) if longs.len() == 1));
+    }
+
+    #[test]
+    fn reads_gzipped_files() {
+        let bytes = file(&named(3, "int", &42i32.to_be_bytes()));
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let (_, root) = Nbt::from_file(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(root.get("int").and_then(Nbt::as_int), Some(42));
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let bytes = file(&named(3, "int", &42i32.to_be_bytes()));
+        for length in 0..bytes.len() {
+            assert!(Nbt::from_file(&bytes[..length]).is_err(), "{}", length);
+        }
+        assert!(Nbt::from_file(&[0x1f, 0x8b, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        // root is not a compound
+        assert!(Nbt::from_file(&named(3, "int", &42i32.to_be_bytes())).is_err());
+        // unknown tag
+        assert!(Nbt::from_file(&file(&named(13, "tag", &[]))).is_err());
+        // negative and oversized lengths
+        assert!(Nbt::from_file(&file(&named(7, "bytes", &(-1i32).to_be_bytes()))).is_err());
+        assert!(Nbt::from_file(&file(&named(11, "ints", &i32::MAX.to_be_bytes()))).is_err());
+        let list = [[1].as_slice(), &i32::MAX.to_be_bytes()].concat();
+        assert!(Nbt::from_file(&file(&named(9, "list", &list))).is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        // lists holding a single list, the innermost holds a byte
+        let nested = |depth: u32| {
+            let mut payload = Vec::new();
+            for _ in 0..depth {
+                payload.extend([9, 0, 0, 0, 1]);
+            }
+            payload.extend([1, 0, 0, 0, 1, 7]);
+            file(&named(9, "list", &payload))
+        };
+        assert!(Nbt::from_file(&nested(16)).is_ok());
+        assert!(Nbt::from_file(&nested(MAX_DEPTH)).is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use super::{heightmap::pixel, VoxelWorldLoadError};
+use crate::VoxelPalette;
+use bevy::{
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageType},
+};
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use std::io::Write;
+
+/// Palette files used by pixel art tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// Lospec `.hex`, one `rrggbb` colour per line.
+    Hex,
+    /// GIMP `.gpl`.
+    Gpl,
+    /// Adobe `.act`, 256 rgb triplets followed by an optional colour count.
+    Act,
+    /// A `.png` image read left to right, top to bottom, usually 256x1 like magica voxel exports.
+    Png,
+}
+
+impl PaletteFormat {
+    /// Picks the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "hex" => Some(Self::Hex),
+            "gpl" => Some(Self::Gpl),
+            "act" => Some(Self::Act),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+impl VoxelPalette {
+    /// Reads a palette file. Colours are given to materials in order starting from material 0,
+    /// the order .vox files store their palette in, and nothing is emissive.
+    pub fn from_palette_file(
+        bytes: &[u8],
+        format: PaletteFormat,
+    ) -> Result<Self, VoxelWorldLoadError> {
+        let mut palette = Self::default();
+        palette.import(bytes, format, false)?;
+        Ok(palette)
+    }
+
+    /// Replaces colours with the ones in a palette file, materials past the end of the file keep
+    /// their colour. With `keep_emission` materials that glowed keep glowing in their new colour,
+    /// otherwise nothing is emissive afterwards.
+    pub fn import(
+        &mut self,
+        bytes: &[u8],
+        format: PaletteFormat,
+        keep_emission: bool,
+    ) -> Result<(), VoxelWorldLoadError> {
+        let colours = match format {
+            PaletteFormat::Hex => parse_hex(bytes)?,
+            PaletteFormat::Gpl => parse_gpl(bytes)?,
+            PaletteFormat::Act => parse_act(bytes)?,
+            PaletteFormat::Png => parse_png(bytes)?,
+        };
+        if colours.len() > 256 {
+            return Err(format!("Palette has {} colours, at most 256 fit", colours.len()).into());
+        }
+
+        for (i, [r, g, b]) in colours.into_iter().enumerate() {
+            let srgb = Vec3::new(r as f32, g as f32, b as f32) / 255.0;
+            let linear = srgb.powf(2.2);
+            self.set_colour(i as u8, Color::rgb_linear(linear.x, linear.y, linear.z));
+        }
+        if !keep_emission {
+            for i in 0..=255 {
+                self.set_emission(i, 0.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all 256 colours as a palette file, emission is not saved.
+    pub fn export(&self, format: PaletteFormat) -> Vec<u8> {
+        let colours: Vec<[u8; 3]> = (0..=255)
+            .map(|i| {
+                let [r, g, b, _] = self.colour(i).as_linear_rgba_f32();
+                let srgb = Vec3::new(r, g, b).max(Vec3::ZERO).powf(1.0 / 2.2) * 255.0;
+                let srgb = srgb.round().min(Vec3::splat(255.0));
+                [srgb.x as u8, srgb.y as u8, srgb.z as u8]
+            })
+            .collect();
+
+        match format {
+            PaletteFormat::Hex => colours
+                .iter()
+                .map(|[r, g, b]| format!("{:02x}{:02x}{:02x}\n", r, g, b))
+                .collect::<String>()
+                .into_bytes(),
+            PaletteFormat::Gpl => {
+                let mut text = "GIMP Palette\nName: Voxel palette\nColumns: 16\n#\n".to_string();
+                for (i, [r, g, b]) in colours.iter().enumerate() {
+                    text += &format!("{:3} {:3} {:3}\tMaterial {}\n", r, g, b, i);
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::Act => {
+                let mut bytes: Vec<u8> = colours.concat();
+                // colour count and no transparent colour
+                bytes.extend(256u16.to_be_bytes());
+                bytes.extend(0xFFFFu16.to_be_bytes());
+                bytes
+            }
+            PaletteFormat::Png => encode_png(&colours, colours.len()),
+        }
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<&str, VoxelWorldLoadError> {
+    Ok(std::str::from_utf8(bytes).map_err(|error| format!("Palette is not text: {}", error))?)
+}
+
+fn parse_hex(bytes: &[u8]) -> Result<Vec<[u8; 3]>, VoxelWorldLoadError> {
+    let mut colours = Vec::new();
+    for (number, line) in text(bytes)?.lines().enumerate() {
+        let line = line.trim().trim_start_matches('#');
+        if line.is_empty() {
+            continue;
+        }
+
+        let channel = |i: usize| {
+            line.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        match (line.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => colours.push([r, g, b]),
+            _ => {
+                return Err(format!("Invalid colour on line {}: {}", number + 1, line).into());
+            }
+        }
+    }
+    Ok(colours)
+}
+
+fn parse_gpl(bytes: &[u8]) -> Result<Vec<[u8; 3]>, VoxelWorldLoadError> {
+    let mut lines = text(bytes)?.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("GIMP Palette") {
+        return Err("Not a GIMP palette".to_string().into());
+    }
+
+    let mut colours = Vec::new();
+    for (number, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        // anything after the three channels is the colour's name
+        let mut channels = line.split_whitespace().map(|c| c.parse::<u8>().ok());
+        match (channels.next(), channels.next(), channels.next()) {
+            (Some(Some(r)), Some(Some(g)), Some(Some(b))) => colours.push([r, g, b]),
+            _ => {
+                return Err(format!("Invalid colour on line {}: {}", number + 1, line).into());
+            }
+        }
+    }
+    Ok(colours)
+}
+
+fn parse_act(bytes: &[u8]) -> Result<Vec<[u8; 3]>, VoxelWorldLoadError> {
+    if bytes.len() < 768 {
+        return Err(format!("Adobe colour table is {} bytes, expected 768", bytes.len()).into());
+    }
+
+    // files with a count at the end only use that many colours
+    let count = match bytes.get(768..770) {
+        Some(&[high, low]) if u16::from_be_bytes([high, low]) != 0 => {
+            (u16::from_be_bytes([high, low]) as usize).min(256)
+        }
+        _ => 256,
+    };
+    Ok(bytes[..count * 3]
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect())
+}
+
+fn parse_png(bytes: &[u8]) -> Result<Vec<[u8; 3]>, VoxelWorldLoadError> {
+    let image = Image::from_buffer(
+        bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+    )
+    .map_err(|error| format!("Failed to decode image: {}", error))?;
+
+    let width = image.texture_descriptor.size.width as usize;
+    let height = image.texture_descriptor.size.height as usize;
+    Ok((0..width * height)
+        .map(|i| {
+            let colour = (pixel(&image, i % width, i / width) * 255.0).round();
+            [colour.x as u8, colour.y as u8, colour.z as u8]
+        })
+        .collect())
+}
+
+/// An 8 bit rgb png `width` pixels wide, `colours` are the rows one after another.
+pub(super) fn encode_png(colours: &[[u8; 3]], width: usize) -> Vec<u8> {
+    fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(kind);
+        png.extend(data);
+        png.extend(crc.sum().to_be_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend(((colours.len() / width) as u32).to_be_bytes());
+    // bit depth, rgb, deflate, no filtering and no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    // each row starts with its filter type
+    let mut rows = Vec::new();
+    for row in colours.chunks_exact(width) {
+        rows.push(0);
+        rows.extend(row.concat());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&rows).unwrap();
+    let data = encoder.finish().unwrap();
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &data);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A palette with a different srgb colour for every material.
+    fn palette() -> VoxelPalette {
+        let mut palette = VoxelPalette::default();
+        for i in 0..=255u8 {
+            let srgb = Vec3::new(i as f32, 255.0 - i as f32, (i as f32 * 7.0) % 256.0) / 255.0;
+            let linear = srgb.powf(2.2);
+            palette.set_colour(i, Color::rgb_linear(linear.x, linear.y, linear.z));
+        }
+        palette
+    }
+
+    fn assert_colours_eq(a: &VoxelPalette, b: &VoxelPalette) {
+        for i in 0..=255 {
+            let a = Vec4::from(a.colour(i).as_linear_rgba_f32());
+            let b = Vec4::from(b.colour(i).as_linear_rgba_f32());
+            assert!(a.abs_diff_eq(b, 1e-5), "material {}: {} != {}", i, a, b);
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let palette = palette();
+        for format in [
+            PaletteFormat::Hex,
+            PaletteFormat::Gpl,
+            PaletteFormat::Act,
+            PaletteFormat::Png,
+        ] {
+            let file = palette.export(format);
+            let imported = VoxelPalette::from_palette_file(&file, format).unwrap();
+            assert_colours_eq(&imported, &palette);
+        }
+    }
+
+    #[test]
+    fn colours_are_srgb_in_files() {
+        let imported = VoxelPalette::from_palette_file(b"#804020\n", PaletteFormat::Hex).unwrap();
+        let [r, g, b, _] = imported.colour(0).as_linear_rgba_f32();
+        let expected = Vec3::new(128.0, 64.0, 32.0) / 255.0;
+        assert!(Vec3::new(r, g, b).abs_diff_eq(expected.powf(2.2), 1e-6));
+
+        let mut palette = VoxelPalette::default();
+        palette.set_colour(0, Color::rgb_linear(0.5, 0.25, 0.0));
+        let file = String::from_utf8(palette.export(PaletteFormat::Hex)).unwrap();
+        let srgb = [0.5f32, 0.25].map(|c| (c.powf(1.0 / 2.2) * 255.0).round() as u8);
+        let expected = format!("{:02x}{:02x}00", srgb[0], srgb[1]);
+        assert_eq!(file.lines().next(), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn act_colour_count_limits_the_colours() {
+        let mut file = vec![255; 768];
+        file.extend(2u16.to_be_bytes());
+        let mut palette = VoxelPalette::default();
+        palette.import(&file, PaletteFormat::Act, false).unwrap();
+        assert_eq!(palette.colour(1), Color::rgb_linear(1.0, 1.0, 1.0));
+        // past the count keeps its colour
+        assert_eq!(palette.colour(2), Color::rgb_linear(0.0, 0.0, 0.0));
+
+        // a count of 0 means every colour
+        file[768..].copy_from_slice(&0u16.to_be_bytes());
+        palette.import(&file, PaletteFormat::Act, false).unwrap();
+        assert_eq!(palette.colour(255), Color::rgb_linear(1.0, 1.0, 1.0));
+
+        assert!(VoxelPalette::from_palette_file(&file[..767], PaletteFormat::Act).is_err());
+    }
+
+    #[test]
+    fn too_many_colours_are_an_error() {
+        let file = "ffffff\n".repeat(257);
+        assert!(VoxelPalette::from_palette_file(file.as_bytes(), PaletteFormat::Hex).is_err());
+        let file = "ffffff\n".repeat(256);
+        assert!(VoxelPalette::from_palette_file(file.as_bytes(), PaletteFormat::Hex).is_ok());
+    }
+
+    #[test]
+    fn invalid_files_are_errors() {
+        for (file, format) in [
+            (b"fffff\n".as_slice(), PaletteFormat::Hex),
+            (b"Not a palette\n", PaletteFormat::Gpl),
+            (b"GIMP Palette\n255 0\n", PaletteFormat::Gpl),
+            (b"\x89PNG", PaletteFormat::Png),
+        ] {
+            assert!(VoxelPalette::from_palette_file(file, format).is_err());
+        }
+    }
+
+    #[test]
+    fn keep_emission_keeps_materials_glowing() {
+        let mut palette = palette();
+        palette.set_emission(3, 2.0);
+        let file = b"000000\n111111\n222222\nff0000\n";
+
+        let mut kept = palette.clone();
+        kept.import(file, PaletteFormat::Hex, true).unwrap();
+        assert_eq!(kept.emission(3), 2.0);
+        assert_eq!(kept.colour(3), Color::rgb_linear(1.0, 0.0, 0.0));
+
+        let mut cleared = palette;
+        cleared.import(file, PaletteFormat::Hex, false).unwrap();
+        assert_eq!(cleared.emission(3), 0.0);
+    }
+
+    #[test]
+    fn formats_come_from_extensions() {
+        assert_eq!(
+            PaletteFormat::from_path("palettes/db32.GPL"),
+            Some(PaletteFormat::Gpl)
+        );
+        assert_eq!(PaletteFormat::from_path("a.hex"), Some(PaletteFormat::Hex));
+        assert_eq!(PaletteFormat::from_path("a.txt"), None);
+        assert_eq!(PaletteFormat::from_path("act"), None);
+    }
+}
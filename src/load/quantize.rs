@@ -0,0 +1,263 @@
+use super::{Pallete, VoxelWorldLoadError, GH};
+use crate::{Flags, VoxelWorldLoadSettings};
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+
+/// How far a true colour source had to move to fit in the 255 palette entries, distances are
+/// in srgb units between 0 and 255 per channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuantizationReport {
+    pub source_colours: usize,
+    pub palette_colours: usize,
+    /// Average distance of every voxel from its palette colour.
+    pub mean_error: f32,
+    pub max_error: f32,
+}
+
+impl std::fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Quantized {} colours to {}, mean error {:.2}, max error {:.2}",
+            self.source_colours, self.palette_colours, self.mean_error, self.max_error
+        )
+    }
+}
+
+/// Builds a median cut palette of at most `max_colours` colours (and never more than 255),
+/// returning the palette entries and the index of the entry that each colour maps to.
+pub fn median_cut(
+    colours: &[[u8; 3]],
+    max_colours: usize,
+) -> (Vec<[u8; 3]>, Vec<u8>, QuantizationReport) {
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for colour in colours {
+        *counts.entry(*colour).or_default() += 1;
+    }
+    let mut unique: Vec<([u8; 3], u32)> = counts.into_iter().collect();
+    unique.sort_unstable();
+
+    // keep splitting the box with the widest weighted channel at its median
+    let mut boxes = vec![scored(unique)];
+    while boxes.len() < max_colours.min(255) {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (_, _, score))| *score)
+            .map(|(i, (_, channel, score))| (i, *channel, *score));
+        let Some((i, channel, score)) = widest else {
+            break;
+        };
+        if score == 0 {
+            break;
+        }
+
+        let (mut colours, _, _) = boxes.swap_remove(i);
+        colours.sort_unstable_by_key(|(colour, _)| (colour[channel], *colour));
+        let total: u64 = colours.iter().map(|(_, count)| *count as u64).sum();
+        let mut seen = 0;
+        let mut split = 1;
+        for (index, (_, count)) in colours.iter().enumerate() {
+            seen += *count as u64;
+            if seen * 2 >= total {
+                split = (index + 1).clamp(1, colours.len() - 1);
+                break;
+            }
+        }
+        let rest = colours.split_off(split);
+        boxes.push(scored(colours));
+        boxes.push(scored(rest));
+    }
+    let boxes: Vec<_> = boxes
+        .into_iter()
+        .map(|(colours, _, _)| colours)
+        .filter(|colours| !colours.is_empty())
+        .collect();
+
+    let palette: Vec<[u8; 3]> = boxes
+        .iter()
+        .map(|colours| {
+            let mut sum = DVec3::ZERO;
+            let mut weight = 0;
+            for (colour, count) in colours {
+                let colour = DVec3::new(colour[0] as f64, colour[1] as f64, colour[2] as f64);
+                sum += colour * *count as f64;
+                weight += *count as u64;
+            }
+            let average = (sum / weight as f64).round();
+            [average.x as u8, average.y as u8, average.z as u8]
+        })
+        .collect();
+
+    // map every colour to its nearest entry rather than its box
+    let mut nearest: HashMap<[u8; 3], (u8, f32)> = HashMap::new();
+    let mut report = QuantizationReport {
+        source_colours: boxes.iter().map(Vec::len).sum(),
+        palette_colours: palette.len(),
+        ..default()
+    };
+    let mut indices = Vec::with_capacity(colours.len());
+    let mut total_error = 0.0;
+    for colour in colours {
+        let (index, error) = *nearest.entry(*colour).or_insert_with(|| {
+            palette
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (i as u8, distance(*entry, *colour)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap_or((0, 0.0))
+        });
+        indices.push(index);
+        total_error += error as f64;
+        report.max_error = report.max_error.max(error);
+    }
+    if !colours.is_empty() {
+        report.mean_error = (total_error / colours.len() as f64) as f32;
+    }
+
+    (palette, indices, report)
+}
+
+// a box with its widest channel and how worth splitting it is
+fn scored(colours: Vec<([u8; 3], u32)>) -> (Vec<([u8; 3], u32)>, usize, u64) {
+    if colours.len() < 2 {
+        return (colours, 0, 0);
+    }
+    let (channel, range) = widest_channel(&colours);
+    let weight: u64 = colours.iter().map(|(_, count)| *count as u64).sum();
+    (colours, channel, range as u64 * weight)
+}
+
+fn widest_channel(colours: &[([u8; 3], u32)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = colours.iter().map(|(colour, _)| colour[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+    let a = Vec3::new(a[0] as f32, a[1] as f32, a[2] as f32);
+    let b = Vec3::new(b[0] as f32, b[1] as f32, b[2] as f32);
+    a.distance(b)
+}
+
+/// Turns srgb palette entries into a `Pallete` starting at material 1.
+pub fn srgb_pallete(colours: &[[u8; 3]]) -> Pallete {
+    let mut pallete = Pallete([[0.0; 4]; 256]);
+    for (i, colour) in colours.iter().take(255).enumerate() {
+        let colour = Vec3::new(colour[0] as f32, colour[1] as f32, colour[2] as f32) / 255.0;
+        pallete[i + 1] = colour.powf(2.2).extend(0.0).to_array();
+    }
+    pallete
+}
+
+impl GH {
+    /// Places true colour voxels in voxel space (y up) into the smallest world that fits them,
+    /// every voxel gets `Flags::COLLISION_FLAG`.
+    pub fn from_coloured_voxels(
+        voxels: &[(IVec3, [u8; 3])],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<(GH, QuantizationReport), VoxelWorldLoadError> {
+        let mut min = IVec3::splat(i32::MAX);
+        let mut max = IVec3::splat(i32::MIN);
+        for (pos, _) in voxels {
+            min = min.min(*pos);
+            max = max.max(*pos);
+        }
+        if voxels.is_empty() {
+            min = IVec3::ZERO;
+            max = IVec3::ZERO;
+        }
+
+        // positions can span more than an i32
+        let extent = [0, 1, 2].map(|axis| max[axis] as i64 - min[axis] as i64 + 1);
+        let model_size = UVec3::from_array(extent.map(|length| length.min(u32::MAX as i64) as u32));
+        let size = GH::size_to_fit(model_size)?;
+        let offset = settings.anchor.offset(model_size, size);
+
+        let colours: Vec<[u8; 3]> = voxels.iter().map(|(_, colour)| *colour).collect();
+        let (palette, indices, report) = median_cut(&colours, 255);
+
+        let mut gh = GH::empty(size);
+        gh.pallete = srgb_pallete(&palette);
+        for ((pos, _), index) in voxels.iter().zip(indices) {
+            let pos = offset + (*pos - min).as_uvec3();
+            gh.set_voxel(pos, index + 1, Flags::COLLISION_FLAG);
+        }
+
+        Ok((gh, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::Anchor;
+
+    #[test]
+    fn few_colours_are_kept_exactly() {
+        let colours = [[255, 0, 0], [0, 0, 255], [255, 0, 0], [10, 200, 30]];
+        let (palette, indices, report) = median_cut(&colours, 255);
+        assert_eq!(palette.len(), 3);
+        for (colour, index) in colours.iter().zip(&indices) {
+            assert_eq!(palette[*index as usize], *colour);
+        }
+        assert_eq!(report.source_colours, 3);
+        assert_eq!(report.max_error, 0.0);
+    }
+
+    #[test]
+    fn colours_are_reduced_to_the_limit() {
+        let colours: Vec<_> = (0..=255).map(|i| [i as u8, 255 - i as u8, 0]).collect();
+        let (palette, indices, report) = median_cut(&colours, 16);
+        assert_eq!(palette.len(), 16);
+        assert!(indices
+            .iter()
+            .all(|index| (*index as usize) < palette.len()));
+        assert!(report.max_error > 0.0 && report.max_error < 16.0);
+    }
+
+    #[test]
+    fn common_colours_do_not_overflow() {
+        // more voxels of one colour than fit in a u32 once multiplied by the colour
+        let mut colours = vec![[255, 254, 253]; (1 << 24) + (1 << 20)];
+        colours.push([0, 0, 0]);
+        let (palette, _, _) = median_cut(&colours, 1);
+        // the average is weighted by how often each colour is used
+        assert_eq!(palette, [[255, 254, 253]]);
+    }
+
+    #[test]
+    fn places_voxels_in_the_smallest_world() {
+        let voxels = [
+            (IVec3::new(-5, 10, 3), [255, 0, 0]),
+            (IVec3::new(-4, 11, 3), [0, 255, 0]),
+        ];
+        let settings = VoxelWorldLoadSettings {
+            anchor: Anchor::Corner,
+            ..default()
+        };
+        let (gh, _) = GH::from_coloured_voxels(&voxels, &settings).unwrap();
+        assert_eq!(gh.texture_size, crate::load::MIN_TEXTURE_SIZE);
+        let (red, flags) = gh.get_voxel(UVec3::new(0, 0, 0));
+        let (green, _) = gh.get_voxel(UVec3::new(1, 1, 0));
+        assert_eq!(flags, Flags::COLLISION_FLAG);
+        assert_eq!(gh.pallete[red as usize], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(gh.pallete[green as usize], [0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn models_spanning_every_position_are_errors() {
+        let voxels = [
+            (IVec3::splat(i32::MIN), [255, 0, 0]),
+            (IVec3::splat(i32::MAX), [0, 255, 0]),
+        ];
+        assert!(matches!(
+            GH::from_coloured_voxels(&voxels, &default()),
+            Err(VoxelWorldLoadError::ModelTooLarge(_))
+        ));
+    }
+}
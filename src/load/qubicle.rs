@@ -0,0 +1,254 @@
+use super::{reader::Reader, QuantizationReport, VoxelWorldLoadError, GH, MAX_TEXTURE_SIZE};
+use crate::VoxelWorldLoadSettings;
+use bevy::prelude::*;
+
+/// Every qubicle binary file starts with version 1.1.0.0.
+pub const QB_MAGIC: &[u8; 4] = &[1, 1, 0, 0];
+
+// run length encoding markers in compressed files
+const CODE_FLAG: u32 = 2;
+const NEXT_SLICE_FLAG: u32 = 6;
+
+// voxels in the largest world
+const MAX_VOXELS: u64 = (MAX_TEXTURE_SIZE as u64).pow(3);
+
+impl GH {
+    /// Loads a qubicle binary `.qb` file, merging all matrices at their positions.
+    pub fn from_qb(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<(GH, QuantizationReport), VoxelWorldLoadError> {
+        let mut reader = Reader::new(file, "qubicle file");
+        if reader.take(4)? != QB_MAGIC {
+            return Err("Not a qubicle file".into());
+        }
+        let bgra = reader.u32()? == 1;
+        let right_handed = reader.u32()? == 1;
+        let compressed = reader.u32()? != 0;
+        let _visibility_mask_encoded = reader.u32()?;
+        let matrix_count = reader.u32()?;
+
+        let mut voxels = Vec::new();
+        let mut total_voxels = 0;
+        for _ in 0..matrix_count {
+            let name_length = reader.u8()? as usize;
+            reader.take(name_length)?;
+            let size = UVec3::new(reader.u32()?, reader.u32()?, reader.u32()?);
+            let position = IVec3::new(reader.i32()?, reader.i32()?, reader.i32()?);
+            if size.cmpgt(UVec3::splat(MAX_TEXTURE_SIZE)).any() {
+                return Err(VoxelWorldLoadError::ModelTooLarge(size));
+            }
+            // negating z and adding the size to the position have to stay in range
+            for axis in 0..3 {
+                let end = position[axis] as i64 + size[axis] as i64;
+                if position[axis] == i32::MIN || end > i32::MAX as i64 {
+                    return Err("Qubicle matrix position is out of range".into());
+                }
+            }
+            // a run can repeat a voxel billions of times, so check the sizes before decoding
+            let voxel_count = size.x as u64 * size.y as u64 * size.z as u64;
+            total_voxels += voxel_count;
+            if total_voxels > MAX_VOXELS {
+                return Err("Qubicle matrices have more voxels than fit in a world".into());
+            }
+            if !compressed && voxel_count * 4 > reader.bytes.len() as u64 {
+                return Err("Qubicle matrix is larger than the file".into());
+            }
+
+            let mut push = |index: u64, value: u32| {
+                let [r, g, b, a] = value.to_le_bytes();
+                // with a visibility mask alpha holds the visible faces, still 0 when empty
+                if a == 0 {
+                    return;
+                }
+                let [x, y] = [size.x as u64, size.y as u64];
+                let local = UVec3::new(
+                    (index % x) as u32,
+                    (index / x % y) as u32,
+                    (index / (x * y)) as u32,
+                );
+                let mut pos = position + local.as_ivec3();
+                if !right_handed {
+                    pos.z = -pos.z;
+                }
+                let colour = if bgra { [b, g, r] } else { [r, g, b] };
+                voxels.push((pos, colour));
+            };
+
+            if compressed {
+                // slices along z, each a run length encoded list of x then y
+                for z in 0..size.z {
+                    let mut index = 0;
+                    loop {
+                        let data = reader.u32()?;
+                        if data == NEXT_SLICE_FLAG {
+                            break;
+                        }
+                        let (count, value) = if data == CODE_FLAG {
+                            (reader.u32()?, reader.u32()?)
+                        } else {
+                            (1, data)
+                        };
+                        for _ in 0..count {
+                            if index >= size.x * size.y {
+                                return Err("Qubicle slice has too many voxels".into());
+                            }
+                            push(
+                                z as u64 * size.x as u64 * size.y as u64 + index as u64,
+                                value,
+                            );
+                            index += 1;
+                        }
+                    }
+                }
+            } else {
+                for index in 0..voxel_count {
+                    push(index, reader.u32()?);
+                }
+            }
+        }
+
+        GH::from_coloured_voxels(&voxels, settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::Anchor;
+
+    fn header(compressed: bool, matrix_count: u32) -> Vec<u8> {
+        let mut file = QB_MAGIC.to_vec();
+        // rgba, right handed
+        for value in [0, 1, compressed as u32, 0, matrix_count] {
+            file.extend(value.to_le_bytes());
+        }
+        file
+    }
+
+    fn matrix(file: &mut Vec<u8>, size: [u32; 3], position: [i32; 3]) {
+        file.extend([1, b'm']);
+        for value in size {
+            file.extend(value.to_le_bytes());
+        }
+        for value in position {
+            file.extend(value.to_le_bytes());
+        }
+    }
+
+    fn settings() -> VoxelWorldLoadSettings {
+        VoxelWorldLoadSettings {
+            anchor: Anchor::Corner,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn loads_uncompressed_matrices() {
+        let mut file = header(false, 1);
+        matrix(&mut file, [2, 1, 2], [0, 0, 0]);
+        for rgba in [[255, 0, 0, 255], [0; 4], [0; 4], [0, 0, 255, 255]] {
+            file.extend(rgba);
+        }
+
+        let (gh, report) = GH::from_qb(&file, &settings()).unwrap();
+        assert_eq!(report.source_colours, 2);
+        let voxels: Vec<_> = gh.voxels().collect();
+        assert_eq!(voxels.len(), 2);
+        let (red, _) = gh.get_voxel(UVec3::new(0, 0, 0));
+        let (blue, _) = gh.get_voxel(UVec3::new(1, 0, 1));
+        assert_eq!(gh.pallete[red as usize], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(gh.pallete[blue as usize], [0.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn loads_compressed_matrices() {
+        let mut file = header(true, 1);
+        matrix(&mut file, [2, 2, 2], [0, 0, 0]);
+        // a run of three voxels then an empty one, and an empty slice
+        for value in [
+            CODE_FLAG,
+            3,
+            0xFF00FF00,
+            0,
+            NEXT_SLICE_FLAG,
+            NEXT_SLICE_FLAG,
+        ] {
+            file.extend(value.to_le_bytes());
+        }
+
+        let (gh, _) = GH::from_qb(&file, &settings()).unwrap();
+        let voxels: Vec<_> = gh.voxels().map(|(pos, _, _)| pos).collect();
+        assert_eq!(voxels.len(), 3);
+        assert!(!voxels.contains(&UVec3::new(1, 1, 0)));
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let mut file = header(false, 1);
+        matrix(&mut file, [2, 1, 2], [0, 0, 0]);
+        file.extend([255; 16]);
+        for length in 0..file.len() {
+            assert!(
+                GH::from_qb(&file[..length], &settings()).is_err(),
+                "{}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_headers_are_errors() {
+        let mut file = header(false, 1);
+        matrix(&mut file, [MAX_TEXTURE_SIZE + 1, 1, 1], [0, 0, 0]);
+        assert!(matches!(
+            GH::from_qb(&file, &settings()),
+            Err(VoxelWorldLoadError::ModelTooLarge(_))
+        ));
+
+        let mut file = header(false, 1);
+        matrix(&mut file, [4, 4, 4], [0, 0, 0]);
+        assert!(GH::from_qb(&file, &settings()).is_err());
+
+        for position in [[i32::MAX - 1, 0, 0], [0, 0, i32::MIN]] {
+            let mut file = header(true, 1);
+            matrix(&mut file, [2, 2, 2], position);
+            for value in [0xFFFFFFFF, NEXT_SLICE_FLAG, NEXT_SLICE_FLAG] {
+                file.extend(value.to_le_bytes());
+            }
+            assert!(GH::from_qb(&file, &settings()).is_err());
+        }
+    }
+
+    #[test]
+    fn runs_past_the_matrix_are_errors() {
+        let mut file = header(true, 1);
+        matrix(&mut file, [16, 16, 1], [0, 0, 0]);
+        for value in [CODE_FLAG, u32::MAX, 0xFFFFFFFF, NEXT_SLICE_FLAG] {
+            file.extend(value.to_le_bytes());
+        }
+        assert!(GH::from_qb(&file, &settings()).is_err());
+    }
+
+    #[test]
+    fn matrices_larger_than_a_world_are_errors() {
+        // a matrix the size of the largest world with every slice empty
+        let largest = |file: &mut Vec<u8>| {
+            matrix(file, [MAX_TEXTURE_SIZE; 3], [0, 0, 0]);
+            for _ in 0..MAX_TEXTURE_SIZE {
+                file.extend(NEXT_SLICE_FLAG.to_le_bytes());
+            }
+        };
+        let mut file = header(true, 1);
+        largest(&mut file);
+        assert!(GH::from_qb(&file, &settings()).is_ok());
+
+        let mut file = header(true, 2);
+        largest(&mut file);
+        matrix(&mut file, [1, 1, 1], [0, 0, 0]);
+        for value in [0xFFFFFFFF, NEXT_SLICE_FLAG] {
+            file.extend(value.to_le_bytes());
+        }
+        assert!(GH::from_qb(&file, &settings()).is_err());
+    }
+}
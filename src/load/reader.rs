@@ -0,0 +1,66 @@
+/// Reads little endian values from a file in memory.
+pub struct Reader<'a> {
+    pub bytes: &'a [u8],
+    /// Name of the format for error messages.
+    format: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], format: &'static str) -> Self {
+        Self { bytes, format }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < length {
+            return Err(format!("Unexpected end of {}", self.format));
+        }
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    pub fn leb128(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!(
+            "Invalid variable length integer in {}",
+            self.format
+        ))
+    }
+
+    /// A line of text without the line ending.
+    pub fn line(&mut self) -> Result<&'a str, String> {
+        let length = self
+            .bytes
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| format!("Unexpected end of {}", self.format))?;
+        let line = self.take(length + 1)?;
+        std::str::from_utf8(&line[..length])
+            .map(|line| line.trim_end_matches('\r'))
+            .map_err(|_| format!("Invalid text in {}", self.format))
+    }
+}
@@ -0,0 +1,117 @@
+use super::{read_file, VoxelWorldLoadError, GH, MIN_TEXTURE_SIZE};
+use bevy::prelude::*;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+pub const REGION_EXTENSION: &str = "vwr";
+
+// Every region is a zlib compressed snapshot of a world of `region_size`, see snapshot.rs.
+
+/// A directory of fixed size regions of an endless world, named `x.y.z.vwr` by region coordinate.
+/// Regions are written to a temporary file that replaces the old one once it is complete, so a
+/// crash while saving leaves the previous save in place.
+#[derive(Clone, Debug)]
+pub struct RegionStore {
+    pub directory: PathBuf,
+    /// Side length of a region in voxels, a power of two between `MIN_TEXTURE_SIZE` and the size of
+    /// the world it is loaded into.
+    pub region_size: u32,
+}
+
+impl RegionStore {
+    pub fn new(directory: impl Into<PathBuf>, region_size: u32) -> Self {
+        Self {
+            directory: directory.into(),
+            region_size,
+        }
+    }
+
+    pub fn check_region_size(&self, texture_size: u32) -> Result<(), VoxelWorldLoadError> {
+        if self.region_size.is_power_of_two()
+            && (MIN_TEXTURE_SIZE..=texture_size).contains(&self.region_size)
+        {
+            Ok(())
+        } else {
+            Err(VoxelWorldLoadError::InvalidSize(self.region_size))
+        }
+    }
+
+    /// Streamed worlds can only move by whole regions, or the regions they are saved to would not
+    /// line up with the ones they were loaded from.
+    pub fn check_shift(&self, shift: IVec3) -> Result<(), VoxelWorldLoadError> {
+        if shift % self.region_size as i32 == IVec3::ZERO {
+            Ok(())
+        } else {
+            Err(VoxelWorldLoadError::UnalignedResize(
+                shift,
+                self.region_size,
+            ))
+        }
+    }
+
+    /// Region coordinate of the region with the voxel at `pos` of the endless world in it.
+    pub fn region_at(&self, pos: IVec3) -> IVec3 {
+        let region_size = self.region_size as i32;
+        IVec3::new(
+            pos.x.div_euclid(region_size),
+            pos.y.div_euclid(region_size),
+            pos.z.div_euclid(region_size),
+        )
+    }
+
+    /// Path of the file the region at `region`, in region coordinates, is saved to.
+    pub fn region_path(&self, region: IVec3) -> PathBuf {
+        self.directory.join(format!(
+            "{}.{}.{}.{}",
+            region.x, region.y, region.z, REGION_EXTENSION
+        ))
+    }
+
+    /// The region at `region`, or `None` if it has never been saved.
+    pub fn load(&self, region: IVec3) -> Result<Option<GH>, VoxelWorldLoadError> {
+        let path = self.region_path(region).display().to_string();
+        let file = match read_file(&path) {
+            Ok(file) => file,
+            Err(VoxelWorldLoadError::NotFound(_)) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        let mut snapshot = Vec::new();
+        ZlibDecoder::new(file.as_slice())
+            .read_to_end(&mut snapshot)
+            .map_err(|error| format!("region {} is not compressed: {}", path, error))?;
+        let gh = GH::from_snapshot(&snapshot)?;
+        if gh.texture_size != self.region_size {
+            return Err(format!(
+                "region {} is {} voxels across instead of {}",
+                path, gh.texture_size, self.region_size
+            )
+            .into());
+        }
+        Ok(Some(gh))
+    }
+
+    /// Saves `gh`, a world of `region_size`, as the region at `region`.
+    pub fn save(&self, region: IVec3, gh: &GH) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&gh.to_snapshot())?;
+        let data = encoder.finish()?;
+
+        let path = self.region_path(region);
+        let temporary = path.with_extension(format!("{}.tmp", REGION_EXTENSION));
+        let mut file = File::create(&temporary)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &path)?;
+
+        // the rename only survives a crash once the directory has been written too
+        #[cfg(unix)]
+        File::open(&self.directory)?.sync_all()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,526 @@
+use super::{nbt::Nbt, Pallete, VoxelWorldLoadError, GH};
+use crate::{Flags, VoxelWorldLoadSettings};
+use bevy::{prelude::*, utils::HashMap};
+
+/// What a minecraft block turns into when a schematic is loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct SchematicBlock {
+    pub colour: Color,
+    pub flags: u8,
+}
+
+/// Maps minecraft block names to voxels. Names are looked up with their namespace first
+/// (`minecraft:stone`) and then without it (`stone`), block states are ignored. Blocks mapped to
+/// `None` are left empty and blocks missing from the table use `unknown`.
+#[derive(Clone, Debug)]
+pub struct SchematicBlocks {
+    pub blocks: HashMap<String, Option<SchematicBlock>>,
+    pub unknown: Option<SchematicBlock>,
+}
+
+impl Default for SchematicBlocks {
+    fn default() -> Self {
+        let mut blocks = HashMap::new();
+        let mut solid = |name: &str, r: u8, g: u8, b: u8| {
+            let block = SchematicBlock {
+                colour: Color::rgb_u8(r, g, b),
+                flags: Flags::COLLISION_FLAG,
+            };
+            blocks.insert(name.to_string(), Some(block));
+        };
+
+        for (name, r, g, b) in [
+            ("stone", 125, 125, 125),
+            ("cobblestone", 118, 118, 118),
+            ("mossy_cobblestone", 110, 118, 95),
+            ("stone_bricks", 122, 121, 122),
+            ("mossy_stone_bricks", 115, 121, 105),
+            ("smooth_stone", 158, 158, 158),
+            ("granite", 149, 103, 85),
+            ("diorite", 188, 188, 188),
+            ("andesite", 136, 136, 136),
+            ("deepslate", 80, 80, 82),
+            ("cobbled_deepslate", 77, 77, 80),
+            ("bedrock", 85, 85, 85),
+            ("dirt", 134, 96, 67),
+            ("coarse_dirt", 119, 85, 59),
+            ("grass_block", 95, 159, 53),
+            ("podzol", 91, 63, 24),
+            ("mud", 60, 57, 60),
+            ("clay", 160, 166, 179),
+            ("sandstone", 216, 203, 155),
+            ("red_sandstone", 186, 99, 29),
+            ("bricks", 150, 97, 83),
+            ("obsidian", 15, 10, 24),
+            ("netherrack", 97, 38, 38),
+            ("nether_bricks", 44, 21, 26),
+            ("end_stone", 219, 222, 158),
+            ("quartz_block", 235, 229, 222),
+            ("prismarine", 99, 156, 151),
+            ("snow_block", 249, 254, 254),
+            ("ice", 145, 183, 253),
+            ("packed_ice", 141, 180, 250),
+            ("glass", 175, 213, 219),
+            ("bookshelf", 117, 94, 59),
+            ("hay_block", 166, 136, 38),
+            ("coal_ore", 105, 105, 105),
+            ("iron_ore", 136, 129, 122),
+            ("gold_ore", 143, 140, 125),
+            ("diamond_ore", 121, 141, 140),
+            ("iron_block", 220, 220, 220),
+            ("gold_block", 246, 208, 61),
+            ("diamond_block", 98, 237, 228),
+            ("glowstone", 171, 131, 84),
+            ("sea_lantern", 172, 199, 190),
+            ("crafting_table", 119, 73, 42),
+            ("furnace", 110, 110, 110),
+            ("chest", 162, 115, 48),
+        ] {
+            solid(name, r, g, b);
+        }
+
+        // every shape of a material gets the same colour
+        for (wood, r, g, b) in [
+            ("oak", 162, 130, 78),
+            ("spruce", 114, 84, 48),
+            ("birch", 192, 175, 121),
+            ("jungle", 160, 115, 80),
+            ("acacia", 168, 90, 50),
+            ("dark_oak", 66, 43, 20),
+            ("mangrove", 117, 54, 48),
+            ("cherry", 226, 178, 172),
+        ] {
+            for shape in ["planks", "stairs", "slab", "fence", "fence_gate", "door"] {
+                solid(&format!("{}_{}", wood, shape), r, g, b);
+            }
+            let [r, g, b] = [r, g, b].map(|channel: u8| (channel as u32 * 2 / 3) as u8);
+            solid(&format!("{}_log", wood), r, g, b);
+            solid(&format!("{}_wood", wood), r, g, b);
+            solid(&format!("{}_leaves", wood), 60, 110, 40);
+        }
+        for (stone, r, g, b) in [
+            ("stone", 125, 125, 125),
+            ("cobblestone", 118, 118, 118),
+            ("stone_brick", 122, 121, 122),
+            ("sandstone", 216, 203, 155),
+            ("brick", 150, 97, 83),
+            ("quartz", 235, 229, 222),
+        ] {
+            for shape in ["stairs", "slab", "wall"] {
+                solid(&format!("{}_{}", stone, shape), r, g, b);
+            }
+        }
+        for (colour, r, g, b) in [
+            ("white", 234, 236, 237),
+            ("orange", 241, 118, 20),
+            ("magenta", 190, 69, 180),
+            ("light_blue", 58, 175, 217),
+            ("yellow", 249, 198, 40),
+            ("lime", 112, 185, 26),
+            ("pink", 238, 141, 172),
+            ("gray", 63, 68, 72),
+            ("light_gray", 142, 142, 135),
+            ("cyan", 21, 138, 145),
+            ("purple", 122, 42, 173),
+            ("blue", 53, 57, 157),
+            ("brown", 114, 72, 41),
+            ("green", 85, 110, 28),
+            ("red", 161, 39, 35),
+            ("black", 21, 21, 26),
+        ] {
+            for block in ["wool", "concrete", "terracotta", "stained_glass", "carpet"] {
+                solid(&format!("{}_{}", colour, block), r, g, b);
+            }
+        }
+
+        for (name, r, g, b) in [
+            ("sand", 219, 207, 163),
+            ("red_sand", 190, 102, 33),
+            ("gravel", 131, 127, 126),
+        ] {
+            let block = SchematicBlock {
+                colour: Color::rgb_u8(r, g, b),
+                flags: Flags::COLLISION_FLAG | Flags::SAND_FLAG,
+            };
+            blocks.insert(name.to_string(), Some(block));
+        }
+
+        for (name, r, g, b) in [
+            ("water", 63, 118, 228),
+            ("lava", 207, 92, 20),
+            ("grass", 110, 160, 60),
+            ("tall_grass", 110, 160, 60),
+            ("fern", 100, 140, 60),
+            ("dandelion", 250, 230, 40),
+            ("poppy", 200, 30, 30),
+            ("torch", 255, 200, 80),
+            ("vine", 70, 110, 40),
+        ] {
+            let block = SchematicBlock {
+                colour: Color::rgb_u8(r, g, b),
+                flags: Flags::NONE,
+            };
+            blocks.insert(name.to_string(), Some(block));
+        }
+
+        for name in [
+            "air",
+            "cave_air",
+            "void_air",
+            "structure_void",
+            "barrier",
+            "light",
+        ] {
+            blocks.insert(name.to_string(), None);
+        }
+
+        Self {
+            blocks,
+            unknown: Some(SchematicBlock {
+                colour: Color::rgb_u8(200, 0, 200),
+                flags: Flags::COLLISION_FLAG,
+            }),
+        }
+    }
+}
+
+impl SchematicBlocks {
+    fn get(&self, block_state: &str) -> Option<SchematicBlock> {
+        let name = block_state.split('[').next().unwrap_or(block_state);
+        if let Some(block) = self.blocks.get(name) {
+            return *block;
+        }
+        let short_name = name.strip_prefix("minecraft:").unwrap_or(name);
+        match self.blocks.get(short_name) {
+            Some(block) => *block,
+            None => {
+                warn!("No voxel for minecraft block {}", name);
+                self.unknown
+            }
+        }
+    }
+}
+
+impl GH {
+    /// Loads a Sponge `.schem` (versions 1 to 3) or vanilla structure `.nbt` file.
+    pub fn from_schematic(
+        file: &[u8],
+        settings: &VoxelWorldLoadSettings,
+    ) -> Result<GH, VoxelWorldLoadError> {
+        let (_, root) = Nbt::from_file(file)?;
+
+        // sponge version 3 wraps everything in a Schematic compound
+        let root = root.get("Schematic").unwrap_or(&root);
+        let (size, block_states, blocks) = if root.get("Width").is_some() {
+            read_sponge(root)?
+        } else if root.get("size").is_some() {
+            read_structure(root)?
+        } else {
+            return Err("Not a sponge schematic or structure file".into());
+        };
+
+        // give every distinct colour a material
+        let mut pallete = Pallete([[0.0; 4]; 256]);
+        let mut colours: Vec<[u8; 3]> = Vec::new();
+        let mut voxels = Vec::with_capacity(block_states.len());
+        for block_state in &block_states {
+            let Some(block) = settings.schematic_blocks.get(block_state) else {
+                voxels.push(None);
+                continue;
+            };
+            let [r, g, b, _] = block.colour.as_rgba_f32();
+            let colour = [r, g, b].map(|channel| (channel * 255.0).round() as u8);
+            let material = match colours.iter().position(|other| *other == colour) {
+                Some(index) => index + 1,
+                None if colours.len() < 255 => {
+                    colours.push(colour);
+                    pallete[colours.len()] = [r.powf(2.2), g.powf(2.2), b.powf(2.2), 0.0];
+                    colours.len()
+                }
+                None => nearest_colour(&colours, colour) + 1,
+            };
+            voxels.push(Some((material as u8, block.flags)));
+        }
+
+        let texture_size = GH::size_to_fit(size)?;
+        let offset = settings.anchor.offset(size, texture_size);
+
+        let mut gh = GH::empty(texture_size);
+        gh.pallete = pallete;
+        for (pos, state) in blocks {
+            let voxel = voxels
+                .get(state)
+                .ok_or_else(|| format!("Block state {} is not in the palette", state))?;
+            if let Some((material, flags)) = voxel {
+                gh.set_voxel(offset + pos, *material, *flags);
+            }
+        }
+
+        Ok(gh)
+    }
+}
+
+type Blocks = (UVec3, Vec<String>, Vec<(UVec3, usize)>);
+
+fn read_sponge(root: &Nbt) -> Result<Blocks, VoxelWorldLoadError> {
+    let dimension = |key: &str| {
+        root.get(key)
+            .and_then(Nbt::as_int)
+            .map(|value| value as u16 as u32)
+            .ok_or_else(|| format!("Schematic has no {}", key))
+    };
+    let size = UVec3::new(
+        dimension("Width")?,
+        dimension("Height")?,
+        dimension("Length")?,
+    );
+
+    let (palette, data) = match root.get("Blocks") {
+        Some(blocks) => (blocks.get("Palette"), blocks.get("Data")),
+        None => (root.get("Palette"), root.get("BlockData")),
+    };
+    let palette = palette
+        .and_then(Nbt::as_compound)
+        .ok_or("Schematic has no block palette")?;
+    let data = data
+        .and_then(Nbt::as_byte_array)
+        .ok_or("Schematic has no block data")?;
+
+    let mut block_states = vec![String::new(); palette.len()];
+    for (block_state, index) in palette {
+        let index = index
+            .as_int()
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index < block_states.len())
+            .ok_or_else(|| format!("Invalid palette index for {}", block_state))?;
+        block_states[index] = block_state.clone();
+    }
+
+    // indices are varints in x, then z, then y order
+    let mut blocks = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    for y in 0..size.y {
+        for z in 0..size.z {
+            for x in 0..size.x {
+                let mut index = 0;
+                for shift in (0..35).step_by(7) {
+                    let byte = *bytes.next().ok_or("Schematic block data is too short")?;
+                    index |= ((byte & 0x7F) as usize) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                blocks.push((UVec3::new(x, y, z), index));
+            }
+        }
+    }
+
+    Ok((size, block_states, blocks))
+}
+
+fn read_structure(root: &Nbt) -> Result<Blocks, VoxelWorldLoadError> {
+    let size = root
+        .get("size")
+        .and_then(Nbt::as_list)
+        .filter(|size| size.len() == 3)
+        .ok_or("Structure has an invalid size")?;
+    let size = read_position(size)?;
+
+    // structures with several palettes pick one at random in game, use the first
+    let palette = match root.get("palettes").and_then(Nbt::as_list) {
+        Some(palettes) => palettes.first().and_then(Nbt::as_list),
+        None => root.get("palette").and_then(Nbt::as_list),
+    }
+    .ok_or("Structure has no block palette")?;
+    let block_states = palette
+        .iter()
+        .map(|block| block.get("Name").and_then(Nbt::as_str).unwrap_or("air"))
+        .map(str::to_string)
+        .collect();
+
+    let mut blocks = Vec::new();
+    for block in root
+        .get("blocks")
+        .and_then(Nbt::as_list)
+        .ok_or("Structure has no blocks")?
+    {
+        let state = block
+            .get("state")
+            .and_then(Nbt::as_int)
+            .ok_or("Structure block has no state")?;
+        let pos = block
+            .get("pos")
+            .and_then(Nbt::as_list)
+            .filter(|pos| pos.len() == 3)
+            .ok_or("Structure block has no position")?;
+        let pos = read_position(pos)?;
+        if pos.cmpge(size).any() {
+            return Err("Structure block is outside of the structure".into());
+        }
+        blocks.push((pos, state as usize));
+    }
+
+    Ok((size, block_states, blocks))
+}
+
+fn read_position(list: &[Nbt]) -> Result<UVec3, VoxelWorldLoadError> {
+    let mut position = [0; 3];
+    for (axis, value) in list.iter().enumerate() {
+        position[axis] = value
+            .as_int()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or("Invalid structure position")?;
+    }
+    Ok(UVec3::from_array(position))
+}
+
+fn nearest_colour(colours: &[[u8; 3]], colour: [u8; 3]) -> usize {
+    let distance = |other: &[u8; 3]| {
+        (0..3)
+            .map(|i| (other[i] as i32 - colour[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    (0..colours.len())
+        .min_by_key(|i| distance(&colours[*i]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{Anchor, MAX_TEXTURE_SIZE, MIN_TEXTURE_SIZE};
+
+    fn named(tag: u8, name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        bytes.extend((name.len() as u16).to_be_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn compound(tags: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = tags.concat();
+        bytes.push(0);
+        bytes
+    }
+
+    fn list(tag: u8, payloads: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        bytes.extend((payloads.len() as i32).to_be_bytes());
+        bytes.extend(payloads.concat());
+        bytes
+    }
+
+    fn ints(values: [i32; 3]) -> Vec<u8> {
+        list(3, &values.map(|value| value.to_be_bytes().to_vec()))
+    }
+
+    fn settings() -> VoxelWorldLoadSettings {
+        VoxelWorldLoadSettings {
+            anchor: Anchor::Corner,
+            ..default()
+        }
+    }
+
+    fn sponge(size: [i16; 3], data: &[u8]) -> Vec<u8> {
+        let palette = compound(&[
+            named(3, "minecraft:air", &0i32.to_be_bytes()),
+            named(3, "minecraft:stone", &1i32.to_be_bytes()),
+            named(3, "minecraft:sand", &2i32.to_be_bytes()),
+        ]);
+        let mut block_data = (data.len() as i32).to_be_bytes().to_vec();
+        block_data.extend(data);
+        named(
+            10,
+            "Schematic",
+            &compound(&[
+                named(2, "Width", &size[0].to_be_bytes()),
+                named(2, "Height", &size[1].to_be_bytes()),
+                named(2, "Length", &size[2].to_be_bytes()),
+                named(10, "Palette", &palette),
+                named(7, "BlockData", &block_data),
+            ]),
+        )
+    }
+
+    fn structure(size: [i32; 3], state: i32, pos: [i32; 3]) -> Vec<u8> {
+        let block = compound(&[
+            named(3, "state", &state.to_be_bytes()),
+            named(9, "pos", &ints(pos)),
+        ]);
+        let palette = compound(&[named(8, "Name", b"\x00\x0eminecraft:sand")]);
+        named(
+            10,
+            "",
+            &compound(&[
+                named(9, "size", &ints(size)),
+                named(9, "palette", &list(10, &[palette])),
+                named(9, "blocks", &list(10, &[block])),
+            ]),
+        )
+    }
+
+    #[test]
+    fn loads_sponge_schematics() {
+        // blocks go along x, then z, then y
+        let file = sponge([2, 2, 1], &[1, 0, 0, 2]);
+        let gh = GH::from_schematic(&file, &settings()).unwrap();
+        assert_eq!(gh.texture_size, MIN_TEXTURE_SIZE);
+
+        let voxels: Vec<_> = gh.voxels().collect();
+        assert_eq!(voxels.len(), 2);
+        let (stone, flags) = gh.get_voxel(UVec3::new(0, 0, 0));
+        assert_eq!(flags, Flags::COLLISION_FLAG);
+        let (sand, flags) = gh.get_voxel(UVec3::new(1, 1, 0));
+        assert_eq!(flags, Flags::COLLISION_FLAG | Flags::SAND_FLAG);
+        assert_ne!(stone, sand);
+        assert_ne!(stone, 0);
+    }
+
+    #[test]
+    fn loads_structure_files() {
+        let gh = GH::from_schematic(&structure([1, 2, 1], 0, [0, 1, 0]), &settings()).unwrap();
+        let voxels: Vec<_> = gh.voxels().collect();
+        assert_eq!(voxels.len(), 1);
+        assert_eq!(voxels[0].0, UVec3::new(0, 1, 0));
+        assert_eq!(voxels[0].2, Flags::COLLISION_FLAG | Flags::SAND_FLAG);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let file = sponge([2, 2, 1], &[1, 0, 0]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+
+        let file = sponge([2, 2, 1], &[1, 0, 0, 2]);
+        for length in [0, 1, 10, file.len() / 2, file.len() - 1] {
+            assert!(GH::from_schematic(&file[..length], &settings()).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_headers_are_errors() {
+        // sizes are unsigned shorts
+        let file = sponge([-1, -1, -1], &[0; 64]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+
+        let file = structure([MAX_TEXTURE_SIZE as i32 * 2, 1, 1], 0, [0, 0, 0]);
+        assert!(matches!(
+            GH::from_schematic(&file, &settings()),
+            Err(VoxelWorldLoadError::ModelTooLarge(_))
+        ));
+        let file = structure([1, -1, 1], 0, [0, 0, 0]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+    }
+
+    #[test]
+    fn invalid_blocks_are_errors() {
+        // outside of the structure
+        let file = structure([1, 1, 1], 0, [0, 1, 0]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+        // not in the palette
+        let file = structure([1, 1, 1], 1, [0, 0, 0]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+        let file = sponge([1, 1, 1], &[3]);
+        assert!(GH::from_schematic(&file, &settings()).is_err());
+    }
+}
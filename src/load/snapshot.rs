@@ -0,0 +1,220 @@
+use super::{reader::Reader, MaterialProperties, Pallete, VoxelWorldLoadError, BRICK_SIZE, GH};
+use bevy::prelude::*;
+
+/// Magic bytes at the start of every snapshot.
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"BVWS";
+pub const SNAPSHOT_VERSION: u32 = 2;
+pub const SNAPSHOT_EXTENSION: &str = "vws";
+
+// Snapshot layout, all values are little endian:
+//
+// magic           4 bytes "BVWS"
+// version         u32
+// texture size    u32
+// pallete         256 * 4 f32, alpha is used for emission
+// materials       256 * 4 f32 roughness, metalness, transparency, emission (version 2 and up)
+// voxel runs      until the end of the file
+//
+// Every run is a LEB128 encoded length followed by the u16 voxel value (material | flags << 8)
+// repeated that many times, in the order of `GH::index`.
+
+impl GH {
+    /// Lossless copy of the world including the flags byte of every voxel.
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+        snapshot.extend(SNAPSHOT_VERSION.to_le_bytes());
+        snapshot.extend(self.texture_size.to_le_bytes());
+        for colour in self.pallete.iter() {
+            for channel in colour {
+                snapshot.extend(channel.to_le_bytes());
+            }
+        }
+        for material in self.materials.iter() {
+            for value in [
+                material.roughness,
+                material.metalness,
+                material.transparency,
+                material.emission,
+            ] {
+                snapshot.extend(value.to_le_bytes());
+            }
+        }
+
+        // rows of voxels along z that only go through bricks of air are skipped in one go
+        let mut run = (0, 0);
+        let mut push = |voxel: u16, length: u64| {
+            if voxel == run.0 {
+                run.1 += length;
+            } else {
+                push_run(&mut snapshot, run.0, run.1);
+                run = (voxel, length);
+            }
+        };
+        let size = self.texture_size;
+        let brick_size = self.brick_grid_size() as usize;
+        let empty_rows: Vec<bool> = self
+            .brick_grid
+            .chunks_exact(brick_size)
+            .map(|row| row.iter().all(|entry| *entry == 0))
+            .collect();
+        for x in 0..size {
+            for y in 0..size {
+                let first = self.brick_index(UVec3::new(x, y, 0) / BRICK_SIZE);
+                if empty_rows[first / brick_size] {
+                    push(0, size as u64);
+                    continue;
+                }
+                let row = &self.brick_grid[first..first + brick_size];
+
+                for (z, entry) in row.iter().enumerate() {
+                    if *entry == 0 {
+                        push(0, BRICK_SIZE as u64);
+                        continue;
+                    }
+                    let start = GH::brick_voxel_index(UVec3::new(x, y, z as u32 * BRICK_SIZE));
+                    let voxels = &self.brick(*entry)[start..start + BRICK_SIZE as usize];
+                    for voxel in voxels {
+                        push(*voxel, 1);
+                    }
+                }
+            }
+        }
+        push_run(&mut snapshot, run.0, run.1);
+
+        snapshot
+    }
+
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<GH, VoxelWorldLoadError> {
+        let mut reader = Reader::new(snapshot, "snapshot");
+        if reader.take(4)? != SNAPSHOT_MAGIC {
+            return Err("Not a voxel world snapshot!".into());
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", version).into());
+        }
+        let texture_size = reader.u32()?;
+        GH::check_size(texture_size)?;
+
+        let mut pallete = Pallete([[0.0; 4]; 256]);
+        for colour in pallete.iter_mut() {
+            for channel in colour.iter_mut() {
+                *channel = f32::from_bits(reader.u32()?);
+            }
+        }
+
+        let mut materials = [MaterialProperties::default(); 256];
+        if version >= 2 {
+            for material in materials.iter_mut() {
+                material.roughness = f32::from_bits(reader.u32()?);
+                material.metalness = f32::from_bits(reader.u32()?);
+                material.transparency = f32::from_bits(reader.u32()?);
+                material.emission = f32::from_bits(reader.u32()?);
+            }
+        }
+
+        let mut gh = GH::empty(texture_size);
+        gh.pallete = pallete;
+        gh.materials = materials;
+
+        let size = texture_size as usize;
+        let voxel_count = size * size * size;
+        let mut position: usize = 0;
+        while !reader.is_empty() {
+            let length = reader.leb128()?;
+            let voxel = reader.take(2)?;
+            let end = usize::try_from(length)
+                .ok()
+                .and_then(|length| position.checked_add(length))
+                .filter(|end| *end <= voxel_count)
+                .ok_or("Snapshot contains more voxels than the world size")?;
+
+            // the world starts out as air
+            if voxel != [0, 0] {
+                for index in position..end {
+                    let pos = UVec3::new(
+                        (index / (size * size)) as u32,
+                        (index / size % size) as u32,
+                        (index % size) as u32,
+                    );
+                    gh.set_voxel(pos, voxel[0], voxel[1]);
+                }
+            }
+            position = end;
+        }
+        if position != voxel_count {
+            return Err("Snapshot is missing voxels".into());
+        }
+
+        Ok(gh)
+    }
+}
+
+fn push_run(snapshot: &mut Vec<u8>, voxel: u16, mut length: u64) {
+    if length == 0 {
+        return;
+    }
+    loop {
+        let byte = (length & 0x7F) as u8;
+        length >>= 7;
+        if length == 0 {
+            snapshot.push(byte);
+            break;
+        }
+        snapshot.push(byte | 0x80);
+    }
+    snapshot.extend(voxel.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(texture_size: u32) -> Vec<u8> {
+        let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+        snapshot.extend(SNAPSHOT_VERSION.to_le_bytes());
+        snapshot.extend(texture_size.to_le_bytes());
+        snapshot.extend(vec![0; 256 * 4 * 4 * 2]);
+        snapshot
+    }
+
+    #[test]
+    fn round_trip_keeps_flags() {
+        let mut gh = GH::empty(32);
+        gh.set_voxel(UVec3::new(1, 2, 3), 7, 16);
+        gh.set_voxel(UVec3::new(31, 31, 31), 200, 128 | 8);
+        gh.pallete[7] = [0.1, 0.2, 0.3, 0.5];
+        gh.materials[7].roughness = 0.25;
+
+        let loaded = GH::from_snapshot(&gh.to_snapshot()).unwrap();
+        assert_eq!(loaded.texture_size, 32);
+        assert_eq!(
+            loaded.voxels().collect::<Vec<_>>(),
+            gh.voxels().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.pallete[7], [0.1, 0.2, 0.3, 0.5]);
+        assert_eq!(loaded.materials[7], gh.materials[7]);
+    }
+
+    #[test]
+    fn huge_run_is_an_error() {
+        let mut snapshot = header(32);
+        // a run of u64::MAX voxels
+        snapshot.extend([0xFF; 9]);
+        snapshot.push(0x01);
+        snapshot.extend([1, 0]);
+        assert!(GH::from_snapshot(&snapshot).is_err());
+    }
+
+    #[test]
+    fn too_many_voxels_is_an_error() {
+        let mut snapshot = header(32);
+        push_run(&mut snapshot, 0, 32 * 32 * 32);
+        push_run(&mut snapshot, 1, 1);
+        assert!(GH::from_snapshot(&snapshot).is_err());
+
+        let mut snapshot = header(32);
+        push_run(&mut snapshot, 0, 32 * 32 * 32 - 1);
+        assert!(GH::from_snapshot(&snapshot).is_err());
+    }
+}
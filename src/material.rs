@@ -0,0 +1,208 @@
+use crate::{VoxelWorldLoadError, VoxelizationMaterial, VoxelizationMaterialType};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::RangeInclusive, sync::OnceLock};
+
+/// Gameplay properties of a material, stored in a `MaterialRegistry` under its name. Palette
+/// entries that no material covers behave like the default material.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegisteredMaterial {
+    /// Palette index of the material.
+    pub index: u8,
+    /// How many palette entries from `index` on belong to the material, like the stages fire
+    /// burns out through.
+    pub count: u8,
+    /// Relative to water. Falling sand and water sink through voxels of lighter materials that
+    /// do not have `Flags::COLLISION_FLAG`.
+    pub density: f32,
+    /// Chance that fire next to a voxel with `Flags::COLLISION_FLAG` sets it alight, 0 never
+    /// catches fire and 1 always does.
+    pub flammability: f32,
+    /// Voxels harder than 1 are only destroyed within `radius / hardness` of a
+    /// `CollisionEffect::Destroy`.
+    pub hardness: f32,
+    /// How fast particles sliding over the material slow down, as a fraction of their gravity.
+    pub friction: f32,
+    /// `Flags` the material is placed with.
+    pub flags: u8,
+    /// Glows with its own colour when its palette entry is not emissive already.
+    pub emissive: bool,
+}
+
+impl Default for RegisteredMaterial {
+    fn default() -> Self {
+        Self {
+            index: 0,
+            count: 1,
+            density: 1.0,
+            flammability: 0.0,
+            hardness: 1.0,
+            friction: 0.5,
+            flags: 0,
+            emissive: false,
+        }
+    }
+}
+
+impl RegisteredMaterial {
+    /// Every palette index that belongs to the material.
+    pub fn indices(&self) -> RangeInclusive<u8> {
+        self.index..=self.index.saturating_add(self.count.max(1) - 1)
+    }
+
+    pub fn contains(&self, index: u8) -> bool {
+        self.indices().contains(&index)
+    }
+
+    /// Voxelizes meshes as this material.
+    pub fn voxelization_material(&self) -> VoxelizationMaterial {
+        VoxelizationMaterial {
+            material: VoxelizationMaterialType::Material(self.index),
+            flags: self.flags,
+        }
+    }
+}
+
+/// Names for palette indices, so gameplay code does not hardcode them. The properties are uploaded
+/// to the gpu along with the palette whenever the resource changes, the shaders find the
+/// materials the engine uses by the names below.
+///
+/// The default registry describes the default palette, replace it with `from_ron` for worlds with
+/// a different palette.
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MaterialRegistry {
+    materials: BTreeMap<String, RegisteredMaterial>,
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        static DEFAULT: OnceLock<MaterialRegistry> = OnceLock::new();
+        DEFAULT
+            .get_or_init(|| {
+                MaterialRegistry::from_ron(include_str!("materials.ron"))
+                    .expect("Default material registry is valid")
+            })
+            .clone()
+    }
+}
+
+impl MaterialRegistry {
+    /// Material `VoxelizationMaterial` uses by default.
+    pub const DEFAULT: &'static str = "default";
+    /// Grass below other voxels turns into this.
+    pub const DIRT: &'static str = "dirt";
+    /// Spreads to flammable voxels with `Flags::COLLISION_FLAG` and burns out.
+    pub const FIRE: &'static str = "fire";
+    pub const GRASS: &'static str = "grass";
+    /// Flows down and sideways.
+    pub const WATER: &'static str = "water";
+
+    /// An empty registry, the engine's own behaviour is turned off for materials it can not find.
+    pub fn empty() -> Self {
+        Self {
+            materials: BTreeMap::new(),
+        }
+    }
+
+    /// Reads a map from names to materials, properties that are left out use their defaults.
+    pub fn from_ron(ron: &str) -> Result<Self, VoxelWorldLoadError> {
+        Ok(ron::from_str(ron).map_err(|error| format!("Invalid material registry: {}", error))?)
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("Material registries always serialize")
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredMaterial> {
+        self.materials.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut RegisteredMaterial> {
+        self.materials.get_mut(name)
+    }
+
+    /// Palette index of a material.
+    pub fn index(&self, name: &str) -> Option<u8> {
+        self.get(name).map(|material| material.index)
+    }
+
+    /// Adds a material, returning the one it replaced.
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        material: RegisteredMaterial,
+    ) -> Option<RegisteredMaterial> {
+        self.materials.insert(name.into(), material)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<RegisteredMaterial> {
+        self.materials.remove(name)
+    }
+
+    /// The material a palette index belongs to. If several overlap the one with the fewest
+    /// indices wins, then the first by name.
+    pub fn find(&self, index: u8) -> Option<(&str, &RegisteredMaterial)> {
+        self.iter()
+            .filter(|(_, material)| material.contains(index))
+            .min_by_key(|(_, material)| material.count.max(1))
+    }
+
+    /// Every material in order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RegisteredMaterial)> {
+        self.materials
+            .iter()
+            .map(|(name, material)| (name.as_str(), material))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_ron_fills_in_defaults() {
+        let registry = MaterialRegistry::from_ron(
+            r#"{
+                "lava": (index: 20, count: 3, density: 3.0, flammability: 1.0, emissive: true),
+                "stone": (index: 30),
+            }"#,
+        )
+        .unwrap();
+
+        let lava = registry.get("lava").unwrap();
+        assert_eq!(lava.indices(), 20..=22);
+        assert_eq!((lava.density, lava.flammability), (3.0, 1.0));
+        assert!(lava.emissive);
+        assert_eq!(lava.hardness, RegisteredMaterial::default().hardness);
+        assert_eq!(
+            registry.get("stone"),
+            Some(&RegisteredMaterial {
+                index: 30,
+                ..default()
+            })
+        );
+        assert_eq!(registry.find(21).map(|(name, _)| name), Some("lava"));
+        assert_eq!(registry.find(23), None);
+        assert_eq!(
+            MaterialRegistry::from_ron(&registry.to_ron()).unwrap(),
+            registry
+        );
+    }
+
+    #[test]
+    fn from_ron_rejects_unknown_properties() {
+        assert!(MaterialRegistry::from_ron(r#"{"stone": (index: 30, weight: 2.0)}"#).is_err());
+        assert!(MaterialRegistry::from_ron("not a registry").is_err());
+    }
+
+    #[test]
+    fn default_registry_parses() {
+        let registry = MaterialRegistry::default();
+        assert_eq!(registry.index(MaterialRegistry::FIRE), Some(9));
+        assert_eq!(registry.get(MaterialRegistry::FIRE).unwrap().count, 5);
+        assert_eq!(registry.index(MaterialRegistry::WATER), Some(8));
+    }
+}
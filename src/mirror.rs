@@ -0,0 +1,32 @@
+use crate::load::GH;
+use bevy::prelude::*;
+
+/// A copy of the voxel world in main memory. Insert one to keep it in sync with the loaded world
+/// and every `VoxelEdit`, the headless plugin always has one. Voxels changed by the gpu on its own,
+/// like animation, automata and physics `CollisionEffect`s, are not mirrored.
+#[derive(Resource, Clone)]
+pub struct VoxelWorldMirror {
+    pub gh: GH,
+}
+
+impl Default for VoxelWorldMirror {
+    fn default() -> Self {
+        // the same empty world the gpu starts with
+        Self { gh: GH::empty(128) }
+    }
+}
+
+impl VoxelWorldMirror {
+    pub fn texture_size(&self) -> u32 {
+        self.gh.texture_size
+    }
+
+    /// Material and flags of the voxel at `pos`, `None` outside of the world.
+    pub fn get_voxel(&self, pos: IVec3) -> Option<(u8, u8)> {
+        let size = self.gh.texture_size as i32;
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(IVec3::splat(size)).any() {
+            return None;
+        }
+        Some(self.gh.get_voxel(pos.as_uvec3()))
+    }
+}
@@ -0,0 +1,78 @@
+use crate::load::GH;
+use bevy::prelude::*;
+
+/// Colours of the 256 materials of the loaded world, material 0 is air. Filled from every world
+/// that is loaded and written to the gpu whenever it changes, so materials can be recoloured
+/// without reloading the world.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct VoxelPalette {
+    colours: [Vec3; 256],
+    emission: [f32; 256],
+}
+
+impl Default for VoxelPalette {
+    fn default() -> Self {
+        Self {
+            colours: [Vec3::ZERO; 256],
+            emission: [0.0; 256],
+        }
+    }
+}
+
+impl VoxelPalette {
+    /// Linear colour of a material.
+    pub fn colour(&self, index: u8) -> Color {
+        let colour = self.colours[index as usize];
+        Color::rgb_linear(colour.x, colour.y, colour.z)
+    }
+
+    pub fn set_colour(&mut self, index: u8, colour: Color) {
+        let [r, g, b, _] = colour.as_linear_rgba_f32();
+        self.colours[index as usize] = Vec3::new(r, g, b);
+    }
+
+    /// How strongly a material glows in its colour, 0 does not glow at all.
+    pub fn emission(&self, index: u8) -> f32 {
+        self.emission[index as usize]
+    }
+
+    pub fn set_emission(&mut self, index: u8, emission: f32) {
+        self.emission[index as usize] = emission.max(0.0);
+    }
+
+    /// Takes the palette of a world. Emissive entries are stored brightened by their emission,
+    /// like magica voxel does, so the colour is divided back out.
+    pub fn from_gh(gh: &GH) -> Self {
+        let mut palette = Self::default();
+        for i in 0..256 {
+            let [r, g, b, emissive] = gh.pallete[i];
+            let colour = Vec3::new(r, g, b);
+            if emissive != 0.0 {
+                // keep entries that glow with an emission of 0 glowing
+                let emission = gh.materials[i].emission.max(f32::MIN_POSITIVE);
+                palette.colours[i] = colour / (1.0 + emission);
+                palette.emission[i] = emission;
+            } else {
+                palette.colours[i] = colour;
+            }
+        }
+        palette
+    }
+
+    /// Writes the palette into a world, the inverse of `from_gh`.
+    pub fn write_to(&self, gh: &mut GH) {
+        for i in 0..256 {
+            gh.pallete[i] = self.pallete_entry(i);
+            gh.materials[i].emission = self.emission[i];
+        }
+    }
+
+    /// An entry the way the shaders read it, with the alpha marking emissive materials.
+    pub(crate) fn pallete_entry(&self, index: usize) -> [f32; 4] {
+        let emission = self.emission[index];
+        let emissive = if emission > 0.0 { 1.0 } else { 0.0 };
+        (self.colours[index] * (1.0 + emission))
+            .extend(emissive)
+            .to_array()
+    }
+}
@@ -1,14 +1,18 @@
 use crate::{
     voxel_pipeline::{
-        compute::{AnimationData, PhysicsData},
+        compute::{AnimationData, PhysicsData, MAX_TYPE_BUFFER_DATA},
         voxel_world::{ExtractedPortal, VoxelUniforms},
     },
-    Box, BoxCollider, Edges, Particle, Portal, RenderGraphSettings, VoxelPhysics,
-    VoxelizationMaterial, VoxelizationMaterialType,
+    Box, BoxCollider, CollisionEffect, Edges, FloatingOrigin, Particle, Portal,
+    RenderGraphSettings, VoxelAnimation, VoxelAnimationPlayer, VoxelModel, VoxelPhysics,
+    VoxelStreaming, VoxelWorldAsset, VoxelizationMaterial, VoxelizationMaterialType, GH,
 };
 use bevy::{
+    asset::HandleId,
+    math::Affine3A,
     prelude::*,
     render::renderer::{RenderDevice, RenderQueue},
+    transform::TransformSystem,
     utils::HashMap,
 };
 
@@ -20,7 +24,11 @@ impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(insert_physics_data.in_base_set(CoreSet::PreUpdate))
             .add_system(extract_physics_data.in_base_set(CoreSet::PostUpdate))
-            .add_system(extract_animation_data.in_base_set(CoreSet::PostUpdate));
+            .add_system(
+                extract_animation_data
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(TransformSystem::TransformPropagate),
+            );
     }
 }
 
@@ -80,6 +88,9 @@ pub fn insert_physics_data(
     physics_data: Res<PhysicsData>,
     render_device: Res<RenderDevice>,
     render_graph_settings: Res<RenderGraphSettings>,
+    mut streaming: Option<ResMut<VoxelStreaming>>,
+    floating_origin: Res<FloatingOrigin>,
+    voxel_uniforms: Res<VoxelUniforms>,
 ) {
     if !render_graph_settings.physics {
         return;
@@ -140,6 +151,23 @@ pub fn insert_physics_data(
                         bytemuck::cast(result[data_index + 23]),
                     ),
                 );
+
+                // points that hit something changed the voxels around them
+                let radius = match voxel_physics.collision_effect {
+                    CollisionEffect::None => continue,
+                    CollisionEffect::Destroy { radius }
+                    | CollisionEffect::Place { radius, .. }
+                    | CollisionEffect::SetFlags { radius, .. } => radius,
+                };
+                if let Some(streaming) = streaming.as_mut() {
+                    if voxel_physics.hit_normal != Vec3::ZERO {
+                        let pos =
+                            world_to_voxel(transform.translation, voxel_uniforms.texture_size);
+                        let range = IVec3::splat((radius * VOXELS_PER_METER).ceil() as i32);
+                        let pos = floating_origin.to_global(pos);
+                        streaming.mark_dirty(pos - range, pos + range);
+                    }
+                }
             }
         }
     }
@@ -156,6 +184,46 @@ pub fn world_to_render(world_pos: Vec3, voxel_world_size: u32) -> Vec3 {
     2.0 * world_pos * VOXELS_PER_METER / voxel_world_size as f32
 }
 
+/// The voxels of a `VoxelModel` or animation frame cropped to the box around them, four materials
+/// to a word in the same order as `GH::index`.
+pub struct PackedModel {
+    /// Corner of the box relative to the center of the world the model was loaded into.
+    min: IVec3,
+    size: UVec3,
+    data: Vec<u32>,
+}
+
+impl PackedModel {
+    fn new(gh: &GH) -> Self {
+        let mut min = UVec3::splat(u32::MAX);
+        let mut max = UVec3::ZERO;
+        for (pos, _, _) in gh.voxels().filter(|(_, material, _)| *material != 0) {
+            min = min.min(pos);
+            max = max.max(pos);
+        }
+        if min.cmpgt(max).any() {
+            return Self {
+                min: IVec3::ZERO,
+                size: UVec3::ZERO,
+                data: Vec::new(),
+            };
+        }
+
+        let size = max - min + UVec3::ONE;
+        let mut data = vec![0; ((size.x * size.y * size.z + 3) / 4) as usize];
+        for (pos, material, _) in gh.voxels().filter(|(_, material, _)| *material != 0) {
+            let pos = pos - min;
+            let index = (pos.x * size.y * size.z + pos.y * size.z + pos.z) as usize;
+            data[index / 4] |= (material as u32) << (index % 4 * 8);
+        }
+        Self {
+            min: min.as_ivec3() - IVec3::splat(gh.texture_size as i32 / 2),
+            size,
+            data,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct TypeBuffer {
     header: Vec<u32>,
@@ -211,6 +279,70 @@ impl TypeBuffer {
         self.data.push(bytemuck::cast(value.z));
     }
 
+    /// Length of the buffer `finish` returns.
+    fn len(&self) -> usize {
+        1 + self.header.len() + self.data.len()
+    }
+
+    /// Pushes data that is not an object, returning where it starts in the data after the header.
+    fn push_data(&mut self, data: &[u32]) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(data);
+        offset
+    }
+
+    /// Pushes a model at `transform` whose voxels were pushed at `offset`. Only the box of the
+    /// world it covers is sent, the shader fills every voxel in it whose center is inside a voxel
+    /// of the model.
+    fn push_model(
+        &mut self,
+        transform: &Transform,
+        model: &PackedModel,
+        offset: u32,
+        material: u8,
+        flags: u8,
+        voxel_world_size: u32,
+    ) {
+        if model.size == UVec3::ZERO || transform.scale.cmpeq(Vec3::ZERO).any() {
+            return;
+        }
+
+        // model voxels to world voxels, with the same origin as world_to_voxel
+        let model_to_world = Affine3A::from_translation(Vec3::splat(voxel_world_size as f32 / 2.0))
+            * Affine3A::from_scale(Vec3::splat(VOXELS_PER_METER))
+            * transform.compute_affine()
+            * Affine3A::from_scale(Vec3::splat(1.0 / VOXELS_PER_METER))
+            * Affine3A::from_translation(model.min.as_vec3());
+
+        let size = model.size.as_vec3();
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for i in 0..8 {
+            let corner = Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32) * size;
+            let corner = model_to_world.transform_point3(corner);
+            min = min.min(corner);
+            max = max.max(corner);
+        }
+        let min = min.floor().as_ivec3().max(IVec3::ZERO);
+        let max =
+            (max.ceil().as_ivec3() - IVec3::ONE).min(IVec3::splat(voxel_world_size as i32 - 1));
+        if min.cmpgt(max).any() {
+            return;
+        }
+
+        let world_to_model = model_to_world.inverse();
+        self.push_object(3, |type_buffer| {
+            type_buffer.push_ivec3(min);
+            type_buffer.push_u32(material as u32);
+            type_buffer.push_u32(flags as u32);
+            type_buffer.push_ivec3(max);
+            type_buffer.push_mat3(world_to_model.matrix3.into());
+            type_buffer.push_vec3(world_to_model.translation.into());
+            type_buffer.push_ivec3(model.size.as_ivec3());
+            type_buffer.push_u32(offset);
+        });
+    }
+
     fn push_mat3(&mut self, value: Mat3) {
         self.data.push(bytemuck::cast(value.x_axis.x));
         self.data.push(bytemuck::cast(value.x_axis.y));
@@ -224,12 +356,20 @@ impl TypeBuffer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn extract_animation_data(
     mut animation_data: ResMut<AnimationData>,
     particle_query: Query<(&Transform, &Particle)>,
     mut portal_query: Query<(&Transform, &Portal, &mut VoxelizationMaterial)>,
     edges_query: Query<(&Transform, &Edges)>,
     boxes_query: Query<(&Transform, &Box)>,
+    model_query: Query<(&GlobalTransform, &VoxelModel)>,
+    voxel_world_assets: Res<Assets<VoxelWorldAsset>>,
+    mut asset_events: EventReader<AssetEvent<VoxelWorldAsset>>,
+    animation_query: Query<(&GlobalTransform, &VoxelAnimationPlayer)>,
+    animations: Res<Assets<VoxelAnimation>>,
+    mut animation_events: EventReader<AssetEvent<VoxelAnimation>>,
+    mut packed_models: Local<HashMap<(HandleId, usize), PackedModel>>,
     mut voxel_uniforms: ResMut<VoxelUniforms>,
     render_queue: Res<RenderQueue>,
 ) {
@@ -269,6 +409,60 @@ pub fn extract_animation_data(
         });
     }
 
+    // add models and the current frame of animations, packing each one the first time it is drawn
+    for event in asset_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            packed_models.retain(|(id, _), _| *id != handle.id());
+        }
+    }
+    for event in animation_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            packed_models.retain(|(id, _), _| *id != handle.id());
+        }
+    }
+    let models = model_query.iter().filter_map(|(transform, voxel_model)| {
+        let asset = voxel_world_assets.get(&voxel_model.model)?;
+        Some((
+            transform,
+            (voxel_model.model.id(), 0),
+            &asset.gh,
+            voxel_model.material,
+            voxel_model.flags,
+        ))
+    });
+    let animations = animation_query.iter().filter_map(|(transform, player)| {
+        let animation = animations.get(&player.animation)?;
+        let frame = player.frame(animation.frame_count());
+        Some((
+            transform,
+            (player.animation.id(), frame),
+            animation.frames.get(frame)?,
+            player.material,
+            player.flags,
+        ))
+    });
+    let mut model_offsets = HashMap::new();
+    for (transform, key, gh, material, flags) in models.chain(animations) {
+        let model = packed_models
+            .entry(key)
+            .or_insert_with(|| PackedModel::new(gh));
+        if !model_offsets.contains_key(&key) {
+            if type_buffer.len() + model.data.len() > MAX_TYPE_BUFFER_DATA {
+                warn!("Voxel model does not fit in the animation buffer");
+                continue;
+            }
+            model_offsets.insert(key, type_buffer.push_data(&model.data));
+        }
+        type_buffer.push_model(
+            &transform.compute_transform(),
+            model,
+            model_offsets[&key],
+            material,
+            flags,
+            voxel_world_size,
+        );
+    }
+
     // grab all the poratls in pairs
     voxel_uniforms.portals = [ExtractedPortal::default(); 32];
     let mut portals: Vec<(&Transform, &Portal, Mut<VoxelizationMaterial>)> =
@@ -310,3 +504,57 @@ pub fn extract_animation_data(
         bytemuck::cast_slice(&type_buffer.finish()),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Flags;
+
+    #[test]
+    fn packed_models_are_cropped_to_their_voxels() {
+        let mut gh = GH::empty(32);
+        // relative to the corner of the box, (0, 0, 0) to (2, 3, 2)
+        let voxels = [
+            (UVec3::new(0, 0, 0), 5),
+            (UVec3::new(1, 0, 0), 6),
+            (UVec3::new(0, 1, 2), 7),
+            (UVec3::new(2, 3, 0), 9),
+            (UVec3::new(1, 1, 0), 1),
+            (UVec3::new(1, 1, 1), 2),
+            (UVec3::new(1, 1, 2), 3),
+            (UVec3::new(1, 2, 0), 4),
+        ];
+        let min = UVec3::new(10, 12, 14);
+        for (pos, material) in voxels {
+            gh.set_voxel(min + pos, material, 0);
+        }
+        // voxels without a material are left out of the box
+        gh.set_voxel(UVec3::new(30, 30, 30), 0, Flags::COLLISION_FLAG);
+
+        let model = PackedModel::new(&gh);
+        assert_eq!(model.min, IVec3::new(-6, -4, -2));
+        assert_eq!(model.size, UVec3::new(3, 4, 3));
+        // index x * 12 + y * 3 + z, four materials to a word starting from the low byte
+        assert_eq!(
+            model.data,
+            [
+                0x0000_0005,
+                0x0000_0700,
+                0,
+                0x0100_0006,
+                0x0004_0302,
+                0,
+                0,
+                0,
+                0x0000_0900,
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_models_pack_to_nothing() {
+        let model = PackedModel::new(&GH::empty(32));
+        assert_eq!(model.size, UVec3::ZERO);
+        assert!(model.data.is_empty());
+    }
+}
@@ -0,0 +1,525 @@
+use crate::{
+    load::GH,
+    voxel_pipeline::{
+        compute::{QueryData, MAX_QUERIES, MAX_QUERY_RESULTS},
+        voxel_world::VoxelUniforms,
+    },
+};
+use bevy::{
+    prelude::*,
+    render::renderer::{RenderDevice, RenderQueue},
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+/// Frames to wait after submitting queries before mapping the results, with pipelined
+/// rendering the frame that runs the queries can still be in flight one frame later.
+const FRAMES_BEFORE_MAP: u32 = 2;
+/// Result words a ray query uses, hit, position, normal, distance and voxel.
+const RAY_RESULT_WORDS: usize = 9;
+
+// states of the results buffer while it is being mapped
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+pub struct QueryPlugin;
+
+impl Plugin for QueryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelQueries>()
+            .insert_resource(QueryReadback::None)
+            .add_event::<VoxelQueryEvent>()
+            .add_system(extract_voxel_queries.in_base_set(CoreSet::PostUpdate));
+    }
+}
+
+/// Identifies the `VoxelQueryEvent` that answers a query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoxelQueryId(u64);
+
+/// A read of the voxel world. Positions are voxel coordinates from 0 to the world size on each
+/// axis with y up, like `VoxelEdit`, and voxels outside of the world read as air.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoxelQuery {
+    Point(IVec3),
+    /// Every voxel of the box including both corners, at most `MAX_QUERY_RESULTS` of them.
+    Box {
+        min: IVec3,
+        max: IVec3,
+    },
+    /// The first voxel that is not air along the ray, `max_distance` is in voxels.
+    Ray {
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+    },
+}
+
+impl VoxelQuery {
+    fn result_words(&self) -> usize {
+        match *self {
+            VoxelQuery::Point(_) => 1,
+            VoxelQuery::Box { min, max } => {
+                let size = (min.max(max) - min.min(max) + IVec3::ONE).as_uvec3();
+                size.x as usize * size.y as usize * size.z as usize
+            }
+            VoxelQuery::Ray { .. } => RAY_RESULT_WORDS,
+        }
+    }
+
+    /// Answers the query from a world on the cpu, the same way `query.wgsl` does on the gpu. The
+    /// headless plugin answers queries this way from the `VoxelWorldMirror`, so they do not see
+    /// voxels changed by automata, animation or physics `CollisionEffect`s.
+    pub fn run(&self, gh: &GH) -> VoxelQueryResult {
+        let size = gh.texture_size as i32;
+        let value = |pos: IVec3| {
+            if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(IVec3::splat(size)).any() {
+                return 0;
+            }
+            let (material, flags) = gh.get_voxel(pos.as_uvec3());
+            material as u32 | (flags as u32) << 8
+        };
+        let sample = |pos: IVec3| voxel_sample(pos, value(pos), |material| gh.pallete[material]);
+
+        match *self {
+            VoxelQuery::Point(pos) => VoxelQueryResult::Point(sample(pos)),
+            VoxelQuery::Box { min, max } => {
+                if self.result_words() > MAX_QUERY_RESULTS {
+                    return VoxelQueryResult::Box(Vec::new());
+                }
+                let (min, max) = (min.min(max), min.max(max));
+                let mut voxels = Vec::with_capacity(self.result_words());
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        for z in min.z..=max.z {
+                            voxels.push(sample(IVec3::new(x, y, z)));
+                        }
+                    }
+                }
+                VoxelQueryResult::Box(voxels)
+            }
+            VoxelQuery::Ray {
+                origin,
+                direction,
+                max_distance,
+            } => {
+                let direction = direction.normalize_or_zero();
+                // signum is 1 for zero where the gpu sign is 0
+                let step = IVec3::select(
+                    direction.cmpeq(Vec3::ZERO),
+                    IVec3::ZERO,
+                    direction.signum().as_ivec3(),
+                );
+                let safe_direction = Vec3::select(
+                    direction.abs().cmplt(Vec3::splat(1e-8)),
+                    Vec3::splat(1e-8),
+                    direction,
+                );
+                let delta = (Vec3::ONE / safe_direction).abs();
+                let sign = step.as_vec3();
+
+                let mut pos = origin.floor().as_ivec3();
+                let mut side = (sign * (pos.as_vec3() - origin) + sign * 0.5 + 0.5) * delta;
+                let mut normal = IVec3::ZERO;
+                let mut distance = 0.0;
+                // past the far side of the world there is nothing left to hit, which also keeps
+                // huge distances from overflowing the step count
+                let reach = max_distance
+                    .min(origin.distance(Vec3::splat(size as f32 / 2.0)) + size as f32 * 2.0);
+                let max_steps = (3.0 * (size as f32 + reach.max(0.0))) as i64;
+                for _ in 0..max_steps {
+                    if distance > max_distance {
+                        break;
+                    }
+                    if value(pos) & 0xFF != 0 {
+                        return VoxelQueryResult::Ray(Some(VoxelRayHit {
+                            sample: sample(pos),
+                            normal,
+                            distance,
+                        }));
+                    }
+
+                    if side.x < side.y && side.x < side.z {
+                        distance = side.x;
+                        side.x += delta.x;
+                        pos.x += step.x;
+                        normal = IVec3::new(-step.x, 0, 0);
+                    } else if side.y < side.z {
+                        distance = side.y;
+                        side.y += delta.y;
+                        pos.y += step.y;
+                        normal = IVec3::new(0, -step.y, 0);
+                    } else {
+                        distance = side.z;
+                        side.z += delta.z;
+                        pos.z += step.z;
+                        normal = IVec3::new(0, 0, -step.z);
+                    }
+                }
+                VoxelQueryResult::Ray(None)
+            }
+        }
+    }
+
+    fn push_to(&self, result_offset: usize, data: &mut Vec<u32>) {
+        let ivec3 = |value: IVec3| value.to_array().map(bytemuck::cast::<i32, u32>);
+        let vec3 = |value: Vec3| value.to_array().map(bytemuck::cast::<f32, u32>);
+        let (query_type, a, b, max_distance) = match *self {
+            VoxelQuery::Point(pos) => (0, ivec3(pos), [0; 3], 0.0),
+            VoxelQuery::Box { min, max } => (1, ivec3(min.min(max)), ivec3(min.max(max)), 0.0),
+            VoxelQuery::Ray {
+                origin,
+                direction,
+                max_distance,
+            } => (
+                2,
+                vec3(origin),
+                vec3(direction.normalize_or_zero()),
+                max_distance,
+            ),
+        };
+        data.push(query_type);
+        data.push(result_offset as u32);
+        data.extend(a);
+        data.extend(b);
+        data.push(bytemuck::cast(max_distance));
+    }
+}
+
+/// A voxel read back from the world, `colour` is its palette colour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelSample {
+    pub pos: IVec3,
+    pub material: u8,
+    pub flags: u8,
+    pub colour: Color,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelRayHit {
+    pub sample: VoxelSample,
+    /// Normal of the face the ray entered through, zero when the ray started inside the voxel.
+    pub normal: IVec3,
+    /// Distance along the ray in voxels.
+    pub distance: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoxelQueryResult {
+    Point(VoxelSample),
+    /// Voxels of the box with x changing slowest then y, empty if the box was too large.
+    Box(Vec<VoxelSample>),
+    Ray(Option<VoxelRayHit>),
+}
+
+/// Sent one or more frames after a query was submitted.
+#[derive(Clone, Debug)]
+pub struct VoxelQueryEvent {
+    pub id: VoxelQueryId,
+    pub query: VoxelQuery,
+    pub result: VoxelQueryResult,
+}
+
+/// Queries the voxel world on the gpu, results arrive as `VoxelQueryEvent`s once the gpu has
+/// finished the frame, without waiting for it.
+#[derive(Resource, Default)]
+pub struct VoxelQueries {
+    queries: VecDeque<(VoxelQueryId, VoxelQuery)>,
+    next_id: u64,
+}
+
+impl VoxelQueries {
+    pub fn submit(&mut self, query: VoxelQuery) -> VoxelQueryId {
+        let id = VoxelQueryId(self.next_id);
+        self.next_id += 1;
+        self.queries.push_back((id, query));
+        id
+    }
+
+    pub fn point(&mut self, pos: IVec3) -> VoxelQueryId {
+        self.submit(VoxelQuery::Point(pos))
+    }
+
+    pub fn box_region(&mut self, min: IVec3, max: IVec3) -> VoxelQueryId {
+        self.submit(VoxelQuery::Box { min, max })
+    }
+
+    pub fn ray(&mut self, origin: Vec3, direction: Vec3, max_distance: f32) -> VoxelQueryId {
+        self.submit(VoxelQuery::Ray {
+            origin,
+            direction,
+            max_distance,
+        })
+    }
+
+    /// Queries that have not been sent to the gpu yet.
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// Takes every queued query, oldest first.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (VoxelQueryId, VoxelQuery)> + '_ {
+        self.queries.drain(..)
+    }
+}
+
+/// Splits a voxel texture value, `pallete` gives the linear colour of a material.
+fn voxel_sample(pos: IVec3, value: u32, pallete: impl Fn(usize) -> [f32; 4]) -> VoxelSample {
+    let material = (value & 0xFF) as u8;
+    let [r, g, b, a] = pallete(material as usize);
+    VoxelSample {
+        pos,
+        material,
+        flags: (value >> 8) as u8,
+        colour: Color::rgba_linear(r, g, b, a),
+    }
+}
+
+type SubmittedQueries = Vec<(VoxelQueryId, VoxelQuery, usize)>;
+
+/// The batch of queries on the gpu, only one is read back at a time.
+#[derive(Resource)]
+enum QueryReadback {
+    Submitted {
+        queries: SubmittedQueries,
+        result_length: usize,
+        frames: u32,
+    },
+    Mapping {
+        queries: SubmittedQueries,
+        result_length: usize,
+        map_state: Arc<AtomicU8>,
+    },
+    None,
+}
+
+/// Reads back the last batch of queries when the gpu is done with it and submits the next one.
+fn extract_voxel_queries(
+    mut voxel_queries: ResMut<VoxelQueries>,
+    mut readback: ResMut<QueryReadback>,
+    mut query_data: ResMut<QueryData>,
+    mut query_events: EventWriter<VoxelQueryEvent>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    query_data.result_length = 0;
+
+    if let QueryReadback::Submitted {
+        queries,
+        result_length,
+        frames,
+    } = readback.as_mut()
+    {
+        *frames += 1;
+        if *frames >= FRAMES_BEFORE_MAP {
+            let map_state = Arc::new(AtomicU8::new(MAP_PENDING));
+            let callback_state = map_state.clone();
+            query_data
+                .results_buffer_cpu
+                .slice(..*result_length as u64 * 4)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let state = match result {
+                        Ok(()) => MAP_DONE,
+                        Err(_) => MAP_FAILED,
+                    };
+                    callback_state.store(state, Ordering::Release);
+                });
+            *readback = QueryReadback::Mapping {
+                queries: std::mem::take(queries),
+                result_length: *result_length,
+                map_state,
+            };
+        }
+    }
+
+    if let QueryReadback::Mapping {
+        queries,
+        result_length,
+        map_state,
+    } = readback.as_mut()
+    {
+        render_device.poll(wgpu::Maintain::Poll);
+        match map_state.load(Ordering::Acquire) {
+            MAP_PENDING => return,
+            MAP_FAILED => {
+                error!("Failed to read back {} voxel queries", queries.len());
+                *readback = QueryReadback::None;
+                return;
+            }
+            _ => {}
+        }
+
+        let slice = query_data
+            .results_buffer_cpu
+            .slice(..*result_length as u64 * 4);
+        let data = slice.get_mapped_range();
+        let results: &[u32] = bytemuck::cast_slice(&data);
+        let sample = |pos: IVec3, value: u32| {
+            voxel_sample(pos, value, |material| {
+                voxel_uniforms.pallete[material].colour.to_array()
+            })
+        };
+
+        for (id, query, offset) in queries.drain(..) {
+            let result = match query {
+                VoxelQuery::Point(pos) => VoxelQueryResult::Point(sample(pos, results[offset])),
+                VoxelQuery::Box { min, max } => {
+                    let (min, max) = (min.min(max), min.max(max));
+                    let size = max - min + IVec3::ONE;
+                    let mut voxels = Vec::with_capacity(query.result_words());
+                    for x in 0..size.x {
+                        for y in 0..size.y {
+                            for z in 0..size.z {
+                                let index = offset + ((x * size.y + y) * size.z + z) as usize;
+                                voxels.push(sample(min + IVec3::new(x, y, z), results[index]));
+                            }
+                        }
+                    }
+                    VoxelQueryResult::Box(voxels)
+                }
+                VoxelQuery::Ray { .. } => {
+                    let words = &results[offset..offset + RAY_RESULT_WORDS];
+                    let ivec3 = |words: &[u32]| {
+                        IVec3::new(
+                            bytemuck::cast(words[0]),
+                            bytemuck::cast(words[1]),
+                            bytemuck::cast(words[2]),
+                        )
+                    };
+                    VoxelQueryResult::Ray((words[0] != 0).then(|| VoxelRayHit {
+                        sample: sample(ivec3(&words[1..4]), words[8]),
+                        normal: ivec3(&words[4..7]),
+                        distance: bytemuck::cast(words[7]),
+                    }))
+                }
+            };
+            query_events.send(VoxelQueryEvent { id, query, result });
+        }
+
+        drop(data);
+        query_data.results_buffer_cpu.unmap();
+        *readback = QueryReadback::None;
+    }
+
+    // queries stay pending until the gpu can answer them
+    if !matches!(readback.as_ref(), QueryReadback::None)
+        || !query_data.pipeline_ready.load(Ordering::Relaxed)
+    {
+        return;
+    }
+
+    // take as many queries as fit in the result buffer
+    let mut queries = Vec::new();
+    let mut data = vec![0; 2];
+    let mut result_length = 0;
+    while let Some((id, query)) = voxel_queries.queries.front().copied() {
+        let words = query.result_words();
+        if words > MAX_QUERY_RESULTS {
+            warn!(
+                "Voxel query {:?} reads more than {} voxels",
+                query, MAX_QUERY_RESULTS
+            );
+            voxel_queries.queries.pop_front();
+            query_events.send(VoxelQueryEvent {
+                id,
+                query,
+                result: VoxelQueryResult::Box(Vec::new()),
+            });
+            continue;
+        }
+        if result_length + words > MAX_QUERY_RESULTS || queries.len() == MAX_QUERIES {
+            break;
+        }
+
+        query.push_to(result_length, &mut data);
+        queries.push((id, query, result_length));
+        result_length += words;
+        voxel_queries.queries.pop_front();
+    }
+
+    if queries.is_empty() {
+        return;
+    }
+
+    data[0] = queries.len() as u32;
+    data[1] = result_length as u32;
+    render_queue.write_buffer(&query_data.query_buffer, 0, bytemuck::cast_slice(&data));
+    query_data.result_length = result_length as u32;
+    *readback = QueryReadback::Submitted {
+        queries,
+        result_length,
+        frames: 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(gh: &GH, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelRayHit> {
+        let query = VoxelQuery::Ray {
+            origin,
+            direction,
+            max_distance,
+        };
+        match query.run(gh) {
+            VoxelQueryResult::Ray(hit) => hit,
+            result => panic!("ray query answered with {:?}", result),
+        }
+    }
+
+    fn world_with_wall() -> GH {
+        let mut gh = GH::empty(32);
+        for y in 0..32 {
+            for z in 0..32 {
+                gh.set_voxel(UVec3::new(20, y, z), 5, 16);
+            }
+        }
+        gh
+    }
+
+    #[test]
+    fn ray_hits_the_first_solid_voxel() {
+        let gh = world_with_wall();
+        let hit = ray(&gh, Vec3::new(2.5, 10.5, 10.5), Vec3::X, 100.0).unwrap();
+        assert_eq!(hit.sample.pos, IVec3::new(20, 10, 10));
+        assert_eq!((hit.sample.material, hit.sample.flags), (5, 16));
+        assert_eq!(hit.normal, IVec3::new(-1, 0, 0));
+        assert!((hit.distance - 17.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_misses_when_nothing_is_in_the_way() {
+        let gh = world_with_wall();
+        assert_eq!(ray(&gh, Vec3::new(2.5, 10.5, 10.5), -Vec3::X, 100.0), None);
+        assert_eq!(ray(&gh, Vec3::new(2.5, 10.5, 10.5), Vec3::Y, 100.0), None);
+    }
+
+    #[test]
+    fn ray_stops_at_max_distance() {
+        let gh = world_with_wall();
+        let origin = Vec3::new(2.5, 10.5, 10.5);
+        assert_eq!(ray(&gh, origin, Vec3::X, 17.0), None);
+        assert!(ray(&gh, origin, Vec3::X, 18.0).is_some());
+    }
+
+    #[test]
+    fn huge_distances_do_not_overflow() {
+        let gh = world_with_wall();
+        let far = Vec3::new(-1e6, 10.5, 10.5);
+        assert_eq!(ray(&gh, far, -Vec3::X, f32::MAX), None);
+        let hit = ray(&gh, Vec3::new(-100.5, 10.5, 10.5), Vec3::X, f32::INFINITY).unwrap();
+        assert_eq!(hit.sample.pos, IVec3::new(20, 10, 10));
+    }
+}
@@ -0,0 +1,409 @@
+use crate::{
+    load::{RegionStore, VoxelWorldGenerator, GH},
+    VOXELS_PER_METER,
+};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
+};
+use std::sync::{Arc, Mutex};
+
+/// Keeps the loaded world in step with the `RegionStore` of an endless world. Regions changed by
+/// `VoxelEdits` or physics `CollisionEffect`s are marked dirty until `flush` saves them, and
+/// `load_region` replaces part of the loaded world with a region from the store.
+///
+/// With an entity with `VoxelStreamingFocus` the loaded world becomes a window that follows it,
+/// moving a region at a time once the focus is more than a region from its center. Regions that
+/// come into view are loaded, or generated if they were never saved, and dirty regions that go out
+/// of view are saved.
+///
+/// Moves and loads happen on the gpu in the frame the regions are ready, only the regions that are
+/// saved are read back. Automata and animation do not mark regions dirty.
+#[derive(Resource)]
+pub struct VoxelStreaming {
+    pub store: RegionStore,
+    /// Fills regions that have never been saved, they are left empty without one.
+    pub generator: Option<Arc<dyn VoxelWorldGenerator>>,
+    /// Seed the `generator` is run with.
+    pub seed: u64,
+    dirty: HashSet<IVec3>,
+    loads: Vec<IVec3>,
+    flush: bool,
+}
+
+impl VoxelStreaming {
+    pub fn new(store: RegionStore) -> Self {
+        Self {
+            store,
+            generator: None,
+            seed: 0,
+            dirty: HashSet::default(),
+            loads: Vec::new(),
+            flush: false,
+        }
+    }
+
+    /// Marks every region the box between `min` and `max` touches as changed, in voxels of the
+    /// endless world.
+    pub fn mark_dirty(&mut self, min: IVec3, max: IVec3) {
+        let first = self.store.region_at(min.min(max));
+        let last = self.store.region_at(min.max(max));
+        for x in first.x..=last.x {
+            for y in first.y..=last.y {
+                for z in first.z..=last.z {
+                    self.dirty.insert(IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    pub fn is_dirty(&self, region: IVec3) -> bool {
+        self.dirty.contains(&region)
+    }
+
+    /// Regions that changed since they were last saved, in region coordinates.
+    pub fn dirty_regions(&self) -> impl Iterator<Item = IVec3> + '_ {
+        self.dirty.iter().copied()
+    }
+
+    /// Replaces the region at `region` of the loaded world with its last save, or generates it
+    /// again if it was never saved. Regions outside of the loaded world are skipped.
+    pub fn load_region(&mut self, region: IVec3) -> &mut Self {
+        self.loads.push(region);
+        self
+    }
+
+    /// Marks regions that could not be saved as dirty again, so they are saved with the next
+    /// `flush` or once they go out of view.
+    pub(crate) fn mark_unsaved(&mut self, regions: impl IntoIterator<Item = IVec3>) {
+        self.dirty.extend(regions);
+    }
+
+    /// Saves every dirty region of the loaded world.
+    pub fn flush(&mut self) -> &mut Self {
+        self.flush = true;
+        self
+    }
+}
+
+/// The entity the world follows while `VoxelStreaming`.
+#[derive(Component)]
+pub struct VoxelStreamingFocus;
+
+/// Where the loaded world is in the endless world of `VoxelStreaming`. It starts at zero and moves
+/// every time the world does, including for `ResizeVoxelWorld`.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloatingOrigin {
+    /// Position of voxel (0, 0, 0) of the loaded world in the endless world.
+    pub offset: IVec3,
+}
+
+impl FloatingOrigin {
+    pub fn to_global(&self, pos: IVec3) -> IVec3 {
+        pos + self.offset
+    }
+
+    pub fn to_local(&self, pos: IVec3) -> IVec3 {
+        pos - self.offset
+    }
+}
+
+/// Sent on the frame the world moves to follow the `VoxelStreamingFocus`. Entities without a
+/// parent, which includes the focus, cameras and portals, have already been moved by
+/// `translation`. Anything else that keeps positions in the world has to be moved too.
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelWorldShifted {
+    /// Voxels that were at `pos` are now at `pos - shift`.
+    pub shift: IVec3,
+    pub translation: Vec3,
+}
+
+/// Work on the loaded world for `VoxelStreaming`. `shift` is how far the world moves in voxels,
+/// which is zero when regions are only loaded or saved.
+#[derive(Resource, Default)]
+pub(crate) enum StreamJob {
+    #[default]
+    None,
+    /// Regions are being loaded or generated, with their corner in the moved world, for a world of
+    /// `texture_size`.
+    Loading {
+        shift: IVec3,
+        texture_size: u32,
+        task: Task<Vec<(UVec3, GH)>>,
+    },
+    /// The world has moved and the regions in `saves` are being read back from the gpu, in the
+    /// same order.
+    Reading {
+        store: RegionStore,
+        saves: Vec<IVec3>,
+        readback: Arc<Mutex<Option<Vec<GH>>>>,
+    },
+    /// Regions are being saved, the task gives those that could not be saved.
+    Saving(Task<Vec<IVec3>>),
+}
+
+fn in_world(corner: IVec3, texture_size: u32) -> bool {
+    corner.cmpge(IVec3::ZERO).all() && corner.cmplt(IVec3::splat(texture_size as i32)).all()
+}
+
+/// Corners of the regions of a world of `texture_size`.
+fn region_corners(region_size: u32, texture_size: u32) -> impl Iterator<Item = IVec3> {
+    let count = (texture_size / region_size) as i32;
+    (0..count * count * count).map(move |i| {
+        IVec3::new(i / (count * count), i / count % count, i % count) * region_size as i32
+    })
+}
+
+/// Starts loading the regions the next job needs. The world moves if the focus at `focus` is more
+/// than a region from its center, otherwise only regions asked for are loaded and saved.
+pub(crate) fn start_stream_job(
+    streaming: &mut VoxelStreaming,
+    floating_origin: &FloatingOrigin,
+    focus: Option<Vec3>,
+    texture_size: u32,
+) -> Option<StreamJob> {
+    // world_to_voxel keeps the center of the world at the origin
+    let region_size = streaming.store.region_size as i32;
+    let shift = focus.map_or(IVec3::ZERO, |focus| {
+        (focus * VOXELS_PER_METER / region_size as f32).as_ivec3() * region_size
+    });
+
+    let mut loads = Vec::new();
+    for corner in region_corners(region_size as u32, texture_size) {
+        let old_corner = corner + shift;
+        if !in_world(old_corner, texture_size) {
+            let region = streaming
+                .store
+                .region_at(floating_origin.to_global(old_corner));
+            loads.push((corner.as_uvec3(), region));
+        }
+    }
+    for region in std::mem::take(&mut streaming.loads) {
+        let corner = floating_origin.to_local(region * region_size) - shift;
+        if !in_world(corner, texture_size) {
+            warn!("Region {} is not in the loaded voxel world", region);
+            continue;
+        }
+        if !loads.iter().any(|(_, loaded)| *loaded == region) {
+            loads.push((corner.as_uvec3(), region));
+        }
+    }
+    if shift == IVec3::ZERO && loads.is_empty() && !streaming.flush {
+        return None;
+    }
+
+    let store = streaming.store.clone();
+    let generator = streaming.generator.clone();
+    let seed = streaming.seed;
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        loads
+            .into_iter()
+            .map(|(corner, region)| {
+                let gh = load_region(&store, generator.as_deref(), seed, region);
+                (corner, gh)
+            })
+            .collect()
+    });
+    Some(StreamJob::Loading {
+        shift,
+        texture_size,
+        task,
+    })
+}
+
+/// Loads a region from the store, or generates it if it was never saved.
+fn load_region(
+    store: &RegionStore,
+    generator: Option<&dyn VoxelWorldGenerator>,
+    seed: u64,
+    region: IVec3,
+) -> GH {
+    match store.load(region) {
+        Ok(Some(gh)) => return gh,
+        Ok(None) => {}
+        Err(error) => error!("{}, generating it again", error),
+    }
+
+    let mut gh = GH::empty(store.region_size);
+    if let Some(generator) = generator {
+        generator.generate_region(&mut gh, region * store.region_size as i32, seed);
+    }
+    gh
+}
+
+/// Takes the dirty regions that have to be saved before the world moves by `shift`, those that go
+/// out of view or every one of them for a `flush`. Regions that are loaded again lose their
+/// changes.
+pub(crate) fn take_saves(
+    streaming: &mut VoxelStreaming,
+    floating_origin: &FloatingOrigin,
+    shift: IVec3,
+    regions: &[(UVec3, GH)],
+    texture_size: u32,
+) -> Vec<(UVec3, IVec3)> {
+    let flush = std::mem::take(&mut streaming.flush);
+    let mut saves = Vec::new();
+    for corner in region_corners(streaming.store.region_size, texture_size) {
+        let region = streaming.store.region_at(floating_origin.to_global(corner));
+        let leaving = !in_world(corner - shift, texture_size);
+        if (flush || leaving) && streaming.dirty.remove(&region) {
+            saves.push((corner.as_uvec3(), region));
+        }
+    }
+    for (corner, _) in regions {
+        let global = floating_origin.to_global(corner.as_ivec3() + shift);
+        let region = streaming.store.region_at(global);
+        streaming.dirty.remove(&region);
+    }
+    saves
+}
+
+/// Copies the region with its corner at `corner` out of `gh`.
+pub(crate) fn copy_region(gh: &GH, corner: UVec3, region_size: u32) -> GH {
+    let mut region = GH::empty(region_size);
+    region.pallete = gh.pallete.clone();
+    region.materials = gh.materials;
+    region.copy_bricks(gh, corner, UVec3::ZERO, UVec3::splat(region_size));
+    region
+}
+
+/// Moves `gh` by `shift` and places `regions` on top, for worlds kept on the cpu. Only the bricks
+/// of the regions are written when the world stays where it is.
+pub(crate) fn shift_world(gh: &mut GH, shift: IVec3, regions: &[(UVec3, GH)]) {
+    if shift != IVec3::ZERO {
+        let mut shifted = GH::empty(gh.texture_size);
+        shifted.pallete = gh.pallete.clone();
+        shifted.materials = gh.materials;
+        let kept = IVec3::splat(gh.texture_size as i32) - shift.abs();
+        if kept.cmpgt(IVec3::ZERO).all() {
+            shifted.copy_bricks(
+                gh,
+                shift.max(IVec3::ZERO).as_uvec3(),
+                (-shift).max(IVec3::ZERO).as_uvec3(),
+                kept.as_uvec3(),
+            );
+        }
+        *gh = shifted;
+    }
+    for (corner, region) in regions {
+        gh.copy_bricks(
+            region,
+            UVec3::ZERO,
+            *corner,
+            UVec3::splat(region.texture_size),
+        );
+    }
+}
+
+/// Saves `regions` to `store` on the `AsyncComputeTaskPool`, the task gives the regions that
+/// could not be saved.
+pub(crate) fn spawn_saves(store: &RegionStore, regions: Vec<(IVec3, GH)>) -> StreamJob {
+    if regions.is_empty() {
+        return StreamJob::None;
+    }
+
+    let store = store.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let mut failed = Vec::new();
+        for (region, gh) in regions {
+            if let Err(error) = store.save(region, &gh) {
+                let path = store.region_path(region);
+                error!("Failed to save region {}: {}", path.display(), error);
+                failed.push(region);
+            }
+        }
+        failed
+    });
+    StreamJob::Saving(task)
+}
+
+/// Moves the `FloatingOrigin` and everything without a parent along with a shifted world.
+pub(crate) fn finish_shift<'a>(
+    shift: IVec3,
+    floating_origin: &mut FloatingOrigin,
+    transforms: impl Iterator<Item = Mut<'a, Transform>>,
+    shift_events: &mut EventWriter<VoxelWorldShifted>,
+) {
+    if shift == IVec3::ZERO {
+        return;
+    }
+
+    floating_origin.offset += shift;
+    let translation = -shift.as_vec3() / VOXELS_PER_METER;
+    for mut transform in transforms {
+        transform.translation += translation;
+    }
+    shift_events.send(VoxelWorldShifted { shift, translation });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPool;
+    use futures_lite::future;
+
+    #[test]
+    fn loaded_regions_only_replace_their_bricks() {
+        let mut gh = GH::empty(64);
+        gh.set_voxel(UVec3::new(1, 1, 1), 1, 0);
+        gh.set_voxel(UVec3::new(40, 40, 40), 2, 0);
+        let mut region = GH::empty(32);
+        region.set_voxel(UVec3::new(2, 2, 2), 3, 0);
+
+        shift_world(&mut gh, IVec3::ZERO, &[(UVec3::ZERO, region)]);
+        assert_eq!(gh.get_voxel(UVec3::new(1, 1, 1)), (0, 0));
+        assert_eq!(gh.get_voxel(UVec3::new(2, 2, 2)), (3, 0));
+        assert_eq!(gh.get_voxel(UVec3::new(40, 40, 40)), (2, 0));
+        assert_eq!(gh.brick_count(), 2);
+    }
+
+    #[test]
+    fn shifted_worlds_move_their_voxels() {
+        let mut gh = GH::empty(64);
+        gh.set_voxel(UVec3::new(40, 1, 1), 1, 0);
+        gh.set_voxel(UVec3::new(1, 1, 1), 2, 0);
+
+        shift_world(&mut gh, IVec3::new(32, 0, 0), &[]);
+        assert_eq!(gh.get_voxel(UVec3::new(8, 1, 1)), (1, 0));
+        assert_eq!(gh.voxels().count(), 1);
+    }
+
+    #[test]
+    fn failed_saves_are_dirty_again() {
+        AsyncComputeTaskPool::init(TaskPool::new);
+        // a file where the store expects a directory
+        let file = std::env::temp_dir().join(format!("voxel-regions-{}", std::process::id()));
+        std::fs::write(&file, []).unwrap();
+        let mut streaming = VoxelStreaming::new(RegionStore::new(&file, 32));
+        streaming.mark_dirty(IVec3::ZERO, IVec3::ZERO);
+        let saves = take_saves(
+            &mut streaming,
+            &FloatingOrigin::default(),
+            IVec3::ZERO,
+            &[],
+            64,
+        );
+        assert!(saves.is_empty());
+        streaming.flush();
+        let saves = take_saves(
+            &mut streaming,
+            &FloatingOrigin::default(),
+            IVec3::ZERO,
+            &[],
+            64,
+        );
+        assert_eq!(saves, [(UVec3::ZERO, IVec3::ZERO)]);
+        assert!(!streaming.is_dirty(IVec3::ZERO));
+
+        let saves = vec![(IVec3::ZERO, GH::empty(32))];
+        let StreamJob::Saving(task) = spawn_saves(&streaming.store, saves) else {
+            panic!("nothing to save");
+        };
+        let failed = future::block_on(task);
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(failed, [IVec3::ZERO]);
+        streaming.mark_unsaved(failed);
+        assert!(streaming.is_dirty(IVec3::ZERO));
+    }
+}
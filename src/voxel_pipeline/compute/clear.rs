@@ -1,4 +1,5 @@
 use crate::{
+    load::BRICK_SIZE,
     voxel_pipeline::voxel_world::{VoxelData, VoxelUniforms},
     RenderGraphSettings,
 };
@@ -46,7 +47,8 @@ impl render_graph::Node for ClearNode {
         let voxel_data = world.resource::<VoxelData>();
         let voxel_uniforms = world.resource::<VoxelUniforms>();
         let pipeline_cache = world.resource::<PipelineCache>();
-        let dispatch_size = voxel_uniforms.texture_size / 4;
+        // a workgroup for every brick
+        let dispatch_size = voxel_uniforms.texture_size / BRICK_SIZE;
         let render_graph_settings = world.get_resource::<RenderGraphSettings>().unwrap();
 
         if !render_graph_settings.clear {
@@ -0,0 +1,74 @@
+use super::{ComputeData, EditData};
+use crate::voxel_pipeline::voxel_world::VoxelData;
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::{borrow::Cow, sync::atomic::Ordering};
+
+pub struct EditNode;
+
+#[derive(Resource)]
+pub struct Pipeline(CachedComputePipelineId);
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let compute_bind_group_layout = world.resource::<ComputeData>().bind_group_layout.clone();
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("edit pipeline")),
+            layout: vec![voxel_bind_group_layout, compute_bind_group_layout],
+            shader: super::EDIT_SHADER_HANDLE.typed(),
+            shader_defs: vec![],
+            entry_point: Cow::from("edit"),
+            push_constant_ranges: vec![],
+        });
+
+        Pipeline(update_pipeline)
+    }
+}
+
+impl render_graph::Node for EditNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let compute_data = world.resource::<ComputeData>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let edit_data = world.resource::<EditData>();
+
+        let pipeline = match pipeline_cache.get_compute_pipeline(world.resource::<Pipeline>().0) {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+        // edits are only taken off the queue once this is set, so a batch is never lost
+        edit_data.pipeline_ready.store(true, Ordering::Relaxed);
+
+        let dispatch_size = edit_data.dispatch_size;
+        if dispatch_size.cmpeq(UVec3::ZERO).any() {
+            return Ok(());
+        }
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+        pass.set_bind_group(1, &compute_data.bind_group, &[]);
+
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups(dispatch_size.x, dispatch_size.y, dispatch_size.z);
+
+        Ok(())
+    }
+}
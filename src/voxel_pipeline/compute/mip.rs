@@ -1,4 +1,5 @@
 use crate::{
+    load::BRICK_SIZE,
     voxel_pipeline::voxel_world::{VoxelData, VoxelUniforms},
     RenderGraphSettings,
 };
@@ -44,7 +45,7 @@ impl FromWorld for Pipeline {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadOnly,
+                            access: StorageTextureAccess::ReadWrite,
                             format: TextureFormat::R16Uint,
                             view_dimension: TextureViewDimension::D3,
                         },
@@ -60,6 +61,16 @@ impl FromWorld for Pipeline {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
                 ],
             });
         let mip_bind_group_layout =
@@ -157,12 +168,16 @@ impl render_graph::Node for MipNode {
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::TextureView(&voxel_data.voxel_world),
+                    resource: BindingResource::TextureView(&voxel_data.brick_atlas),
                 },
                 BindGroupEntry {
                     binding: 2,
                     resource: BindingResource::TextureView(&texture_view),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: voxel_data.brick_grid.as_entire_binding(),
+                },
             ],
         });
 
@@ -181,6 +196,9 @@ impl render_graph::Node for MipNode {
             ],
         });
 
+        // the mip texture starts with a texel for every brick
+        let grid_size = voxel_uniforms.texture_size / BRICK_SIZE;
+
         // copy mip texture
         let copy_pipeline = match pipeline_cache.get_compute_pipeline(pipelines.copy_pipeline) {
             Some(pipeline) => pipeline,
@@ -192,12 +210,11 @@ impl render_graph::Node for MipNode {
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            let dispatch_size = voxel_uniforms.texture_size / 4;
             pass.set_bind_group(0, &copy_bind_group, &[]);
             pass.set_bind_group(1, &mip_bind_group, &[]);
 
             pass.set_pipeline(copy_pipeline);
-            pass.dispatch_workgroups(dispatch_size, dispatch_size, dispatch_size);
+            pass.dispatch_workgroups(grid_size, grid_size, grid_size);
         }
 
         // mip texture
@@ -206,7 +223,7 @@ impl render_graph::Node for MipNode {
             None => return Ok(()),
         };
 
-        for i in 1..voxel_uniforms.texture_size.trailing_zeros() {
+        for i in 1..grid_size.trailing_zeros() {
             let from_view = voxel_data.mip_texture.create_view(&TextureViewDescriptor {
                 base_mip_level: i - 1,
                 mip_level_count: NonZeroU32::new(1),
@@ -237,7 +254,7 @@ impl render_graph::Node for MipNode {
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            let dispatch_size = voxel_uniforms.texture_size / 4 / (i + 1);
+            let dispatch_size = ((grid_size >> i) + 3) / 4;
             pass.set_bind_group(0, &copy_bind_group, &[]);
             pass.set_bind_group(1, &mip_bind_group, &[]);
 
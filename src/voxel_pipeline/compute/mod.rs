@@ -10,15 +10,29 @@ use bevy::{
     },
     utils::HashMap,
 };
+use std::sync::{atomic::AtomicBool, Arc};
 
 pub mod animation;
 pub mod automata;
 pub mod clear;
+pub mod edit;
 pub mod mip;
 pub mod physics;
+pub mod query;
 pub mod rebuild;
+pub mod stream;
 
-const MAX_TYPE_BUFFER_DATA: usize = 1000000; // 4mb
+pub(crate) const MAX_TYPE_BUFFER_DATA: usize = 1000000; // 4mb
+/// Most edits that are applied in one frame.
+pub const MAX_EDITS: usize = 4096;
+/// Words in the edit buffer for each edit, after the 4 word header.
+pub const EDIT_WORDS: usize = 13;
+/// Most queries that are read back at once.
+pub const MAX_QUERIES: usize = 4096;
+/// Words in the query buffer for each query, after the 2 word header.
+pub const QUERY_WORDS: usize = 9;
+/// Most result words that are read back at once, one for each voxel of a point or box query.
+pub const MAX_QUERY_RESULTS: usize = 1 << 20;
 
 pub const ANIMATION_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 7356431584756113968);
@@ -26,12 +40,18 @@ pub const AUTOMATA_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2461997473694366307);
 pub const CLEAR_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 15320669235097444653);
+pub const EDIT_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 11862730912473606151);
 pub const MIP_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 6189143918759879663);
 pub const PHYSICS_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 5103938181551247167);
+pub const QUERY_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 4409288157327018493);
 pub const REBUILD_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 18135969847573717619);
+pub const STREAM_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 9924316402751186327);
 
 pub struct ComputeResourcesPlugin;
 
@@ -55,6 +75,12 @@ impl Plugin for ComputeResourcesPlugin {
             "../shaders/compute/clear.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            EDIT_SHADER_HANDLE,
+            "../shaders/compute/edit.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             MIP_SHADER_HANDLE,
@@ -67,12 +93,24 @@ impl Plugin for ComputeResourcesPlugin {
             "../shaders/compute/physics.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            QUERY_SHADER_HANDLE,
+            "../shaders/compute/query.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             REBUILD_SHADER_HANDLE,
             "../shaders/compute/rebuild.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            STREAM_SHADER_HANDLE,
+            "../shaders/compute/stream.wgsl",
+            Shader::from_wgsl
+        );
 
         let render_device = app.world.resource::<RenderDevice>();
         let render_queue = app.world.resource::<RenderQueue>();
@@ -98,6 +136,26 @@ impl Plugin for ComputeResourcesPlugin {
             label: None,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
+        let edit_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&vec![0u32; 4 + MAX_EDITS * EDIT_WORDS]),
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        let query_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&vec![0u32; 2 + MAX_QUERIES * QUERY_WORDS]),
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        let results_buffer_gpu = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&vec![0u32; MAX_QUERY_RESULTS]),
+            label: None,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        });
+        let results_buffer_cpu = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            contents: bytemuck::cast_slice(&vec![0u32; MAX_QUERY_RESULTS]),
+            label: None,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        });
 
         let bind_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -133,6 +191,36 @@ impl Plugin for ComputeResourcesPlugin {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -152,6 +240,18 @@ impl Plugin for ComputeResourcesPlugin {
                     binding: 2,
                     resource: animation_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: edit_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: query_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: results_buffer_gpu.as_entire_binding(),
+                },
             ],
         });
 
@@ -166,8 +266,22 @@ impl Plugin for ComputeResourcesPlugin {
             dispatch_size: 0,
             animation_buffer,
         })
+        .insert_resource(EditData {
+            dispatch_size: UVec3::ZERO,
+            edit_buffer,
+            pipeline_ready: Arc::new(AtomicBool::new(false)),
+        })
         .add_plugin(ExtractResourcePlugin::<PhysicsData>::default())
-        .add_plugin(ExtractResourcePlugin::<AnimationData>::default());
+        .add_plugin(ExtractResourcePlugin::<AnimationData>::default())
+        .insert_resource(QueryData {
+            result_length: 0,
+            query_buffer,
+            results_buffer_gpu,
+            results_buffer_cpu,
+            pipeline_ready: Arc::new(AtomicBool::new(false)),
+        })
+        .add_plugin(ExtractResourcePlugin::<EditData>::default())
+        .add_plugin(ExtractResourcePlugin::<QueryData>::default());
 
         app.sub_app_mut(RenderApp)
             .insert_resource(ComputeData {
@@ -176,11 +290,14 @@ impl Plugin for ComputeResourcesPlugin {
                 uniform_buffer,
             })
             .init_resource::<clear::Pipeline>()
+            .init_resource::<edit::Pipeline>()
             .init_resource::<rebuild::Pipeline>()
             .init_resource::<automata::Pipeline>()
             .init_resource::<physics::Pipeline>()
+            .init_resource::<query::Pipeline>()
             .init_resource::<animation::Pipeline>()
             .init_resource::<mip::Pipeline>()
+            .init_resource::<stream::Pipeline>()
             .add_system(prepare_uniforms.in_set(RenderSet::Prepare));
     }
 }
@@ -222,6 +339,27 @@ pub struct AnimationData {
     pub animation_buffer: Buffer,
 }
 
+#[derive(Clone, Resource, ExtractResource)]
+pub struct EditData {
+    /// Workgroups covering the voxels touched by this frames edits, zero when there are none.
+    pub dispatch_size: UVec3,
+    pub edit_buffer: Buffer,
+    /// Set by the `EditNode` once the edit pipeline has compiled, edits stay queued until then.
+    pub pipeline_ready: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Resource, ExtractResource)]
+pub struct QueryData {
+    /// Result words written by this frames queries, zero when nothing was submitted.
+    pub result_length: u32,
+    pub query_buffer: Buffer,
+    pub results_buffer_gpu: Buffer,
+    pub results_buffer_cpu: Buffer,
+    /// Set by the `QueryNode` once the query pipeline has compiled, queries stay pending until
+    /// then.
+    pub pipeline_ready: Arc<AtomicBool>,
+}
+
 #[derive(Resource)]
 pub struct ComputeData {
     pub bind_group_layout: BindGroupLayout,
@@ -0,0 +1,84 @@
+use super::{ComputeData, QueryData};
+use crate::voxel_pipeline::voxel_world::VoxelData;
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, NodeRunError, RenderGraphContext},
+        render_resource::*,
+        renderer::RenderContext,
+    },
+};
+use std::{borrow::Cow, sync::atomic::Ordering};
+
+pub struct QueryNode;
+
+#[derive(Resource)]
+pub struct Pipeline(CachedComputePipelineId);
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let compute_bind_group_layout = world.resource::<ComputeData>().bind_group_layout.clone();
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("query pipeline")),
+            layout: vec![voxel_bind_group_layout, compute_bind_group_layout],
+            shader: super::QUERY_SHADER_HANDLE.typed(),
+            shader_defs: vec![],
+            entry_point: Cow::from("query"),
+            push_constant_ranges: vec![],
+        });
+
+        Pipeline(update_pipeline)
+    }
+}
+
+impl render_graph::Node for QueryNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let voxel_data = world.resource::<VoxelData>();
+        let compute_data = world.resource::<ComputeData>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let query_data = world.resource::<QueryData>();
+
+        let pipeline = match pipeline_cache.get_compute_pipeline(world.resource::<Pipeline>().0) {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+        // queries are only submitted once this is set, so a batch always gets its results
+        query_data.pipeline_ready.store(true, Ordering::Relaxed);
+
+        if query_data.result_length == 0 {
+            return Ok(());
+        }
+
+        {
+            let mut pass = render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor::default());
+
+            pass.set_bind_group(0, &voxel_data.bind_group, &[]);
+            pass.set_bind_group(1, &compute_data.bind_group, &[]);
+
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups((query_data.result_length + 63) / 64, 1, 1);
+        }
+
+        // copy the results so the main world can map them once the frame is done
+        render_context.command_encoder().copy_buffer_to_buffer(
+            &query_data.results_buffer_gpu,
+            0,
+            &query_data.results_buffer_cpu,
+            0,
+            query_data.result_length as u64 * 4,
+        );
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    load::GH,
+    load::{BRICK_SIZE, GH},
     voxel_pipeline::voxel_world::{VoxelData, VoxelUniforms},
     RenderGraphSettings,
 };
@@ -48,7 +48,8 @@ impl render_graph::Node for RebuildNode {
         let voxel_uniforms = world.resource::<VoxelUniforms>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let render_queue = world.resource::<RenderQueue>();
-        let dispatch_size = voxel_uniforms.texture_size / 4;
+        // a workgroup for every brick
+        let dispatch_size = voxel_uniforms.texture_size / BRICK_SIZE;
         let render_graph_settings = world.get_resource::<RenderGraphSettings>().unwrap();
 
         if !render_graph_settings.rebuild {
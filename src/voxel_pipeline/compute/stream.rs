@@ -0,0 +1,69 @@
+use crate::{load::BRICK_VOXELS, voxel_pipeline::voxel_world::VoxelData};
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, renderer::RenderDevice},
+};
+use std::borrow::Cow;
+
+/// Words in the stream buffer for each brick, its position in the brick grid and its voxels two to
+/// a word, after the 4 word header.
+pub const STREAM_BRICK_WORDS: usize = 3 + BRICK_VOXELS / 2;
+/// Bricks past this many workgroups along x carry on along y.
+pub const STREAM_DISPATCH_WIDTH: u32 = 65535;
+
+/// Copies bricks of the world into and out of a buffer, for `VoxelStreaming`. Unlike the other
+/// passes these run while preparing the frame, see `stream_voxel_world_prepare`.
+#[derive(Resource)]
+pub struct Pipeline {
+    pub bind_group_layout: BindGroupLayout,
+    pub save: CachedComputePipelineId,
+    pub load: CachedComputePipelineId,
+}
+
+impl FromWorld for Pipeline {
+    fn from_world(world: &mut World) -> Self {
+        let voxel_bind_group_layout = world.resource::<VoxelData>().bind_group_layout.clone();
+        let bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("stream bind group layout"),
+                    entries: &[BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    }],
+                });
+
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let queue_pipeline = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("stream pipeline")),
+                layout: vec![voxel_bind_group_layout.clone(), bind_group_layout.clone()],
+                shader: super::STREAM_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+                push_constant_ranges: vec![],
+            })
+        };
+        let save = queue_pipeline("save");
+        let load = queue_pipeline("load");
+
+        Pipeline {
+            bind_group_layout,
+            save,
+            load,
+        }
+    }
+}
+
+/// Workgroups to dispatch for `bricks` bricks, one for each.
+pub fn dispatch_size(bricks: u32) -> (u32, u32) {
+    let rows = (bricks + STREAM_DISPATCH_WIDTH - 1) / STREAM_DISPATCH_WIDTH;
+    (bricks.min(STREAM_DISPATCH_WIDTH), rows)
+}
@@ -1,8 +1,9 @@
 use self::{
     attachments::{AttachmentsNode, AttachmentsPlugin},
     compute::{
-        animation::AnimationNode, automata::AutomataNode, clear::ClearNode, mip::MipNode,
-        physics::PhysicsNode, rebuild::RebuildNode, ComputeResourcesPlugin,
+        animation::AnimationNode, automata::AutomataNode, clear::ClearNode, edit::EditNode,
+        mip::MipNode, physics::PhysicsNode, query::QueryNode, rebuild::RebuildNode,
+        ComputeResourcesPlugin,
     },
     denoise::{DenoiseNode, DenoisePlugin},
     trace::{TraceNode, TracePlugin},
@@ -106,12 +107,18 @@ impl Plugin for RenderPlugin {
         // main graph compute
         let mut graph = render_app.world.resource_mut::<RenderGraph>();
         let clear = ClearNode;
+        let edit = EditNode;
+        let query = QueryNode;
         let automata = AutomataNode;
         let animation = AnimationNode;
         graph.add_node("clear", clear);
+        graph.add_node("edit", edit);
+        graph.add_node("query", query);
         graph.add_node("automata", automata);
         graph.add_node("animation", animation);
-        graph.add_node_edge("clear", "automata");
+        graph.add_node_edge("clear", "edit");
+        graph.add_node_edge("edit", "query");
+        graph.add_node_edge("query", "automata");
         graph.add_node_edge("automata", "animation");
         graph.add_node_edge("animation", CAMERA_DRIVER);
 
@@ -0,0 +1,97 @@
+#define_import_path bevy_voxel_engine::bricks
+
+// Voxels are stored in bricks of BRICK_SIZE^3 voxels in brick_atlas. brick_grid has an entry for
+// every brick of the world, 0 for bricks of air and 1 + the brick's slot in the atlas otherwise,
+// followed by how many slots are in use and how many writes were dropped because the atlas was
+// full. Bricks get a slot the first time anything but air is written to them, and keep it until
+// the render world rebuilds the atlas when it runs low on slots.
+
+const BRICK_SIZE: u32 = 16u;
+// passes over the whole world run a 4x4x4 workgroup for every brick
+const BRICK_THREAD_VOXELS: u32 = 64u;
+
+fn in_voxel_world(pos: vec3<i32>) -> bool {
+    return all(pos >= vec3(0)) && all(pos < vec3(i32(voxel_uniforms.texture_size)));
+}
+
+fn brick_index(brick: vec3<u32>) -> u32 {
+    let size = voxel_uniforms.texture_size / BRICK_SIZE;
+    return brick.x * size * size + brick.y * size + brick.z;
+}
+
+// 0 for bricks of air, 1 + the slot of the brick otherwise
+fn get_brick(brick: vec3<u32>) -> u32 {
+    return atomicLoad(&brick_grid[brick_index(brick)]);
+}
+
+// slots fill the atlas along x, then y, then z, like the voxel world the atlas is indexed with
+// pos.zyx
+fn atlas_pos(slot: u32, pos: vec3<i32>) -> vec3<i32> {
+    let size = voxel_uniforms.atlas_size;
+    let origin = vec3(slot % size, (slot / size) % size, slot / (size * size)) * BRICK_SIZE;
+    return vec3<i32>(origin) + (pos.zyx & vec3(i32(BRICK_SIZE) - 1));
+}
+
+// material | flags << 8 of the voxel at pos, 0 outside of the world
+fn load_voxel(pos: vec3<i32>) -> u32 {
+    if (!in_voxel_world(pos)) {
+        return 0u;
+    }
+    let brick = get_brick(vec3<u32>(pos) / BRICK_SIZE);
+    if (brick == 0u) {
+        return 0u;
+    }
+    return textureLoad(brick_atlas, atlas_pos(brick - 1u, pos)).r;
+}
+
+// gives the brick at index a slot if it has none, 0 when the atlas is full
+fn allocate_brick(index: u32) -> u32 {
+    let count = arrayLength(&brick_grid) - 2u;
+    if (atomicLoad(&brick_grid[count]) >= voxel_uniforms.brick_capacity) {
+        return 0u;
+    }
+    let slot = atomicAdd(&brick_grid[count], 1u);
+    if (slot >= voxel_uniforms.brick_capacity) {
+        return 0u;
+    }
+
+    // if another invocation got there first its brick is used and this slot stays empty
+    loop {
+        let result = atomicCompareExchangeWeak(&brick_grid[index], 0u, slot + 1u);
+        if (result.exchanged) {
+            return slot + 1u;
+        }
+        if (result.old_value != 0u) {
+            return result.old_value;
+        }
+    }
+    return 0u;
+}
+
+// writes are dropped outside of the world and once the atlas is full, those are counted so the
+// atlas can be grown
+fn store_voxel(pos: vec3<i32>, value: u32) {
+    if (!in_voxel_world(pos)) {
+        return;
+    }
+    let index = brick_index(vec3<u32>(pos) / BRICK_SIZE);
+    var brick = atomicLoad(&brick_grid[index]);
+    if (brick == 0u) {
+        // bricks of air are already air
+        if (value == 0u) {
+            return;
+        }
+        brick = allocate_brick(index);
+        if (brick == 0u) {
+            atomicAdd(&brick_grid[arrayLength(&brick_grid) - 1u], 1u);
+            return;
+        }
+    }
+    textureStore(brick_atlas, atlas_pos(brick - 1u, pos), vec4(value));
+}
+
+// the i-th of the BRICK_THREAD_VOXELS voxels a thread of a workgroup for a brick goes through
+fn brick_voxel(brick: vec3<u32>, thread: vec3<u32>, i: u32) -> vec3<i32> {
+    let step = vec3(i & 3u, (i >> 2u) & 3u, i >> 4u);
+    return vec3<i32>(brick * BRICK_SIZE + step * 4u + thread);
+}
@@ -14,12 +14,44 @@ struct Portal {
     normal: vec3<f32>,
 }
 
+struct MaterialProperties {
+    roughness: f32,
+    metalness: f32,
+    transparency: f32,
+    emission: f32,
+};
+
+struct GameplayProperties {
+    density: f32,
+    flammability: f32,
+    hardness: f32,
+    friction: f32,
+    flags: u32,
+    emissive: u32,
+    // uniform arrays need a stride of 16 bytes
+    _padding: vec2<u32>,
+};
+
+// palette indices the automata give behaviour to, 0 when there is no such material
+struct MaterialIds {
+    water: u32,
+    fire: u32,
+    fire_count: u32,
+    dirt: u32,
+    grass: u32,
+};
+
 struct VoxelUniforms {
     materials: array<vec4<f32>, 256>,
+    material_properties: array<MaterialProperties, 256>,
+    gameplay_properties: array<GameplayProperties, 256>,
+    material_ids: MaterialIds,
     portals: array<Portal, 32>,
     levels: array<vec4<u32>, 8>,
     offsets: array<vec4<u32>, 8>,
     texture_size: u32,
+    atlas_size: u32,
+    brick_capacity: u32,
 };
 
 struct TraceUniforms {
@@ -3,9 +3,11 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 struct ComputeUniforms {
     time: f32,
@@ -17,8 +19,10 @@ var<uniform> compute_uniforms: ComputeUniforms;
 @group(1) @binding(2)
 var<storage, read> animation_data: array<u32>;
 
+#import bevy_voxel_engine::bricks
+
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
-    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    let texture_value = load_voxel(pos);
     return vec2(
         texture_value & 0xFFu,
         texture_value >> 8u,
@@ -28,7 +32,7 @@ fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
 fn write_pos(pos: vec3<i32>, material: u32, flags: u32) {
     let voxel_type = get_texture_value(pos);
     if (voxel_type.x == 0u) {
-        textureStore(voxel_world, pos.zyx, vec4(material | (flags << 8u)));
+        store_voxel(pos, material | (flags << 8u));
     }
 }
 
@@ -91,6 +95,51 @@ fn animation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     }
                 }
             }
+        } else if (data_type == 3) {
+            // models, texture_pos is the corner of the box they cover
+            let max_pos = vec3(
+                bitcast<i32>(animation_data[data_index + 5]),
+                bitcast<i32>(animation_data[data_index + 6]),
+                bitcast<i32>(animation_data[data_index + 7]),
+            );
+            let world_to_model = mat3x3<f32>(
+                bitcast<f32>(animation_data[data_index + 8]),
+                bitcast<f32>(animation_data[data_index + 9]),
+                bitcast<f32>(animation_data[data_index + 10]),
+                bitcast<f32>(animation_data[data_index + 11]),
+                bitcast<f32>(animation_data[data_index + 12]),
+                bitcast<f32>(animation_data[data_index + 13]),
+                bitcast<f32>(animation_data[data_index + 14]),
+                bitcast<f32>(animation_data[data_index + 15]),
+                bitcast<f32>(animation_data[data_index + 16]),
+            );
+            let translation = vec3(
+                bitcast<f32>(animation_data[data_index + 17]),
+                bitcast<f32>(animation_data[data_index + 18]),
+                bitcast<f32>(animation_data[data_index + 19]),
+            );
+            let size = vec3(
+                bitcast<i32>(animation_data[data_index + 20]),
+                bitcast<i32>(animation_data[data_index + 21]),
+                bitcast<i32>(animation_data[data_index + 22]),
+            );
+            let model_index = header_len + 1 + i32(animation_data[data_index + 23]);
+            for (var x = texture_pos.x; x <= max_pos.x; x++) {
+                for (var y = texture_pos.y; y <= max_pos.y; y++) {
+                    for (var z = texture_pos.z; z <= max_pos.z; z++) {
+                        // sample the model at the center of every voxel
+                        let pos = vec3(x, y, z);
+                        let model_pos = vec3<i32>(floor(world_to_model * (vec3<f32>(pos) + 0.5) + translation));
+                        if (all(model_pos >= vec3(0)) && all(model_pos < size)) {
+                            let i = model_pos.x * size.y * size.z + model_pos.y * size.z + model_pos.z;
+                            let model_material = (animation_data[model_index + i / 4] >> (u32(i % 4) * 8u)) & 0xFFu;
+                            if (model_material != 0u) {
+                                write_pos(pos, select(material, model_material, material == 0u), flags);
+                            }
+                        }
+                    }
+                }
+            }
         }
     }
 }
\ No newline at end of file
@@ -3,9 +3,11 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 struct ComputeUniforms {
     time: f32,
@@ -17,32 +19,43 @@ var<uniform> compute_uniforms: ComputeUniforms;
 @group(1) @binding(1)
 var<storage, read_write> physics_data: array<u32>;
 
-fn in_texture_bounds(pos: vec3<i32>) -> bool {
-    return all(pos >= vec3(0)) && all(pos < vec3(i32(voxel_uniforms.texture_size)));
-}
+#import bevy_voxel_engine::bricks
 
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
-    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    let texture_value = load_voxel(pos);
     return vec2(
         texture_value & 0xFFu,
         texture_value >> 8u,
     );
 }
 
+fn is_fire(material: u32) -> bool {
+    let ids = voxel_uniforms.material_ids;
+    return ids.fire != 0u && material >= ids.fire && material < ids.fire + ids.fire_count;
+}
+
+// falling voxels swap places with air and with lighter voxels that are not solid
+fn sinks_into(material: u32, below: vec2<u32>) -> bool {
+    if (below.x == 0u) {
+        return true;
+    }
+    return (below.y & (COLLISION_FLAG | ANIMATION_FLAG)) == 0u
+        && voxel_uniforms.gameplay_properties[below.x].density < voxel_uniforms.gameplay_properties[material].density;
+}
+
 fn write_pos(pos: vec3<i32>, material: u32, flags: u32) {
     let voxel_type = get_texture_value(pos);
     if (voxel_type.x == 0u) {
-        textureStore(voxel_world, pos.zyx, vec4(material | (flags << 8u)));
+        store_voxel(pos, material | (flags << 8u));
     }
 }
 
-@compute @workgroup_size(4, 4, 4)
-fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
+fn update_voxel(pos: vec3<i32>) {
     let pos_seed = vec3<u32>(vec3<f32>(pos));
     let pos_time_seed = vec3<u32>(vec3<f32>(pos) + compute_uniforms.time * 240.0);
 
     let material = get_texture_value(pos);
+    let ids = voxel_uniforms.material_ids;
 
     // grass
     // let pos_rand = hash(pos_seed + 100u);
@@ -66,11 +79,11 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // }
 
     // turn grass to dirt 
-    if (material.x == 44u && (material.y & ANIMATION_FLAG) == 0u) {
+    if (ids.grass != 0u && ids.dirt != 0u && material.x == ids.grass && (material.y & ANIMATION_FLAG) == 0u) {
         let rand = hash(pos_time_seed + 10u);
         let new_mat = get_texture_value(pos + vec3(0, 1, 0));
         if (new_mat.x != 0u && (new_mat.y & ANIMATION_FLAG) == 0u && rand.y < 0.01) {
-            textureStore(voxel_world, pos.zyx, vec4(43u | (material.y << 8u)));
+            store_voxel(pos, ids.dirt | (material.y << 8u));
         }
     }
 
@@ -78,7 +91,7 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // let rand = hash(pos_time_seed + 10u);
     // if (material.x == 44u && (material.y & ANIMATION_FLAG) == 0u && rand.x < 0.02) {
     //     // if (get_texture_value(pos + vec3(0, 1, 0)).x == 0u && rand.z < 0.1) {
-    //     //     textureStore(voxel_world, (pos + vec3(0, 1, 0)).zyx, vec4(44u | (material.y << 8u)));
+    //     //     store_voxel(pos + vec3(0, 1, 0), 44u | (material.y << 8u));
     //     // }
 
     //     // pick a random offset to check
@@ -106,8 +119,8 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     //     let new_pos = pos + offset;
     //     let new_mat = get_texture_value(new_pos);
 
-    //     if (in_texture_bounds(new_pos) && new_mat.x != 0u) {
-    //         textureStore(voxel_world, new_pos.zyx, vec4(material.x | (material.y << 8u)));
+    //     if (in_voxel_world(new_pos) && new_mat.x != 0u) {
+    //         store_voxel(new_pos, material.x | (material.y << 8u));
     //     }
     // }
 
@@ -118,9 +131,9 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         let new_pos = pos + vec3(0, -1, 0);
         let new_mat = get_texture_value(new_pos);
 
-        if (in_texture_bounds(new_pos) && new_mat.x == 0u) {
-            textureStore(voxel_world, new_pos.zyx, vec4(material.x | (material.y << 8u)));
-            textureStore(voxel_world, pos.zyx, vec4(0u));
+        if (in_voxel_world(new_pos) && sinks_into(material.x, new_mat)) {
+            store_voxel(new_pos, material.x | (material.y << 8u));
+            store_voxel(pos, new_mat.x | (new_mat.y << 8u));
         } else {
             let rand = hash(pos_time_seed);
             for (var i = 0; i < 4; i += 1) {
@@ -141,9 +154,9 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                 let new_pos = pos + offset;
                 let new_mat = get_texture_value(new_pos);
 
-                if (in_texture_bounds(new_pos) && new_mat.x == 0u) {
-                    textureStore(voxel_world, new_pos.zyx, vec4(material.x | (material.y << 8u)));
-                    textureStore(voxel_world, pos.zyx, vec4(0u));
+                if (in_voxel_world(new_pos) && new_mat.x == 0u) {
+                    store_voxel(new_pos, material.x | (material.y << 8u));
+                    store_voxel(pos, 0u);
                     break;
                 }
             }
@@ -151,7 +164,7 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     }
 
     // fire
-    if (material.x >= 9u && material.x <= 13u) {
+    if (is_fire(material.x)) {
         let rand = hash(pos_time_seed + 20u);
         let i = i32(5.0 * rand.x);
 
@@ -170,20 +183,21 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
         let new_pos = pos + offset;
         let new_mat = get_texture_value(new_pos);
-        if (in_texture_bounds(new_pos) && new_mat.x == 0u && rand.z > 0.08) {
-            let new_material = min(material.x + u32(rand.y * 1.3), 13u);
+        if (in_voxel_world(new_pos) && new_mat.x == 0u && rand.z > 0.08) {
+            let new_material = min(material.x + u32(rand.y * 1.3), ids.fire + ids.fire_count - 1u);
             let flags = AUTOMATA_FLAG;
-            textureStore(voxel_world, new_pos.zyx, vec4(new_material | (flags << 8u)));
+            store_voxel(new_pos, new_material | (flags << 8u));
         }
 
-        if (rand.y < (f32(material.x) + 7.0) / 20.0 && (material.y & AUTOMATA_FLAG) > 0u) {
-            textureStore(voxel_world, pos.zyx, vec4(0u));
+        // later stages burn out faster
+        if (rand.y < (f32(material.x - ids.fire) + 16.0) / 20.0 && (material.y & AUTOMATA_FLAG) > 0u) {
+            store_voxel(pos, 0u);
         }
     }
 
     // fire spreading
     let rand = hash(pos_time_seed + 30u);
-    if (material.x >= 9u && material.x <= 10u && rand.x < 0.1) {
+    if (is_fire(material.x) && material.x <= ids.fire + 1u && rand.x < 0.1) {
         // pick a random offset to check
         let i = i32(6.0 * rand.y);
 
@@ -205,19 +219,20 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         let new_pos = pos + offset;
         let new_mat = get_texture_value(new_pos);
 
-        if (in_texture_bounds(new_pos) && new_mat.x != 0u && (new_mat.y & COLLISION_FLAG) > 0u) {
-            textureStore(voxel_world, new_pos.zyx, vec4(material.x | (COLLISION_FLAG << 8u)));
+        let flammability = voxel_uniforms.gameplay_properties[new_mat.x].flammability;
+        if (in_voxel_world(new_pos) && new_mat.x != 0u && (new_mat.y & COLLISION_FLAG) > 0u && !is_fire(new_mat.x) && rand.z < flammability) {
+            store_voxel(new_pos, material.x | (COLLISION_FLAG << 8u));
         }
     }
 
     // water
-    if (material.x == 8u && (material.y & ANIMATION_FLAG) == 0u) {
+    if (ids.water != 0u && material.x == ids.water && (material.y & ANIMATION_FLAG) == 0u) {
         let new_pos = pos + vec3(0, -1, 0);
         let new_mat = get_texture_value(new_pos);
 
-        if (in_texture_bounds(new_pos) && new_mat.x == 0u) {
-            textureStore(voxel_world, new_pos.zyx, vec4(material.x | (material.y << 8u)));
-            textureStore(voxel_world, pos.zyx, vec4(0u));
+        if (in_voxel_world(new_pos) && sinks_into(material.x, new_mat)) {
+            store_voxel(new_pos, material.x | (material.y << 8u));
+            store_voxel(pos, new_mat.x | (new_mat.y << 8u));
         } else {
             let rand = hash(pos_time_seed);
             for (var i = 0; i < 4; i += 1) {
@@ -252,7 +267,7 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                         let check_pos = pos + offset + check;
                         let check_mat = get_texture_value(check_pos);
 
-                        if (in_texture_bounds(check_pos) && check_mat.x == 8u) {
+                        if (in_voxel_world(check_pos) && check_mat.x == ids.water) {
                             safe = false;
                             break;
                         }
@@ -263,9 +278,9 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     let new_pos = pos + offset;
                     let new_mat = get_texture_value(new_pos);
 
-                    if (in_texture_bounds(new_pos) && new_mat.x == 0u) {
-                        textureStore(voxel_world, new_pos.zyx, vec4(material.x | (material.y << 8u)));
-                        textureStore(voxel_world, pos.zyx, vec4(0u));
+                    if (in_voxel_world(new_pos) && new_mat.x == 0u) {
+                        store_voxel(new_pos, material.x | (material.y << 8u));
+                        store_voxel(pos, 0u);
                     }
 
                     break;
@@ -273,4 +288,16 @@ fn automata(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn automata(@builtin(workgroup_id) brick: vec3<u32>, @builtin(local_invocation_id) thread: vec3<u32>) {
+    // nothing happens in bricks of air
+    if (get_brick(brick) == 0u) {
+        return;
+    }
+
+    for (var i = 0u; i < BRICK_THREAD_VOXELS; i++) {
+        update_voxel(brick_voxel(brick, thread, i));
+    }
+}
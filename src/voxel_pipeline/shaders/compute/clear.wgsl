@@ -3,27 +3,40 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
+
+#import bevy_voxel_engine::bricks
 
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
-    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    let texture_value = load_voxel(pos);
     return vec2(
         texture_value & 0xFFu,
         texture_value >> 8u,
     );
 }
 
-@compute @workgroup_size(4, 4, 4)
-fn clear(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
-
+fn clear_voxel(pos: vec3<i32>) {
     let material = get_texture_value(pos);
 
     // delete old animaiton data
     if ((material.y & (ANIMATION_FLAG | PORTAL_FLAG)) > 0u) {
-        textureStore(voxel_world, pos.zyx, vec4(0u));
+        store_voxel(pos, 0u);
         return;
     }
-}
\ No newline at end of file
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn clear(@builtin(workgroup_id) brick: vec3<u32>, @builtin(local_invocation_id) thread: vec3<u32>) {
+    // bricks of air have nothing to clear
+    if (get_brick(brick) == 0u) {
+        return;
+    }
+
+    for (var i = 0u; i < BRICK_THREAD_VOXELS; i++) {
+        clear_voxel(brick_voxel(brick, thread, i));
+    }
+}
@@ -0,0 +1,88 @@
+#import bevy_voxel_engine::common
+
+@group(0) @binding(0)
+var<uniform> voxel_uniforms: VoxelUniforms;
+@group(0) @binding(1)
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
+@group(0) @binding(2)
+var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
+
+struct ComputeUniforms {
+    time: f32,
+    delta_time: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> compute_uniforms: ComputeUniforms;
+@group(1) @binding(3)
+var<storage, read> edit_data: array<u32>;
+
+#import bevy_voxel_engine::bricks
+
+const EDIT_WORDS = 13u;
+
+fn edit_ivec3(index: u32) -> vec3<i32> {
+    return vec3(
+        bitcast<i32>(edit_data[index + 0u]),
+        bitcast<i32>(edit_data[index + 1u]),
+        bitcast<i32>(edit_data[index + 2u]),
+    );
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn edit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let edit_count = edit_data[0];
+    let pos = edit_ivec3(1u) + vec3<i32>(invocation_id);
+    if (any(pos >= vec3(i32(voxel_uniforms.texture_size)))) {
+        return;
+    }
+
+    let old_value = load_voxel(pos);
+    var material = old_value & 0xFFu;
+    var flags = old_value >> 8u;
+
+    // edits are applied in the order they were queued
+    for (var i = 0u; i < edit_count; i++) {
+        let index = 4u + i * EDIT_WORDS;
+        let edit_type = edit_data[index];
+        let min = edit_ivec3(index + 1u);
+        let max = edit_ivec3(index + 4u);
+        if (any(pos < min) || any(pos > max)) {
+            continue;
+        }
+
+        let a = edit_data[index + 7u];
+        let b = edit_data[index + 8u];
+        if (edit_type == 0u) {
+            // fill box
+            material = a;
+            flags = b;
+        } else if (edit_type == 1u) {
+            // fill sphere
+            let radius = bitcast<f32>(edit_data[index + 9u]);
+            let offset = vec3<f32>(pos) - vec3<f32>(edit_ivec3(index + 10u));
+            if (length(offset) <= radius) {
+                material = a;
+                flags = b;
+            }
+        } else if (edit_type == 2u) {
+            // replace material
+            if (material == a) {
+                material = b;
+            }
+        } else if (edit_type == 3u) {
+            // set flags
+            flags = flags | a;
+        } else if (edit_type == 4u) {
+            // clear flags
+            flags = flags & ~a;
+        }
+    }
+
+    let new_value = material | (flags << 8u);
+    if (new_value != old_value) {
+        store_voxel(pos, new_value);
+    }
+}
@@ -3,30 +3,61 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var mip_texture: texture_storage_3d<rgba8unorm, read_write>; 
+@group(0) @binding(3)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 @group(1) @binding(0)
 var from_texture: texture_storage_3d<rgba8unorm, read_write>; 
 @group(1) @binding(1)
 var to_texture: texture_storage_3d<rgba8unorm, read_write>;
 
+#import bevy_voxel_engine::bricks
 
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
-    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    let texture_value = load_voxel(pos);
     return vec2(
         texture_value & 0xFFu,
         texture_value >> 8u,
     );
 }
 
+// colours of the voxels of each thread summed up, with how many there are in alpha
+var<workgroup> thread_colours: array<vec4<f32>, 64>;
+
+// the first level has a texel for every brick, with the average colour of its voxels and how much
+// of it is filled
 @compute @workgroup_size(4, 4, 4)
-fn copy(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let pos = vec3(i32(invocation_id.x), i32(invocation_id.y), i32(invocation_id.z));
-    let material = get_texture_value(pos);
-    if material.x != 0u {
-        textureStore(mip_texture, pos.zyx, vec4(voxel_uniforms.materials[material.x].rgb, 1.0));
+fn copy(
+    @builtin(workgroup_id) brick: vec3<u32>,
+    @builtin(local_invocation_id) thread: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    var sum = vec4(0.0);
+    if (get_brick(brick) != 0u) {
+        for (var i = 0u; i < BRICK_THREAD_VOXELS; i++) {
+            let material = get_texture_value(brick_voxel(brick, thread, i));
+            if material.x != 0u {
+                sum += vec4(voxel_uniforms.materials[material.x].rgb, 1.0);
+            }
+        }
+    }
+    thread_colours[index] = sum;
+
+    workgroupBarrier();
+    if (index != 0u) {
+        return;
+    }
+
+    var total = vec4(0.0);
+    for (var i = 0u; i < 64u; i++) {
+        total += thread_colours[i];
+    }
+    let pos = vec3<i32>(brick);
+    if total.a > 0.0 {
+        textureStore(mip_texture, pos.zyx, vec4(total.rgb / total.a, total.a / f32(BRICK_SIZE * BRICK_SIZE * BRICK_SIZE)));
     } else {
         textureStore(mip_texture, pos.zyx, vec4(0.0));
     }
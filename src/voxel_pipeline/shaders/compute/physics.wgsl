@@ -3,9 +3,11 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 struct ComputeUniforms {
     time: f32,
@@ -17,7 +19,8 @@ var<uniform> compute_uniforms: ComputeUniforms;
 @group(1) @binding(1)
 var<storage, read_write> physics_data: array<u32>;
 
-// note: raytracing.wgsl requires common.wgsl and for you to define u, voxel_world and gh before you import it
+// note: raytracing.wgsl requires common.wgsl, bricks.wgsl and for you to define u, brick_atlas, brick_grid and gh before you import it
+#import bevy_voxel_engine::bricks
 #import bevy_voxel_engine::raytracing
 
 @compute @workgroup_size(1, 1, 1)
@@ -74,6 +77,14 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                     // velocity = hit.normal * 10.0;
                     velocity = velocity - dot(velocity, hit.normal) * hit.normal;
                     hit_normal = hit.normal;
+
+                    // friction slows sliding by a fraction of the weight pressing into the surface
+                    let friction = voxel_uniforms.gameplay_properties[hit.data & 0xFFu].friction;
+                    let speed = length(velocity);
+                    let slowdown = friction * max(-dot(gravity, hit.normal), 0.0) * compute_uniforms.delta_time;
+                    if (speed > 0.0) {
+                        velocity *= max(speed - slowdown, 0.0) / speed;
+                    }
                     
                     // collision effects
                     let texture_coords = vec3<i32>(world_pos * VOXELS_PER_METER + vec3(f32(voxel_uniforms.texture_size) / 2.0));
@@ -89,21 +100,25 @@ fn physics(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
                                         continue;
                                     }
 
-                                    // destroy
+                                    // destroy, voxels harder than 1 only close to the hit
                                     if (collision_effect.x == 1.0) {
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(0u));
+                                        let material = load_voxel(texture_coords) & 0xFFu;
+                                        let hardness = voxel_uniforms.gameplay_properties[material].hardness;
+                                        if (length(vec3<f32>(offset) / VOXELS_PER_METER) < radius / max(hardness, 1.0)) {
+                                            store_voxel(texture_coords, 0u);
+                                        }
                                     }
                                     // place
                                     if (collision_effect.x == 2.0) {
                                         let material = bitcast<u32>(collision_effect.z);
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(material));
+                                        store_voxel(texture_coords, material);
                                     }
                                     // set flags
                                     if (collision_effect.x == 3.0) {
                                         let flags = bitcast<u32>(collision_effect.z);
-                                        var voxel = textureLoad(voxel_world, texture_coords.zyx).r;
+                                        var voxel = load_voxel(texture_coords);
                                         voxel |= flags << 8u;
-                                        textureStore(voxel_world, texture_coords.zyx, vec4(voxel));
+                                        store_voxel(texture_coords, voxel);
                                     }
                                 }
                             }
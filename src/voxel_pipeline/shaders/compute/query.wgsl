@@ -0,0 +1,143 @@
+#import bevy_voxel_engine::common
+
+@group(0) @binding(0)
+var<uniform> voxel_uniforms: VoxelUniforms;
+@group(0) @binding(1)
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
+@group(0) @binding(2)
+var<storage, read_write> gh: array<atomic<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
+
+struct ComputeUniforms {
+    time: f32,
+    delta_time: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> compute_uniforms: ComputeUniforms;
+@group(1) @binding(4)
+var<storage, read> query_data: array<u32>;
+@group(1) @binding(5)
+var<storage, read_write> query_results: array<u32>;
+
+#import bevy_voxel_engine::bricks
+
+const QUERY_WORDS = 9u;
+
+fn query_ivec3(index: u32) -> vec3<i32> {
+    return vec3(
+        bitcast<i32>(query_data[index + 0u]),
+        bitcast<i32>(query_data[index + 1u]),
+        bitcast<i32>(query_data[index + 2u]),
+    );
+}
+
+fn query_vec3(index: u32) -> vec3<f32> {
+    return vec3(
+        bitcast<f32>(query_data[index + 0u]),
+        bitcast<f32>(query_data[index + 1u]),
+        bitcast<f32>(query_data[index + 2u]),
+    );
+}
+
+// writes hit, position, normal, distance and value of the first solid voxel along the ray
+fn ray_query(origin: vec3<f32>, direction: vec3<f32>, max_distance: f32, slot: u32) {
+    let step = vec3<i32>(sign(direction));
+    let safe_direction = select(direction, vec3(1e-8), abs(direction) < vec3(1e-8));
+    let delta = abs(1.0 / safe_direction);
+
+    var pos = vec3<i32>(floor(origin));
+    var side = (sign(direction) * (vec3<f32>(pos) - origin) + sign(direction) * 0.5 + 0.5) * delta;
+    var normal = vec3(0);
+    var distance = 0.0;
+    // past the far side of the world there is nothing left to hit, which also keeps huge
+    // distances from overflowing the step count
+    let size = f32(voxel_uniforms.texture_size);
+    let reach = min(max_distance, length(origin - size / 2.0) + size * 2.0);
+    let max_steps = i32(3.0 * (size + max(reach, 0.0)));
+    for (var i = 0; i < max_steps; i++) {
+        if (distance > max_distance) {
+            break;
+        }
+
+        let value = load_voxel(pos);
+        if ((value & 0xFFu) != 0u) {
+            query_results[slot + 0u] = 1u;
+            query_results[slot + 1u] = bitcast<u32>(pos.x);
+            query_results[slot + 2u] = bitcast<u32>(pos.y);
+            query_results[slot + 3u] = bitcast<u32>(pos.z);
+            query_results[slot + 4u] = bitcast<u32>(normal.x);
+            query_results[slot + 5u] = bitcast<u32>(normal.y);
+            query_results[slot + 6u] = bitcast<u32>(normal.z);
+            query_results[slot + 7u] = bitcast<u32>(distance);
+            query_results[slot + 8u] = value;
+            return;
+        }
+
+        if (side.x < side.y && side.x < side.z) {
+            distance = side.x;
+            side.x += delta.x;
+            pos.x += step.x;
+            normal = vec3(-step.x, 0, 0);
+        } else if (side.y < side.z) {
+            distance = side.y;
+            side.y += delta.y;
+            pos.y += step.y;
+            normal = vec3(0, -step.y, 0);
+        } else {
+            distance = side.z;
+            side.z += delta.z;
+            pos.z += step.z;
+            normal = vec3(0, 0, -step.z);
+        }
+    }
+    query_results[slot] = 0u;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn query(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let query_count = query_data[0];
+    let result_count = query_data[1];
+    let slot = invocation_id.x;
+    if (query_count == 0u || slot >= result_count) {
+        return;
+    }
+
+    // find the query this result slot belongs to, result offsets are in order
+    var low = 0u;
+    var high = query_count - 1u;
+    loop {
+        if (low >= high) {
+            break;
+        }
+        let middle = (low + high + 1u) / 2u;
+        if (query_data[2u + middle * QUERY_WORDS + 1u] <= slot) {
+            low = middle;
+        } else {
+            high = middle - 1u;
+        }
+    }
+
+    let index = 2u + low * QUERY_WORDS;
+    let query_type = query_data[index];
+    let local = slot - query_data[index + 1u];
+    if (query_type == 0u) {
+        // point
+        query_results[slot] = load_voxel(query_ivec3(index + 2u));
+    } else if (query_type == 1u) {
+        // box, x changes slowest
+        let min = query_ivec3(index + 2u);
+        let size = query_ivec3(index + 5u) - min + 1;
+        let offset = vec3(
+            i32(local) / (size.y * size.z),
+            i32(local) / size.z % size.y,
+            i32(local) % size.z,
+        );
+        query_results[slot] = load_voxel(min + offset);
+    } else if (query_type == 2u && local == 0u) {
+        // ray, the first slot traces for the whole result
+        let max_distance = bitcast<f32>(query_data[index + 8u]);
+        ray_query(query_vec3(index + 2u), query_vec3(index + 5u), max_distance, slot);
+    }
+}
@@ -0,0 +1,64 @@
+#import bevy_voxel_engine::common
+
+@group(0) @binding(0)
+var<uniform> voxel_uniforms: VoxelUniforms;
+@group(0) @binding(1)
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
+
+// a 4 word header with the number of bricks, then every brick as its position in the brick grid
+// followed by its voxels two to a word, in the order of GH::bricks
+@group(1) @binding(0)
+var<storage, read_write> stream_bricks: array<u32>;
+
+#import bevy_voxel_engine::bricks
+
+// 3 + BRICK_SIZE^3 / 2
+const STREAM_BRICK_WORDS: u32 = 2051u;
+// bricks past this many workgroups along x carry on along y
+const STREAM_DISPATCH_WIDTH: u32 = 65535u;
+
+fn stream_brick(workgroup: vec3<u32>) -> u32 {
+    return workgroup.y * STREAM_DISPATCH_WIDTH + workgroup.x;
+}
+
+// one of the two voxels of a word of the brick starting at start
+fn word_voxel(start: u32, word: u32, half: u32) -> vec3<i32> {
+    let brick = vec3(stream_bricks[start], stream_bricks[start + 1u], stream_bricks[start + 2u]);
+    let index = word * 2u + half;
+    let pos = vec3(index >> 8u, (index >> 4u) & 15u, index & 15u);
+    return vec3<i32>(brick * BRICK_SIZE + pos);
+}
+
+// copies bricks out of the world, for regions that are saved
+@compute @workgroup_size(64, 1, 1)
+fn save(@builtin(workgroup_id) workgroup: vec3<u32>, @builtin(local_invocation_index) thread: u32) {
+    let brick = stream_brick(workgroup);
+    if (brick >= stream_bricks[0]) {
+        return;
+    }
+
+    let start = 4u + brick * STREAM_BRICK_WORDS;
+    for (var word = thread; word < STREAM_BRICK_WORDS - 3u; word += 64u) {
+        let low = load_voxel(word_voxel(start, word, 0u));
+        let high = load_voxel(word_voxel(start, word, 1u));
+        stream_bricks[start + 3u + word] = low | (high << 16u);
+    }
+}
+
+// writes bricks into the world, for regions that are loaded
+@compute @workgroup_size(64, 1, 1)
+fn load(@builtin(workgroup_id) workgroup: vec3<u32>, @builtin(local_invocation_index) thread: u32) {
+    let brick = stream_brick(workgroup);
+    if (brick >= stream_bricks[0]) {
+        return;
+    }
+
+    let start = 4u + brick * STREAM_BRICK_WORDS;
+    for (var word = thread; word < STREAM_BRICK_WORDS - 3u; word += 64u) {
+        let voxels = stream_bricks[start + 3u + word];
+        store_voxel(word_voxel(start, word, 0u), voxels & 0xFFFFu);
+        store_voxel(word_voxel(start, word, 1u), voxels >> 16u);
+    }
+}
@@ -73,8 +73,16 @@ fn get_value(pos: vec3<f32>) -> Voxel {
         return Voxel(0u, rounded_pos, size7);
     }
 
+    // bricks of air are skipped whole
+    let brick_size = voxel_uniforms.texture_size / BRICK_SIZE;
+    let brick = vec3<u32>(scaled * f32(brick_size));
+    if (all(brick < vec3(brick_size)) && get_brick(brick) == 0u) {
+        let rounded_pos = ((vec3<f32>(brick) + 0.5) / f32(brick_size)) * 2.0 - 1.0;
+        return Voxel(0u, rounded_pos, brick_size);
+    }
+
     let rounded_pos = (floor(pos * f32(voxel_uniforms.texture_size) * 0.5) + 0.5) / (f32(voxel_uniforms.texture_size) * 0.5);
-    let data = textureLoad(voxel_world, vec3<i32>(scaled * f32(voxel_uniforms.texture_size)).zyx).r;
+    let data = load_voxel(vec3<i32>(scaled * f32(voxel_uniforms.texture_size)));
     return Voxel(data, rounded_pos, voxel_uniforms.texture_size);
 }
 
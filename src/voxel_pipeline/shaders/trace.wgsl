@@ -4,13 +4,15 @@
 @group(0) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(0) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(0) @binding(2)
 var<storage, read_write> gh: array<u32>;
 @group(0) @binding(3)
 var mip: texture_3d<f32>;
 @group(0) @binding(4)
 var texture_sampler: sampler;
+@group(0) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 @group(1) @binding(0)
 var<uniform> trace_uniforms: TraceUniforms;
@@ -19,15 +21,63 @@ var normal: texture_storage_2d<rgba16float, read_write>;
 @group(1) @binding(3)
 var position: texture_storage_2d<rgba32float, read_write>;
 
-// note: raytracing.wgsl requires common.wgsl and for you to define u, voxel_world and gh before you import it
+// note: raytracing.wgsl requires common.wgsl, bricks.wgsl and for you to define u, brick_atlas, brick_grid and gh before you import it
+#import bevy_voxel_engine::bricks
 #import bevy_voxel_engine::raytracing
 
+fn get_material_properties(hit: HitInfo) -> MaterialProperties {
+    return voxel_uniforms.material_properties[hit.data & 0xFFu];
+}
+
+struct Surface {
+    albedo: vec3<f32>,
+    emitted: vec3<f32>,
+    properties: MaterialProperties,
+};
+
+fn get_surface(hit: HitInfo) -> Surface {
+    let properties = get_material_properties(hit);
+
+    // emissive palette entries are brightened by their emission
+    var albedo = hit.material.rgb;
+    var emitted = vec3(0.0);
+    if hit.material.a != 0.0 {
+        albedo = albedo / (1.0 + properties.emission);
+        emitted = albedo * properties.emission;
+    } else if voxel_uniforms.gameplay_properties[hit.data & 0xFFu].emissive != 0u {
+        // registered emissive materials glow with their own colour
+        emitted = albedo;
+    }
+    return Surface(albedo, emitted, properties);
+}
+
 const light_dir = vec3<f32>(0.8, -1.0, 0.8);
 const light_colour = vec3<f32>(1.0, 1.0, 1.0);
 
-fn calculate_direct(material: vec4<f32>, pos: vec3<f32>, normal: vec3<f32>, mode: u32, seed: vec3<u32>, shadow_samples: u32) -> vec3<f32> {
+// light let through by a shadow ray hit, transparent voxels only block part of it
+fn shadow_hit_light(shadow_hit: HitInfo) -> f32 {
+    if !shadow_hit.hit {
+        return 1.0;
+    }
+    return get_material_properties(shadow_hit).transparency;
+}
+
+// direct light reflected towards view_dir, metals only reflect specular light tinted by their albedo
+fn calculate_direct(hit: HitInfo, view_dir: vec3<f32>, mode: u32, seed: vec3<u32>, shadow_samples: u32) -> vec3<f32> {
+    let surface = get_surface(hit);
+    let pos = hit.pos;
+    let normal = hit.normal;
+    let to_light = -normalize(light_dir);
+
     // diffuse
-    let diffuse = max(dot(normal, -normalize(light_dir)), 0.0);
+    let diffuse = max(dot(normal, to_light), 0.0) * (1.0 - surface.properties.metalness);
+
+    // specular, rougher surfaces get a wider and dimmer highlight
+    let half_dir = normalize(to_light - view_dir);
+    let shininess = exp2(10.0 * (1.0 - surface.properties.roughness) + 1.0);
+    let highlight = pow(max(dot(normal, half_dir), 0.0), shininess) * (shininess + 8.0) / (8.0 * pi);
+    let specular_colour = mix(vec3(0.04), surface.albedo, surface.properties.metalness);
+    let specular = highlight * specular_colour * f32(dot(normal, to_light) > 0.0);
 
     // shadow
     var shadow = 1.0;
@@ -41,22 +91,32 @@ fn calculate_direct(material: vec4<f32>, pos: vec3<f32>, normal: vec3<f32>, mode
                 let rand = hash(seed + i) * 2.0 - 1.0;
                 let shadow_ray = Ray(pos, -light_dir + rand * 0.1);
                 let shadow_hit = shoot_ray(shadow_ray, 0.0, 0u);
-                shadow -= f32(shadow_hit.hit) / f32(shadow_samples);
+                shadow -= (1.0 - shadow_hit_light(shadow_hit)) / f32(shadow_samples);
             }
         } else {
             let shadow_ray = Ray(pos, -light_dir);
             let shadow_hit = shoot_ray(shadow_ray, 0.0, 0u);
-            shadow = f32(!shadow_hit.hit);
+            shadow = shadow_hit_light(shadow_hit);
         }
     }
 
-    // emissive
-    var emissive = vec3(0.0);
-    if material.a != 0.0 {
-        emissive = vec3(material.rgb);
-    }
+    return (diffuse * surface.albedo + specular) * shadow * light_colour + surface.emitted;
+}
 
-    return diffuse * shadow * light_colour + emissive;
+// the first surface behind transparent voxels along dir, going through a few voxels at most
+fn trace_behind(hit: HitInfo, dir: vec3<f32>) -> HitInfo {
+    let half_voxel = 0.5 / VOXELS_PER_METER;
+    var behind = hit;
+    for (var i = 0u; i < 8u; i += 1u) {
+        // leave the voxel that was hit on the far side
+        let center = (floor((behind.pos - behind.normal * half_voxel) * VOXELS_PER_METER) + 0.5) / VOXELS_PER_METER;
+        let exit = ray_box_dist(Ray(behind.pos, dir), center - half_voxel, center + half_voxel).y;
+        behind = shoot_ray(Ray(behind.pos + dir * (exit + 0.001), dir), 0.0, 0u);
+        if !behind.hit || get_material_properties(behind).transparency == 0.0 {
+            break;
+        }
+    }
+    return behind;
 }
 
 fn get_voxel(pos: vec3<f32>) -> f32 {
@@ -64,8 +124,8 @@ fn get_voxel(pos: vec3<f32>) -> f32 {
         return 0.0;
     }
 
-    let voxel = textureLoad(voxel_world, vec3<i32>(pos.zyx));
-    return min(f32(voxel.r & 0xFFu), 1.0);
+    let voxel = load_voxel(vec3<i32>(pos));
+    return min(f32(voxel & 0xFFu), 1.0);
 }
 
 // https://www.shadertoy.com/view/ldl3DS
@@ -97,7 +157,8 @@ fn vct(ray: Ray, angle: f32) -> vec4<f32> {
     tcpotr = tcpotr * VOXELS_PER_METER / f32(voxel_uniforms.texture_size) + 0.5;
     loop {
         let size = distance * tan(angle);
-        let mip_level = log2(size * f32(voxel_uniforms.texture_size));
+        // the first level of the mip texture has a texel for every brick
+        let mip_level = log2(size * f32(voxel_uniforms.texture_size / BRICK_SIZE));
 
         let col = textureSampleLevel(mip, texture_sampler, tcpotr.zyx, mip_level);
         color += col;
@@ -147,7 +208,7 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     var samples = 0.0;
     if hit.hit {
         // direct lighting
-        let direct_lighting = calculate_direct(hit.material, hit.pos, hit.normal, mode, seed + 1u, trace_uniforms.samples);
+        let direct_lighting = calculate_direct(hit, ray.dir, mode, seed + 1u, trace_uniforms.samples);
 
         // indirect lighting
         var indirect_lighting = vec3(0.0);
@@ -186,7 +247,7 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
                 let indirect_hit = shoot_ray(Ray(hit.pos, indirect_dir), 0.0, 0u);
                 var lighting = vec3(0.0);
                 if indirect_hit.hit {
-                    lighting = calculate_direct(indirect_hit.material, indirect_hit.pos, indirect_hit.normal, mode, seed + 3u, 1u);
+                    lighting = calculate_direct(indirect_hit, indirect_dir, mode, seed + 3u, 1u);
                 } else {
                     lighting = vec3(0.2);
                     // lighting = skybox(indirect_dir, 10.0);
@@ -206,7 +267,18 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
         }
 
         // final blend
-        output_colour = (indirect_lighting + direct_lighting) * hit.material.rgb;
+        let surface = get_surface(hit);
+        output_colour = indirect_lighting * surface.albedo + direct_lighting;
+
+        // transparent voxels let part of what is behind them through, tinted by their colour
+        if surface.properties.transparency > 0.0 {
+            let behind = trace_behind(hit, ray.dir);
+            var behind_colour = skybox(ray.dir, 10.0);
+            if behind.hit {
+                behind_colour = calculate_direct(behind, ray.dir, mode, seed + 5u, 1u) + 0.3 * get_surface(behind).albedo;
+            }
+            output_colour = mix(output_colour, behind_colour * surface.albedo, surface.properties.transparency);
+        }
 
         // let posing = (hit.pos - hit.normal * 0.01) * VOXELS_PER_METER / f32(voxel_uniforms.texture_size) + 0.5;
         // output_colour = textureSampleLevel(mip, texture_sampler, posing.zyx, (1.0 - trace_uniforms.misc_float) * f32(textureNumLevels(mip))).rgb;
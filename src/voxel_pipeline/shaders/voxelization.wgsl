@@ -34,9 +34,11 @@ struct VoxelizationUniforms {
 @group(2) @binding(0)
 var<uniform> voxel_uniforms: VoxelUniforms;
 @group(2) @binding(1)
-var voxel_world: texture_storage_3d<r16uint, read_write>;
+var brick_atlas: texture_storage_3d<r16uint, read_write>;
 @group(2) @binding(2)
 var<storage, read> gh: array<u32>;
+@group(2) @binding(5)
+var<storage, read_write> brick_grid: array<atomic<u32>>;
 
 @group(3) @binding(0)
 var<uniform> voxelization_uniforms: VoxelizationUniforms;
@@ -45,8 +47,10 @@ var material_texture: texture_2d<f32>;
 @group(3) @binding(2)
 var material_sampler: sampler;
 
+#import bevy_voxel_engine::bricks
+
 fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
-    let texture_value = textureLoad(voxel_world, pos.zyx).r;
+    let texture_value = load_voxel(pos);
     return vec2(
         texture_value & 0xFFu,
         texture_value >> 8u,
@@ -56,7 +60,7 @@ fn get_texture_value(pos: vec3<i32>) -> vec2<u32> {
 fn write_pos(pos: vec3<i32>, material: u32, flags: u32) {
     let voxel_type = get_texture_value(pos);
     if (voxel_type.x == 0u) {
-        textureStore(voxel_world, pos.zyx, vec4(material | (flags << 8u)));
+        store_voxel(pos, material | (flags << 8u));
     }
 }
 
@@ -25,6 +25,8 @@ const COMMON_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 1874948457211004189);
 const RAYTRACING_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 10483863284569474370);
+const BRICKS_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 7730214185936024533);
 
 pub struct TracePlugin;
 
@@ -54,6 +56,12 @@ impl Plugin for TracePlugin {
             "../shaders/raytracing.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            BRICKS_SHADER_HANDLE,
+            "../shaders/bricks.wgsl",
+            Shader::from_wgsl
+        );
 
         app.add_plugin(ExtractComponentPlugin::<TraceSettings>::default());
 
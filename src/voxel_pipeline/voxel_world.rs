@@ -1,6 +1,13 @@
+use super::compute::stream::{self, STREAM_BRICK_WORDS};
 use crate::{
-    load::{Pallete, GH},
-    LoadVoxelWorld,
+    load::{
+        spawn_load_task, ActiveVoxelWorld, MaterialProperties, Pallete, VoxelAnimation,
+        VoxelWorldAsset, VoxelWorldAssetLoader, VoxelWorldLoadError, BRICK_SIZE, BRICK_VOXELS, GH,
+    },
+    streaming::{finish_shift, shift_world, spawn_saves, start_stream_job, take_saves, StreamJob},
+    FloatingOrigin, LoadVoxelWorld, MaterialRegistry, RegisteredMaterial, ResizeVoxelWorld,
+    SaveVoxelWorld, VoxelPalette, VoxelStreaming, VoxelStreamingFocus, VoxelWorldLoadProgress,
+    VoxelWorldLoadSettings, VoxelWorldMirror, VoxelWorldShifted,
 };
 use bevy::{
     prelude::*,
@@ -10,8 +17,32 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
         RenderApp, RenderSet,
     },
+    tasks::Task,
+};
+use futures_lite::future;
+use std::{
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
 };
-use std::sync::Arc;
+
+/// How much of a new world is written to the brick atlas each frame.
+const UPLOAD_BYTES_PER_FRAME: u32 = 16 * 1024 * 1024;
+/// Room left in the brick atlas for voxels placed in empty space after a world is loaded, on top
+/// of twice the bricks the world is loaded with.
+const SPARE_BRICKS: u32 = 4096;
+/// Largest part of the brick atlas read back from the gpu at once when saving.
+const READBACK_BYTES: u32 = 64 * 1024 * 1024;
+/// Words after the brick grid, the slots the shaders have used and the writes they dropped
+/// because the atlas was full.
+const BRICK_GRID_COUNTERS: usize = 2;
+
+// states of the brick atlas usage buffer while it is being mapped
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
 
 pub struct VoxelWorldPlugin;
 
@@ -24,6 +55,16 @@ impl Plugin for VoxelWorldPlugin {
         let buffer_size = gh.get_buffer_size();
         let texture_size = gh.texture_size;
         let gh_offsets = gh.get_offsets();
+        // an empty world always fits
+        let atlas_size =
+            brick_atlas_size(0, gh.brick_grid.len(), max_atlas_size(render_device)).unwrap_or(1);
+        let brick_grid = create_brick_grid(render_device, &gh);
+        let brick_atlas_usage = render_device.create_buffer(&BufferDescriptor {
+            label: Some("brick atlas usage buffer"),
+            size: BRICK_GRID_COUNTERS as u64 * 4,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
         let mut levels = [UVec4::ZERO; 8];
         let mut offsets = [UVec4::ZERO; 8];
@@ -33,36 +74,25 @@ impl Plugin for VoxelWorldPlugin {
         }
 
         // uniforms
+        let material_registry = MaterialRegistry::default();
         let voxel_uniforms = VoxelUniforms {
             pallete: gh.pallete.into(),
+            material_properties: gh.materials,
+            gameplay_properties: (&material_registry).into(),
+            material_ids: (&material_registry).into(),
             portals: [ExtractedPortal::default(); 32],
             levels,
             offsets,
             texture_size,
+            atlas_size,
+            brick_capacity: atlas_size.pow(3),
         };
         let mut uniform_buffer = UniformBuffer::from(voxel_uniforms.clone());
         uniform_buffer.write_buffer(render_device, render_queue);
 
-        // texture
-        let voxel_world = render_device.create_texture_with_data(
-            render_queue,
-            &TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width: gh.texture_size,
-                    height: gh.texture_size,
-                    depth_or_array_layers: gh.texture_size,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D3,
-                format: TextureFormat::R16Uint,
-                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
-                view_formats: &[],
-            },
-            &gh.texture_data.clone(),
-        );
-        let voxel_world = voxel_world.create_view(&TextureViewDescriptor::default());
+        // bricks
+        let brick_atlas_texture = render_device.create_texture(&brick_atlas_descriptor(atlas_size));
+        let brick_atlas = brick_atlas_texture.create_view(&TextureViewDescriptor::default());
 
         // storage
         let grid_heierachy = render_device.create_buffer_with_data(&BufferInitDescriptor {
@@ -72,21 +102,7 @@ impl Plugin for VoxelWorldPlugin {
         });
 
         // mip texture
-        let mip_count = gh.texture_size.trailing_zeros();
-        let mip_texture = render_device.create_texture(&TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: gh.texture_size,
-                height: gh.texture_size,
-                depth_or_array_layers: gh.texture_size,
-            },
-            mip_level_count: mip_count,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
+        let mip_texture = render_device.create_texture(&mip_texture_descriptor(gh.texture_size));
         let mip_texture_view = mip_texture.create_view(&TextureViewDescriptor::default());
 
         // sampler
@@ -147,6 +163,16 @@ impl Plugin for VoxelWorldPlugin {
                         ty: BindingType::Sampler(SamplerBindingType::Filtering),
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(4),
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -160,7 +186,7 @@ impl Plugin for VoxelWorldPlugin {
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::TextureView(&voxel_world),
+                    resource: BindingResource::TextureView(&brick_atlas),
                 },
                 BindGroupEntry {
                     binding: 2,
@@ -174,36 +200,108 @@ impl Plugin for VoxelWorldPlugin {
                     binding: 4,
                     resource: BindingResource::Sampler(&texture_sampler),
                 },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: brick_grid.as_entire_binding(),
+                },
             ],
         });
 
         app.insert_resource(LoadVoxelWorld::None)
             .insert_resource(NewGH::None)
+            .insert_resource(WorldResize::None)
+            .insert_resource(WorldLoad::None)
+            .init_resource::<VoxelWorldLoadProgress>()
+            .insert_resource(SaveVoxelWorld::None)
+            .insert_resource(SavePath::None)
+            .init_resource::<WorldStream>()
+            .init_resource::<StreamJob>()
+            .init_resource::<FloatingOrigin>()
+            .init_resource::<VoxelWorldLoadSettings>()
             .insert_resource(voxel_uniforms)
+            .init_resource::<VoxelPalette>()
+            .insert_resource(material_registry)
             .add_plugin(ExtractResourcePlugin::<NewGH>::default())
+            .add_plugin(ExtractResourcePlugin::<WorldResize>::default())
+            .add_plugin(ExtractResourcePlugin::<SavePath>::default())
+            .add_plugin(ExtractResourcePlugin::<WorldStream>::default())
             .add_plugin(ExtractResourcePlugin::<VoxelUniforms>::default())
-            .add_system(load_voxel_world);
+            .add_event::<VoxelWorldLoadError>()
+            .add_event::<ResizeVoxelWorld>()
+            .add_event::<VoxelWorldShifted>()
+            .add_asset::<VoxelWorldAsset>()
+            .add_asset::<VoxelAnimation>()
+            .init_asset_loader::<VoxelWorldAssetLoader>()
+            .add_systems(
+                (
+                    load_voxel_world,
+                    activate_voxel_world_asset,
+                    upload_voxel_world,
+                    mirror_voxel_world,
+                    update_voxel_palette,
+                    resize_voxel_world,
+                    stream_voxel_world,
+                )
+                    .chain(),
+            )
+            .add_system(update_material_registry)
+            .add_system(save_voxel_world);
 
         app.sub_app_mut(RenderApp)
             .insert_resource(VoxelData {
                 uniform_buffer,
-                voxel_world,
+                brick_atlas,
+                brick_atlas_texture,
+                atlas_size,
+                brick_grid,
                 grid_heierachy,
                 mip_texture,
                 texture_sampler,
                 bind_group_layout,
                 bind_group,
             })
-            .add_system(prepare_uniforms.in_set(RenderSet::Prepare))
+            .insert_resource(BrickAtlasUsage {
+                buffer: brick_atlas_usage,
+                map_state: None,
+                slots: 0,
+                rebuilt_slots: 0,
+            })
+            .init_resource::<StreamReadbacks>()
             .add_system(load_voxel_world_prepare.in_set(RenderSet::Prepare))
-            .add_system(queue_bind_group.in_set(RenderSet::Queue));
+            .add_system(
+                resize_voxel_world_prepare
+                    .after(load_voxel_world_prepare)
+                    .in_set(RenderSet::Prepare),
+            )
+            .add_system(
+                prepare_uniforms
+                    .after(resize_voxel_world_prepare)
+                    .in_set(RenderSet::Prepare),
+            )
+            .add_system(
+                stream_voxel_world_prepare
+                    .after(prepare_uniforms)
+                    .in_set(RenderSet::Prepare),
+            )
+            .add_system(queue_bind_group.in_set(RenderSet::Queue))
+            .add_system(save_voxel_world_cleanup.in_set(RenderSet::Cleanup))
+            .add_system(check_brick_atlas.in_set(RenderSet::Cleanup))
+            .add_system(read_stream_cleanup.in_set(RenderSet::Cleanup));
     }
 }
 
 #[derive(Resource)]
 pub struct VoxelData {
     pub uniform_buffer: UniformBuffer<VoxelUniforms>,
-    pub voxel_world: TextureView,
+    /// Bricks of voxels, laid out as described in `brick_atlas_origin`.
+    pub brick_atlas: TextureView,
+    pub brick_atlas_texture: Texture,
+    /// Bricks along each side of the brick atlas, the render world grows the atlas on its own so
+    /// this is what goes into the uniforms.
+    pub atlas_size: u32,
+    /// `GH::brick_grid` with slots in the atlas in place of indices into `GH::bricks`, followed
+    /// by the `BRICK_GRID_COUNTERS`.
+    pub brick_grid: Buffer,
     pub grid_heierachy: Buffer,
     pub mip_texture: Texture,
     pub texture_sampler: Sampler,
@@ -226,6 +324,68 @@ impl Into<[PalleteEntry; 256]> for Pallete {
     }
 }
 
+/// The parts of a `RegisteredMaterial` the shaders see, for every palette index.
+#[derive(Default, Debug, Clone, Copy, ShaderType)]
+pub struct GameplayProperties {
+    pub density: f32,
+    pub flammability: f32,
+    pub hardness: f32,
+    pub friction: f32,
+    pub flags: u32,
+    pub emissive: u32,
+    // uniform arrays need a stride of 16 bytes
+    _padding: UVec2,
+}
+
+impl From<&MaterialRegistry> for [GameplayProperties; 256] {
+    fn from(registry: &MaterialRegistry) -> Self {
+        // palette entries without a material behave like one with the default properties
+        let unregistered = RegisteredMaterial::default();
+        let mut properties = [GameplayProperties::default(); 256];
+        for (i, properties) in properties.iter_mut().enumerate() {
+            let material = registry
+                .find(i as u8)
+                .map_or(&unregistered, |(_, material)| material);
+            *properties = GameplayProperties {
+                density: material.density,
+                flammability: material.flammability,
+                hardness: material.hardness,
+                friction: material.friction,
+                flags: material.flags as u32,
+                emissive: material.emissive as u32,
+                ..default()
+            };
+        }
+        properties
+    }
+}
+
+/// Palette indices of the materials the shaders give behaviour to, 0 when the registry does not
+/// have them.
+#[derive(Default, Debug, Clone, Copy, ShaderType)]
+pub struct MaterialIds {
+    pub water: u32,
+    pub fire: u32,
+    pub fire_count: u32,
+    pub dirt: u32,
+    pub grass: u32,
+}
+
+impl From<&MaterialRegistry> for MaterialIds {
+    fn from(registry: &MaterialRegistry) -> Self {
+        let index = |name| registry.index(name).unwrap_or(0) as u32;
+        Self {
+            water: index(MaterialRegistry::WATER),
+            fire: index(MaterialRegistry::FIRE),
+            fire_count: registry
+                .get(MaterialRegistry::FIRE)
+                .map_or(0, |fire| fire.count as u32),
+            dirt: index(MaterialRegistry::DIRT),
+            grass: index(MaterialRegistry::GRASS),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, ShaderType)]
 pub struct ExtractedPortal {
     pub transformation: Mat4,
@@ -236,15 +396,188 @@ pub struct ExtractedPortal {
 #[derive(Resource, ExtractResource, Clone, ShaderType)]
 pub struct VoxelUniforms {
     pub pallete: [PalleteEntry; 256],
+    pub material_properties: [MaterialProperties; 256],
+    pub gameplay_properties: [GameplayProperties; 256],
+    pub material_ids: MaterialIds,
     pub portals: [ExtractedPortal; 32],
     pub levels: [UVec4; 8],
     pub offsets: [UVec4; 8],
     pub texture_size: u32,
+    /// Bricks along each side of the brick atlas, set from `VoxelData::atlas_size` in the render
+    /// world.
+    pub atlas_size: u32,
+    /// Slots in the brick atlas, the shaders stop adding bricks once they are all used.
+    pub brick_capacity: u32,
+}
+
+impl VoxelUniforms {
+    /// Sets the size of the world and the layout of its grid hierarchy.
+    fn set_world_size(&mut self, levels: [u32; 8], texture_size: u32) {
+        let offsets = GH::get_offsets_from_levels(&levels);
+        for i in 0..8 {
+            self.levels[i] = UVec4::new(levels[i], 0, 0, 0);
+            self.offsets[i] = UVec4::new(offsets[i], 0, 0, 0);
+        }
+        self.texture_size = texture_size;
+    }
 }
 
 #[derive(Resource, ExtractResource, Clone)]
 enum NewGH {
-    Some(Arc<GH>),
+    Some {
+        gh: Arc<GH>,
+        brick_atlas: Texture,
+        atlas_size: u32,
+        brick_grid: Buffer,
+    },
+    None,
+}
+
+/// A resize to move the bricks of the world into a new brick grid for, see
+/// `Anchor::resize_region`.
+#[derive(Resource, ExtractResource, Clone)]
+enum WorldResize {
+    Some {
+        old_size: u32,
+        new_size: u32,
+        src: UVec3,
+        dst: UVec3,
+        size: u32,
+    },
+    None,
+}
+
+/// Moves the bricks of the world on the gpu for `stream_voxel_world`, after reading back the
+/// regions that are saved.
+#[derive(Clone)]
+struct StreamUpdate {
+    /// Voxels that were at `pos` move to `pos - shift`.
+    shift: IVec3,
+    region_size: u32,
+    /// Corners of the regions to read back, in the world before it moves.
+    saves: Vec<UVec3>,
+    /// Regions to write over the moved world, with their corners.
+    loads: Arc<Vec<(UVec3, GH)>>,
+    /// Filled with the regions in `saves` once they have been read back, or nothing if that
+    /// failed. The slot is shared between both worlds.
+    readback: Arc<Mutex<Option<Vec<GH>>>>,
+}
+
+#[derive(Resource, ExtractResource, Clone, Default)]
+struct WorldStream {
+    update: Option<StreamUpdate>,
+    /// Set by the render world once the stream pipelines have compiled.
+    pipeline_ready: Arc<AtomicBool>,
+}
+
+#[derive(Resource)]
+enum WorldLoad {
+    Parsing(Task<Result<GH, VoxelWorldLoadError>>),
+    /// Parsed and waiting for a brick atlas to be made for it.
+    Parsed(Arc<GH>),
+    Uploading {
+        gh: Arc<GH>,
+        brick_atlas: Texture,
+        brick_grid: Buffer,
+        atlas_size: u32,
+        row: u32,
+    },
+    None,
+}
+
+/// Largest brick atlas the device supports, in bricks along each side.
+fn max_atlas_size(render_device: &RenderDevice) -> u32 {
+    (render_device.limits().max_texture_dimension_3d / BRICK_SIZE).max(1)
+}
+
+/// Bricks along each side of a brick atlas with room for `bricks` bricks of a world with
+/// `grid_bricks` in its brick grid, and some to spare.
+fn brick_atlas_size(
+    bricks: usize,
+    grid_bricks: usize,
+    max_size: u32,
+) -> Result<u32, VoxelWorldLoadError> {
+    let max_bricks = max_size.pow(3);
+    let bricks = u32::try_from(bricks).unwrap_or(u32::MAX);
+    if bricks > max_bricks {
+        return Err(VoxelWorldLoadError::TooManyBricks(bricks, max_bricks));
+    }
+
+    // a world never needs more bricks than its grid has
+    let grid_bricks = u32::try_from(grid_bricks).unwrap_or(u32::MAX);
+    let capacity = bricks
+        .saturating_mul(2)
+        .saturating_add(SPARE_BRICKS)
+        .min(grid_bricks);
+    let mut size = 1u32;
+    while size.pow(3) < capacity && size < max_size {
+        size += 1;
+    }
+    Ok(size)
+}
+
+fn brick_atlas_descriptor(atlas_size: u32) -> TextureDescriptor<'static> {
+    let size = atlas_size * BRICK_SIZE;
+    TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D3,
+        format: TextureFormat::R16Uint,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    }
+}
+
+/// Texel of the first voxel of a slot in the brick atlas. Slots fill the atlas along x, then y,
+/// then z, and voxels are indexed with pos.zyx inside them.
+fn brick_atlas_origin(slot: u32, atlas_size: u32) -> Origin3d {
+    Origin3d {
+        x: slot % atlas_size * BRICK_SIZE,
+        y: slot / atlas_size % atlas_size * BRICK_SIZE,
+        z: slot / (atlas_size * atlas_size) * BRICK_SIZE,
+    }
+}
+
+/// The brick grid of `gh` followed by the `BRICK_GRID_COUNTERS`, bricks in `gh` go in the slot
+/// of the same index.
+fn create_brick_grid(render_device: &RenderDevice, gh: &GH) -> Buffer {
+    let mut contents = gh.brick_grid.clone();
+    contents.extend([gh.brick_count() as u32, 0]);
+    render_device.create_buffer_with_data(&BufferInitDescriptor {
+        contents: bytemuck::cast_slice(&contents),
+        label: None,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+    })
+}
+
+/// The mip texture has a texel for every brick.
+fn mip_texture_descriptor(texture_size: u32) -> TextureDescriptor<'static> {
+    let size = texture_size / BRICK_SIZE;
+    TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: size.trailing_zeros().max(1),
+        sample_count: 1,
+        dimension: TextureDimension::D3,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    }
+}
+
+#[derive(Resource, ExtractResource, Clone)]
+enum SavePath {
+    Some(String),
     None,
 }
 
@@ -253,106 +586,661 @@ fn prepare_uniforms(
     mut voxel_data: ResMut<VoxelData>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
+    mut written_atlas_size: Local<u32>,
 ) {
-    voxel_data.uniform_buffer.set(voxel_uniforms.clone());
+    // only extracted when something in the main world changed them
+    if !voxel_uniforms.is_changed() && *written_atlas_size == voxel_data.atlas_size {
+        return;
+    }
+
+    *written_atlas_size = voxel_data.atlas_size;
+    write_uniforms(
+        &mut voxel_data,
+        &voxel_uniforms,
+        &render_device,
+        &render_queue,
+    );
+}
+
+/// Writes the uniforms to the gpu with the size of the brick atlas the render world has.
+fn write_uniforms(
+    voxel_data: &mut VoxelData,
+    voxel_uniforms: &VoxelUniforms,
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+) {
+    let mut uniforms = voxel_uniforms.clone();
+    uniforms.atlas_size = voxel_data.atlas_size;
+    uniforms.brick_capacity = voxel_data.atlas_size.pow(3);
+    voxel_data.uniform_buffer.set(uniforms);
     voxel_data
         .uniform_buffer
-        .write_buffer(&render_device, &render_queue);
+        .write_buffer(render_device, render_queue);
 }
 
 fn load_voxel_world(
     mut load_voxel_world: ResMut<LoadVoxelWorld>,
     mut new_gh: ResMut<NewGH>,
+    mut world_load: ResMut<WorldLoad>,
+    mut load_progress: ResMut<VoxelWorldLoadProgress>,
+    load_settings: Res<VoxelWorldLoadSettings>,
+) {
+    *new_gh = NewGH::None;
+
+    let request = std::mem::replace(load_voxel_world.as_mut(), LoadVoxelWorld::None);
+    let Some(task) = spawn_load_task(request, load_settings.clone()) else {
+        return;
+    };
+
+    // a new request replaces any load that is still in progress
+    *world_load = WorldLoad::Parsing(task);
+    *load_progress = VoxelWorldLoadProgress::Parsing;
+}
+
+fn activate_voxel_world_asset(
+    active_voxel_world: Option<Res<ActiveVoxelWorld>>,
+    voxel_world_assets: Res<Assets<VoxelWorldAsset>>,
+    mut asset_events: EventReader<AssetEvent<VoxelWorldAsset>>,
+    mut world_load: ResMut<WorldLoad>,
+    mut load_progress: ResMut<VoxelWorldLoadProgress>,
+    mut pending: Local<bool>,
+) {
+    let Some(active_voxel_world) = active_voxel_world else {
+        asset_events.clear();
+        return;
+    };
+
+    if active_voxel_world.is_changed() {
+        *pending = true;
+    }
+    for event in asset_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == active_voxel_world.0 =>
+            {
+                *pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    // wait until the asset has finished loading
+    if *pending {
+        if let Some(asset) = voxel_world_assets.get(&active_voxel_world.0) {
+            *world_load = WorldLoad::Parsed(asset.gh.clone());
+            *load_progress = VoxelWorldLoadProgress::Uploading(0.0);
+            *pending = false;
+        }
+    }
+}
+
+fn start_upload(
+    gh: Arc<GH>,
+    render_device: &RenderDevice,
+) -> Result<WorldLoad, VoxelWorldLoadError> {
+    let atlas_size = brick_atlas_size(
+        gh.brick_count(),
+        gh.brick_grid.len(),
+        max_atlas_size(render_device),
+    )?;
+    let brick_atlas = render_device.create_texture(&brick_atlas_descriptor(atlas_size));
+    let brick_grid = create_brick_grid(render_device, &gh);
+    Ok(WorldLoad::Uploading {
+        gh,
+        brick_atlas,
+        brick_grid,
+        atlas_size,
+        row: 0,
+    })
+}
+
+/// Writes the bricks in a row of slots of the brick atlas.
+fn write_brick_row(
+    render_queue: &RenderQueue,
+    gh: &GH,
+    brick_atlas: &Texture,
+    atlas_size: u32,
+    row: u32,
+) {
+    let first = (row * atlas_size) as usize;
+    let count = (gh.brick_count() - first).min(atlas_size as usize);
+    let brick_size = BRICK_SIZE as usize;
+    let width = count * brick_size;
+
+    // every z row of voxels in a brick is a row of texels, next to the same row of the bricks
+    // beside it in the atlas
+    let mut data = vec![0u16; count * BRICK_VOXELS];
+    let bricks = &gh.bricks[first * BRICK_VOXELS..(first + count) * BRICK_VOXELS];
+    for (i, brick) in bricks.chunks_exact(BRICK_VOXELS).enumerate() {
+        for (j, voxels) in brick.chunks_exact(brick_size).enumerate() {
+            let start = j * width + i * brick_size;
+            data[start..start + brick_size].copy_from_slice(voxels);
+        }
+    }
+
+    render_queue.write_texture(
+        ImageCopyTexture {
+            origin: brick_atlas_origin(first as u32, atlas_size),
+            ..brick_atlas.as_image_copy()
+        },
+        bytemuck::cast_slice(&data),
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(width as u32 * 2),
+            rows_per_image: NonZeroU32::new(BRICK_SIZE),
+        },
+        Extent3d {
+            width: width as u32,
+            height: BRICK_SIZE,
+            depth_or_array_layers: BRICK_SIZE,
+        },
+    );
+}
+
+/// Uploads the bricks of the parsed world into a new brick atlas a few rows at a time and only
+/// swaps it in once every brick has been written, so the previous world stays visible while
+/// loading.
+fn upload_voxel_world(
+    mut world_load: ResMut<WorldLoad>,
+    mut load_progress: ResMut<VoxelWorldLoadProgress>,
+    mut new_gh: ResMut<NewGH>,
+    mut voxel_uniforms: ResMut<VoxelUniforms>,
+    mut load_errors: EventWriter<VoxelWorldLoadError>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if let WorldLoad::Parsing(task) = world_load.as_mut() {
+        let Some(result) = future::block_on(future::poll_once(task)) else {
+            return;
+        };
+
+        // keep the previous world if loading failed
+        match result {
+            Ok(gh) => {
+                *world_load = WorldLoad::Parsed(Arc::new(gh));
+            }
+            Err(error) => {
+                error!("{}", error);
+                load_errors.send(error);
+                *world_load = WorldLoad::None;
+                *load_progress = VoxelWorldLoadProgress::Done;
+                return;
+            }
+        }
+    }
+
+    if let WorldLoad::Parsed(gh) = world_load.as_ref() {
+        match start_upload(gh.clone(), &render_device) {
+            Ok(upload) => {
+                *world_load = upload;
+            }
+            Err(error) => {
+                error!("{}", error);
+                load_errors.send(error);
+                *world_load = WorldLoad::None;
+                *load_progress = VoxelWorldLoadProgress::Done;
+                return;
+            }
+        }
+    }
+
+    if let WorldLoad::Uploading {
+        gh,
+        brick_atlas,
+        brick_grid,
+        atlas_size,
+        row,
+    } = world_load.as_mut()
+    {
+        let rows = (gh.brick_count() as u32 + *atlas_size - 1) / *atlas_size;
+        let row_bytes = *atlas_size * BRICK_VOXELS as u32 * 2;
+        let end = (*row + (UPLOAD_BYTES_PER_FRAME / row_bytes).max(1)).min(rows);
+        for row in *row..end {
+            write_brick_row(&render_queue, gh, brick_atlas, *atlas_size, row);
+        }
+        *row = end;
+
+        if *row < rows {
+            *load_progress = VoxelWorldLoadProgress::Uploading(*row as f32 / rows as f32);
+            return;
+        }
+
+        voxel_uniforms.material_properties = gh.materials;
+        voxel_uniforms.set_world_size(gh.levels, gh.texture_size);
+
+        *new_gh = NewGH::Some {
+            gh: gh.clone(),
+            brick_atlas: brick_atlas.clone(),
+            atlas_size: *atlas_size,
+            brick_grid: brick_grid.clone(),
+        };
+        *world_load = WorldLoad::None;
+        *load_progress = VoxelWorldLoadProgress::Done;
+    }
+}
+
+/// Copies a world into the `VoxelPalette` and `VoxelWorldMirror` once it has been swapped in on
+/// the gpu.
+fn mirror_voxel_world(
+    new_gh: Res<NewGH>,
+    mut palette: ResMut<VoxelPalette>,
+    mirror: Option<ResMut<VoxelWorldMirror>>,
+) {
+    let NewGH::Some { gh, .. } = new_gh.as_ref() else {
+        return;
+    };
+    *palette = VoxelPalette::from_gh(gh);
+    if let Some(mut mirror) = mirror {
+        mirror.gh = gh.as_ref().clone();
+    }
+}
+
+/// Writes the `VoxelPalette` into the uniforms, and so to the gpu, whenever it changes.
+fn update_voxel_palette(
+    palette: Res<VoxelPalette>,
     mut voxel_uniforms: ResMut<VoxelUniforms>,
+    mirror: Option<ResMut<VoxelWorldMirror>>,
 ) {
-    match load_voxel_world.as_ref() {
-        LoadVoxelWorld::Empty(_) | LoadVoxelWorld::File(_) => {
-            let gh = match load_voxel_world.as_ref() {
-                LoadVoxelWorld::Empty(size) => GH::empty(*size),
-                LoadVoxelWorld::File(path) => {
-                    let file = std::fs::read(path).unwrap();
-                    GH::from_vox(&file).unwrap()
+    if !palette.is_changed() {
+        return;
+    }
+
+    for i in 0..256 {
+        voxel_uniforms.pallete[i].colour = palette.pallete_entry(i).into();
+        voxel_uniforms.material_properties[i].emission = palette.emission(i as u8);
+    }
+
+    if let Some(mut mirror) = mirror {
+        palette.write_to(&mut mirror.gh);
+    }
+}
+
+/// Writes the `MaterialRegistry` into the uniforms whenever it changes.
+fn update_material_registry(
+    material_registry: Res<MaterialRegistry>,
+    mut voxel_uniforms: ResMut<VoxelUniforms>,
+) {
+    if material_registry.is_changed() {
+        voxel_uniforms.gameplay_properties = material_registry.as_ref().into();
+        voxel_uniforms.material_ids = material_registry.as_ref().into();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resize_voxel_world(
+    mut resize_events: EventReader<ResizeVoxelWorld>,
+    mut world_resize: ResMut<WorldResize>,
+    mut voxel_uniforms: ResMut<VoxelUniforms>,
+    mut load_errors: EventWriter<VoxelWorldLoadError>,
+    mut floating_origin: ResMut<FloatingOrigin>,
+    streaming: Option<Res<VoxelStreaming>>,
+    mirror: Option<ResMut<VoxelWorldMirror>>,
+    mut transforms: Query<&mut Transform, Without<Parent>>,
+) {
+    *world_resize = WorldResize::None;
+
+    let Some(resize) = resize_events.iter().last() else {
+        return;
+    };
+    if let Err(error) = GH::check_size(resize.new_size) {
+        error!("{}", error);
+        load_errors.send(error);
+        return;
+    }
+    let old_size = voxel_uniforms.texture_size;
+    if resize.new_size == old_size {
+        return;
+    }
+
+    let (src, dst, size) = resize.anchor.resize_region(old_size, resize.new_size);
+    let shift = src.as_ivec3() - dst.as_ivec3();
+    if let Some(Err(error)) = streaming.map(|streaming| streaming.store.check_shift(shift)) {
+        error!("{}", error);
+        load_errors.send(error);
+        return;
+    }
+
+    *world_resize = WorldResize::Some {
+        old_size,
+        new_size: resize.new_size,
+        src,
+        dst,
+        size,
+    };
+    voxel_uniforms.set_world_size(GH::levels_for_size(resize.new_size), resize.new_size);
+    floating_origin.offset += shift;
+
+    if let Some(mut mirror) = mirror {
+        mirror.gh = mirror.gh.resized(resize.new_size, resize.anchor);
+    }
+    let translation = resize.anchor.resize_translation(old_size, resize.new_size);
+    for mut transform in transforms.iter_mut() {
+        transform.translation += translation;
+    }
+}
+
+/// Loads and saves the regions of the world and moves it to follow the `VoxelStreamingFocus`, see
+/// `VoxelStreaming`. The world moves on the gpu in the frame the regions it needs are ready, see
+/// `stream_voxel_world_prepare`.
+#[allow(clippy::too_many_arguments)]
+fn stream_voxel_world(
+    mut streaming: Option<ResMut<VoxelStreaming>>,
+    mut stream_job: ResMut<StreamJob>,
+    mut world_stream: ResMut<WorldStream>,
+    mut floating_origin: ResMut<FloatingOrigin>,
+    new_gh: Res<NewGH>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    load_progress: Res<VoxelWorldLoadProgress>,
+    mirror: Option<ResMut<VoxelWorldMirror>>,
+    focus: Query<&GlobalTransform, With<VoxelStreamingFocus>>,
+    mut transforms: Query<&mut Transform, Without<Parent>>,
+    mut shift_events: EventWriter<VoxelWorldShifted>,
+) {
+    // only clear an update that was sent last frame, so the resource is not extracted every frame
+    if world_stream.update.is_some() {
+        world_stream.update = None;
+    }
+
+    // saves are finished even if the world is replaced in the meantime, regions that could not be
+    // saved are dirty again
+    match stream_job.as_mut() {
+        StreamJob::Reading {
+            store,
+            saves,
+            readback,
+        } => {
+            let Some(regions) = readback.lock().unwrap().take() else {
+                return;
+            };
+            let saves = std::mem::take(saves);
+            // the render world has logged why the regions could not be read back
+            *stream_job = if regions.len() == saves.len() {
+                spawn_saves(store, saves.into_iter().zip(regions).collect())
+            } else {
+                if let Some(streaming) = streaming.as_mut() {
+                    streaming.mark_unsaved(saves);
+                }
+                StreamJob::None
+            };
+            return;
+        }
+        StreamJob::Saving(task) => {
+            let Some(failed) = future::block_on(future::poll_once(task)) else {
+                return;
+            };
+            if let Some(streaming) = streaming.as_mut() {
+                streaming.mark_unsaved(failed);
+            }
+            *stream_job = StreamJob::None;
+            return;
+        }
+        _ => {}
+    }
+
+    // a world that is being loaded replaces the one being streamed
+    let Some(mut streaming) = streaming else {
+        *stream_job = StreamJob::None;
+        return;
+    };
+    let new_world = matches!(*new_gh, NewGH::Some { .. });
+    if new_world || *load_progress != VoxelWorldLoadProgress::Done {
+        *stream_job = StreamJob::None;
+        return;
+    }
+
+    let texture_size = voxel_uniforms.texture_size;
+    match stream_job.as_mut() {
+        StreamJob::None => {
+            if let Err(error) = streaming.store.check_region_size(texture_size) {
+                if streaming.is_changed() {
+                    error!("{}", error);
                 }
-                LoadVoxelWorld::None => unreachable!(),
+                return;
+            }
+            let focus = focus.get_single().ok().map(|focus| focus.translation());
+            if let Some(job) =
+                start_stream_job(&mut streaming, &floating_origin, focus, texture_size)
+            {
+                *stream_job = job;
+            }
+        }
+        StreamJob::Loading {
+            shift,
+            texture_size: job_size,
+            task,
+        } => {
+            // the world was resized in the meantime
+            if *job_size != texture_size {
+                *stream_job = StreamJob::None;
+                return;
+            }
+            if !world_stream.pipeline_ready.load(Ordering::Acquire) {
+                return;
+            }
+            let Some(regions) = future::block_on(future::poll_once(task)) else {
+                return;
             };
+            let shift = *shift;
+
+            let saves = take_saves(
+                &mut streaming,
+                &floating_origin,
+                shift,
+                &regions,
+                texture_size,
+            );
+            if shift == IVec3::ZERO && regions.is_empty() && saves.is_empty() {
+                *stream_job = StreamJob::None;
+                return;
+            }
 
-            let mut levels = [UVec4::ZERO; 8];
-            for i in 0..8 {
-                levels[i] = UVec4::new(gh.levels[i], 0, 0, 0);
+            if let Some(mut mirror) = mirror.filter(|_| shift != IVec3::ZERO || !regions.is_empty())
+            {
+                shift_world(&mut mirror.gh, shift, &regions);
             }
+            let readback = Arc::new(Mutex::new(None));
+            world_stream.update = Some(StreamUpdate {
+                shift,
+                region_size: streaming.store.region_size,
+                saves: saves.iter().map(|(corner, _)| *corner).collect(),
+                loads: Arc::new(regions),
+                readback: readback.clone(),
+            });
+            finish_shift(
+                shift,
+                &mut floating_origin,
+                transforms.iter_mut(),
+                &mut shift_events,
+            );
 
-            voxel_uniforms.pallete = gh.pallete.clone().into();
-            voxel_uniforms.levels = levels;
-            voxel_uniforms.texture_size = gh.texture_size;
+            *stream_job = if saves.is_empty() {
+                StreamJob::None
+            } else {
+                StreamJob::Reading {
+                    store: streaming.store.clone(),
+                    saves: saves.into_iter().map(|(_, region)| region).collect(),
+                    readback,
+                }
+            };
+        }
+        StreamJob::Reading { .. } | StreamJob::Saving(_) => {}
+    }
+}
 
-            *new_gh = NewGH::Some(Arc::new(gh));
-            *load_voxel_world = LoadVoxelWorld::None;
+fn save_voxel_world(mut save_voxel_world: ResMut<SaveVoxelWorld>, mut save_path: ResMut<SavePath>) {
+    match save_voxel_world.as_ref() {
+        SaveVoxelWorld::File(path) => {
+            *save_path = SavePath::Some(path.clone());
+            *save_voxel_world = SaveVoxelWorld::None;
         }
-        LoadVoxelWorld::None => {
-            *new_gh = NewGH::None;
+        // only clear a path that was sent last frame, so the resource is not extracted every frame
+        SaveVoxelWorld::None => {
+            if matches!(save_path.as_ref(), SavePath::Some(_)) {
+                *save_path = SavePath::None;
+            }
         }
     }
 }
 
 fn load_voxel_world_prepare(
     mut voxel_data: ResMut<VoxelData>,
+    mut usage: ResMut<BrickAtlasUsage>,
     render_device: Res<RenderDevice>,
-    render_queue: Res<RenderQueue>,
     new_gh: Res<NewGH>,
 ) {
-    if let NewGH::Some(gh) = new_gh.as_ref() {
-        let buffer_size = gh.get_buffer_size();
+    // already uploaded by upload_voxel_world
+    if let NewGH::Some {
+        gh,
+        brick_atlas,
+        atlas_size,
+        brick_grid,
+    } = new_gh.as_ref()
+    {
+        swap_voxel_world(
+            &mut voxel_data,
+            &render_device,
+            brick_atlas,
+            *atlas_size,
+            brick_grid,
+            gh.texture_size,
+        );
+        usage.rebuilt(gh.brick_count() as u32);
+    }
+}
 
-        // grid hierarchy
-        voxel_data.grid_heierachy = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            contents: &vec![0; buffer_size],
-            label: None,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-        });
+/// Moves the bricks of the world into a brick grid of the new size, behind the commands of the
+/// last frame that still use the old one. Resizes only move whole bricks, so the brick atlas is
+/// kept as it is.
+fn resize_voxel_world_prepare(
+    mut voxel_data: ResMut<VoxelData>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    world_resize: Res<WorldResize>,
+) {
+    let WorldResize::Some {
+        old_size,
+        new_size,
+        src,
+        dst,
+        size,
+    } = *world_resize
+    else {
+        return;
+    };
 
-        // voxel world
-        let voxel_world = render_device.create_texture_with_data(
-            render_queue.as_ref(),
-            &TextureDescriptor {
-                label: None,
-                size: Extent3d {
-                    width: gh.texture_size,
-                    height: gh.texture_size,
-                    depth_or_array_layers: gh.texture_size,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D3,
-                format: TextureFormat::R16Uint,
-                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST,
-                view_formats: &[],
-            },
-            &gh.texture_data,
-        );
-        voxel_data.voxel_world = voxel_world.create_view(&TextureViewDescriptor::default());
+    let mut command_encoder =
+        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    let brick_grid = move_brick_grid(
+        &render_device,
+        &mut command_encoder,
+        &voxel_data.brick_grid,
+        old_size / BRICK_SIZE,
+        new_size / BRICK_SIZE,
+        src / BRICK_SIZE,
+        dst / BRICK_SIZE,
+        UVec3::splat(size / BRICK_SIZE),
+    );
+    render_queue.submit([command_encoder.finish()]);
 
-        // mip texture
-        let mip_count = gh.texture_size.trailing_zeros();
-        let mip_texture = render_device.create_texture(&TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: gh.texture_size,
-                height: gh.texture_size,
-                depth_or_array_layers: gh.texture_size,
-            },
-            mip_level_count: mip_count,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
-        voxel_data.mip_texture = mip_texture;
+    let brick_atlas = voxel_data.brick_atlas_texture.clone();
+    let atlas_size = voxel_data.atlas_size;
+    swap_voxel_world(
+        &mut voxel_data,
+        &render_device,
+        &brick_atlas,
+        atlas_size,
+        &brick_grid,
+        new_size,
+    );
+}
+
+/// Offset in bytes of the entry for `brick` in a brick grid with `grid_size` bricks along each
+/// side.
+fn brick_grid_offset(brick: UVec3, grid_size: u32) -> u64 {
+    let grid_size = grid_size as u64;
+    (brick.x as u64 * grid_size * grid_size + brick.y as u64 * grid_size + brick.z as u64) * 4
+}
+
+/// A new brick grid with `new_grid_size` bricks along each side, with the box of `size` bricks at
+/// `src` in `brick_grid` copied to `dst`. Bricks that are left out keep their slots until the
+/// atlas is rebuilt, so the counters carry over.
+#[allow(clippy::too_many_arguments)]
+fn move_brick_grid(
+    render_device: &RenderDevice,
+    command_encoder: &mut CommandEncoder,
+    brick_grid: &Buffer,
+    old_grid_size: u32,
+    new_grid_size: u32,
+    src: UVec3,
+    dst: UVec3,
+    size: UVec3,
+) -> Buffer {
+    let new_brick_grid = render_device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (new_grid_size.pow(3) as u64 + BRICK_GRID_COUNTERS as u64) * 4,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+
+    // rows of bricks along z are contiguous in both grids
+    for x in 0..size.x {
+        for y in 0..size.y {
+            let row = UVec3::new(x, y, 0);
+            command_encoder.copy_buffer_to_buffer(
+                brick_grid,
+                brick_grid_offset(src + row, old_grid_size),
+                &new_brick_grid,
+                brick_grid_offset(dst + row, new_grid_size),
+                size.z as u64 * 4,
+            );
+        }
     }
+    command_encoder.copy_buffer_to_buffer(
+        brick_grid,
+        old_grid_size.pow(3) as u64 * 4,
+        &new_brick_grid,
+        new_grid_size.pow(3) as u64 * 4,
+        BRICK_GRID_COUNTERS as u64 * 4,
+    );
+    new_brick_grid
+}
+
+/// Replaces the brick atlas and brick grid, along with a grid hierarchy and mip texture for a
+/// world of `texture_size`.
+fn swap_voxel_world(
+    voxel_data: &mut VoxelData,
+    render_device: &RenderDevice,
+    brick_atlas: &Texture,
+    atlas_size: u32,
+    brick_grid: &Buffer,
+    texture_size: u32,
+) {
+    // small worlds have no levels, but bindings can not be empty
+    let buffer_size = GH::get_buffer_size_from_levels(&GH::levels_for_size(texture_size)).max(4);
+
+    // grid hierarchy
+    voxel_data.grid_heierachy = render_device.create_buffer_with_data(&BufferInitDescriptor {
+        contents: &vec![0; buffer_size],
+        label: None,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    });
+
+    // bricks
+    voxel_data.brick_atlas = brick_atlas.create_view(&TextureViewDescriptor::default());
+    voxel_data.brick_atlas_texture = brick_atlas.clone();
+    voxel_data.atlas_size = atlas_size;
+    voxel_data.brick_grid = brick_grid.clone();
+
+    // mip texture
+    voxel_data.mip_texture = render_device.create_texture(&mip_texture_descriptor(texture_size));
 }
 
 fn queue_bind_group(render_device: Res<RenderDevice>, mut voxel_data: ResMut<VoxelData>) {
-    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    voxel_data.bind_group = create_bind_group(&render_device, &voxel_data);
+}
+
+fn create_bind_group(render_device: &RenderDevice, voxel_data: &VoxelData) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: &voxel_data.bind_group_layout,
         entries: &[
@@ -362,7 +1250,7 @@ fn queue_bind_group(render_device: Res<RenderDevice>, mut voxel_data: ResMut<Vox
             },
             BindGroupEntry {
                 binding: 1,
-                resource: BindingResource::TextureView(&voxel_data.voxel_world),
+                resource: BindingResource::TextureView(&voxel_data.brick_atlas),
             },
             BindGroupEntry {
                 binding: 2,
@@ -380,7 +1268,684 @@ fn queue_bind_group(render_device: Res<RenderDevice>, mut voxel_data: ResMut<Vox
                 binding: 4,
                 resource: BindingResource::Sampler(&voxel_data.texture_sampler),
             },
+            BindGroupEntry {
+                binding: 5,
+                resource: voxel_data.brick_grid.as_entire_binding(),
+            },
         ],
+    })
+}
+
+/// Copies `size` bytes of a buffer back from the gpu, this blocks until the gpu has finished.
+fn read_buffer(
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+    copy: impl FnOnce(&mut CommandEncoder, &Buffer),
+    size: u64,
+) -> Result<Vec<u8>, String> {
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("voxel world readback buffer"),
+        size,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
     });
-    voxel_data.bind_group = bind_group;
+
+    let mut command_encoder =
+        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    copy(&mut command_encoder, &buffer);
+    render_queue.submit([command_encoder.finish()]);
+
+    let map_state = map_buffer(&buffer);
+    render_device.poll(wgpu::Maintain::Wait);
+    if map_state.load(Ordering::Acquire) != MAP_DONE {
+        return Err("Failed to read the voxel world back from the gpu".to_string());
+    }
+
+    let data = buffer.slice(..).get_mapped_range().to_vec();
+    buffer.unmap();
+    Ok(data)
+}
+
+/// Copies the voxel world back from the gpu along with its palette and materials, this blocks
+/// until the gpu has finished the frame. Bricks the shaders emptied again are left out.
+pub fn read_voxel_world(
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+    voxel_data: &VoxelData,
+    voxel_uniforms: &VoxelUniforms,
+) -> Result<GH, String> {
+    let mut gh = GH::empty(voxel_uniforms.texture_size);
+    for i in 0..256 {
+        gh.pallete[i] = voxel_uniforms.pallete[i].colour.to_array();
+    }
+    gh.materials = voxel_uniforms.material_properties;
+
+    let grid_bytes = (gh.brick_grid.len() + BRICK_GRID_COUNTERS) as u64 * 4;
+    let brick_grid: Vec<u32> = read_buffer(
+        render_device,
+        render_queue,
+        |command_encoder, buffer| {
+            command_encoder.copy_buffer_to_buffer(&voxel_data.brick_grid, 0, buffer, 0, grid_bytes)
+        },
+        grid_bytes,
+    )?
+    .chunks_exact(4)
+    .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    .collect();
+    let atlas_size = voxel_data.atlas_size;
+    let slots = brick_grid[gh.brick_grid.len()].min(atlas_size.pow(3));
+
+    // read the used rows of slots a few at a time, rows in the buffer have to be aligned to 256
+    // bytes
+    let brick_size = BRICK_SIZE as usize;
+    let row_size = atlas_size * BRICK_SIZE * 2;
+    let padded_row_size = (row_size + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+        / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let slot_row_bytes = padded_row_size * BRICK_SIZE * BRICK_SIZE;
+    let slot_rows = (slots + atlas_size - 1) / atlas_size;
+    let rows_per_read = (READBACK_BYTES / slot_row_bytes).max(1);
+
+    let mut atlas = vec![0u16; (slot_rows * atlas_size) as usize * BRICK_VOXELS];
+    for first_row in (0..slot_rows).step_by(rows_per_read as usize) {
+        let rows = rows_per_read.min(slot_rows - first_row);
+        let data = read_buffer(
+            render_device,
+            render_queue,
+            |command_encoder, buffer| {
+                for i in 0..rows {
+                    command_encoder.copy_texture_to_buffer(
+                        ImageCopyTexture {
+                            origin: brick_atlas_origin((first_row + i) * atlas_size, atlas_size),
+                            ..voxel_data.brick_atlas_texture.as_image_copy()
+                        },
+                        ImageCopyBuffer {
+                            buffer,
+                            layout: ImageDataLayout {
+                                offset: (i * slot_row_bytes) as u64,
+                                bytes_per_row: NonZeroU32::new(padded_row_size),
+                                rows_per_image: NonZeroU32::new(BRICK_SIZE),
+                            },
+                        },
+                        Extent3d {
+                            width: atlas_size * BRICK_SIZE,
+                            height: BRICK_SIZE,
+                            depth_or_array_layers: BRICK_SIZE,
+                        },
+                    );
+                }
+            },
+            (rows * slot_row_bytes) as u64,
+        )?;
+
+        // undo the layout of write_brick_row
+        for (i, row) in data.chunks_exact(slot_row_bytes as usize).enumerate() {
+            let first_slot = (first_row as usize + i) * atlas_size as usize;
+            for (j, texels) in row.chunks_exact(padded_row_size as usize).enumerate() {
+                for (k, voxels) in texels[..row_size as usize]
+                    .chunks_exact(brick_size * 2)
+                    .enumerate()
+                {
+                    let start = (first_slot + k) * BRICK_VOXELS + j * brick_size;
+                    for (voxel, bytes) in atlas[start..start + brick_size]
+                        .iter_mut()
+                        .zip(voxels.chunks_exact(2))
+                    {
+                        *voxel = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    }
+                }
+            }
+        }
+    }
+
+    for (index, slot) in brick_grid[..gh.brick_grid.len()].iter().enumerate() {
+        if *slot == 0 || *slot > slots {
+            continue;
+        }
+        let start = (*slot as usize - 1) * BRICK_VOXELS;
+        let brick = &atlas[start..start + BRICK_VOXELS];
+        if brick.iter().any(|voxel| *voxel != 0) {
+            gh.bricks.extend_from_slice(brick);
+            gh.brick_grid[index] = gh.brick_count() as u32;
+        }
+    }
+    Ok(gh)
+}
+
+fn save_voxel_world_cleanup(
+    voxel_data: Res<VoxelData>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    save_path: Res<SavePath>,
+) {
+    if let SavePath::Some(path) = save_path.as_ref() {
+        let gh = match read_voxel_world(&render_device, &render_queue, &voxel_data, &voxel_uniforms)
+        {
+            Ok(gh) => gh,
+            Err(error) => {
+                error!("Failed to save voxel world to {}: {}", path, error);
+                return;
+            }
+        };
+        match std::fs::write(path, gh.to_file(path)) {
+            Ok(()) => info!("Saved voxel world to {}", path),
+            Err(error) => error!("Failed to save voxel world to {}: {}", path, error),
+        }
+    }
+}
+
+/// Position in bricks of the brick at `index` of a brick grid with `grid_size` bricks along each
+/// side.
+fn brick_position(index: usize, grid_size: u32) -> UVec3 {
+    let (index, grid_size) = (index as u32, grid_size);
+    UVec3::new(
+        index / (grid_size * grid_size),
+        index / grid_size % grid_size,
+        index % grid_size,
+    )
+}
+
+/// The stream buffer for the bricks of `regions` that are not air, see stream.wgsl.
+fn pack_stream_bricks(regions: &[(UVec3, GH)]) -> Vec<u32> {
+    let mut words = vec![0; 4];
+    for (corner, gh) in regions {
+        for (index, entry) in gh.brick_grid.iter().enumerate() {
+            if *entry == 0 {
+                continue;
+            }
+            let brick = *corner / BRICK_SIZE + brick_position(index, gh.brick_grid_size());
+            words.extend(brick.to_array());
+            words.extend(
+                gh.brick(*entry)
+                    .chunks_exact(2)
+                    .map(|voxels| voxels[0] as u32 | (voxels[1] as u32) << 16),
+            );
+        }
+    }
+    words[0] = ((words.len() - 4) / STREAM_BRICK_WORDS) as u32;
+    words
+}
+
+/// The stream buffer for every brick of the regions with their corners at `corners`, for the
+/// shaders to fill in.
+fn save_stream_bricks(corners: &[UVec3], region_size: u32) -> Vec<u32> {
+    let region_bricks = (region_size / BRICK_SIZE).pow(3) as usize;
+    let bricks = corners.len() * region_bricks;
+    let mut words = vec![0; 4 + bricks * STREAM_BRICK_WORDS];
+    words[0] = bricks as u32;
+    for (i, corner) in corners.iter().enumerate() {
+        for j in 0..region_bricks {
+            let brick = *corner / BRICK_SIZE + brick_position(j, region_size / BRICK_SIZE);
+            let start = 4 + (i * region_bricks + j) * STREAM_BRICK_WORDS;
+            words[start..start + 3].copy_from_slice(&brick.to_array());
+        }
+    }
+    words
+}
+
+/// The regions of a stream buffer from `save_stream_bricks` once the shaders have filled it in,
+/// leaving out bricks of air.
+fn unpack_stream_bricks(words: &[u32], region_size: u32) -> Vec<GH> {
+    let region_words = (region_size / BRICK_SIZE).pow(3) as usize * STREAM_BRICK_WORDS;
+    words[4..]
+        .chunks_exact(region_words)
+        .map(|region| {
+            let mut gh = GH::empty(region_size);
+            for (index, brick) in region.chunks_exact(STREAM_BRICK_WORDS).enumerate() {
+                let voxels = &brick[3..];
+                if voxels.iter().all(|voxels| *voxels == 0) {
+                    continue;
+                }
+                gh.bricks.extend(
+                    voxels
+                        .iter()
+                        .flat_map(|voxels| [*voxels as u16, (*voxels >> 16) as u16]),
+                );
+                gh.brick_grid[index] = gh.brick_count() as u32;
+            }
+            gh
+        })
+        .collect()
+}
+
+fn dispatch_stream(
+    command_encoder: &mut CommandEncoder,
+    pipeline: &ComputePipeline,
+    voxel_bind_group: &BindGroup,
+    stream_bind_group: &BindGroup,
+    bricks: u32,
+) {
+    let (x, y) = stream::dispatch_size(bricks);
+    let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, voxel_bind_group, &[]);
+    pass.set_bind_group(1, stream_bind_group, &[]);
+    pass.dispatch_workgroups(x, y, 1);
+}
+
+fn create_stream_buffer(
+    render_device: &RenderDevice,
+    pipeline: &stream::Pipeline,
+    words: &[u32],
+) -> (Buffer, BindGroup) {
+    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+        contents: bytemuck::cast_slice(words),
+        label: Some("stream buffer"),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+    });
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    (buffer, bind_group)
+}
+
+/// Moves the world for a `StreamUpdate`, after copying the regions that are saved out of it to be
+/// read back without waiting on the gpu. Only the brick grid moves, bricks that go out of view
+/// keep their slots until the atlas is rebuilt. The loaded regions replace what was there and
+/// their bricks are given slots on the gpu.
+#[allow(clippy::too_many_arguments)]
+fn stream_voxel_world_prepare(
+    mut voxel_data: ResMut<VoxelData>,
+    mut usage: ResMut<BrickAtlasUsage>,
+    mut stream_readbacks: ResMut<StreamReadbacks>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    world_stream: Res<WorldStream>,
+    pipeline: Res<stream::Pipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let (Some(save_pipeline), Some(load_pipeline)) = (
+        pipeline_cache.get_compute_pipeline(pipeline.save),
+        pipeline_cache.get_compute_pipeline(pipeline.load),
+    ) else {
+        return;
+    };
+    world_stream.pipeline_ready.store(true, Ordering::Release);
+    let Some(update) = &world_stream.update else {
+        return;
+    };
+
+    // make room for the loaded bricks first, the slots in use were read a few frames ago
+    let load_words = pack_stream_bricks(&update.loads);
+    let load_bricks = load_words[0];
+    let capacity = voxel_data.atlas_size.pow(3);
+    if usage.slots.saturating_add(load_bricks) > capacity && usage.slots > usage.rebuilt_slots {
+        match rebuild_brick_atlas(
+            &mut voxel_data,
+            &voxel_uniforms,
+            &render_device,
+            &render_queue,
+            load_bricks as usize,
+        ) {
+            Ok(bricks) => usage.rebuilt(bricks),
+            Err(error) => error!("Failed to grow the brick atlas: {}", error),
+        }
+        write_uniforms(
+            &mut voxel_data,
+            &voxel_uniforms,
+            &render_device,
+            &render_queue,
+        );
+    }
+
+    let mut command_encoder =
+        render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+    if !update.saves.is_empty() {
+        let words = save_stream_bricks(&update.saves, update.region_size);
+        let (buffer, stream_bind_group) = create_stream_buffer(&render_device, &pipeline, &words);
+        dispatch_stream(
+            &mut command_encoder,
+            save_pipeline,
+            &create_bind_group(&render_device, &voxel_data),
+            &stream_bind_group,
+            words[0],
+        );
+
+        let readback = render_device.create_buffer(&BufferDescriptor {
+            label: Some("stream readback buffer"),
+            size: buffer.size(),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        command_encoder.copy_buffer_to_buffer(&buffer, 0, &readback, 0, buffer.size());
+        stream_readbacks.0.push(StreamReadback {
+            buffer: readback,
+            map_state: None,
+            region_size: update.region_size,
+            regions: update.readback.clone(),
+        });
+    }
+
+    let grid_size = voxel_uniforms.texture_size / BRICK_SIZE;
+    if update.shift != IVec3::ZERO {
+        let shift = update.shift / BRICK_SIZE as i32;
+        let kept = (IVec3::splat(grid_size as i32) - shift.abs()).max(IVec3::ZERO);
+        voxel_data.brick_grid = move_brick_grid(
+            &render_device,
+            &mut command_encoder,
+            &voxel_data.brick_grid,
+            grid_size,
+            grid_size,
+            shift.max(IVec3::ZERO).as_uvec3(),
+            (-shift).max(IVec3::ZERO).as_uvec3(),
+            kept.as_uvec3(),
+        );
+    }
+
+    if !update.loads.is_empty() {
+        let region_bricks = update.region_size / BRICK_SIZE;
+        for (corner, _) in update.loads.iter() {
+            for x in 0..region_bricks {
+                for y in 0..region_bricks {
+                    let row = *corner / BRICK_SIZE + UVec3::new(x, y, 0);
+                    command_encoder.clear_buffer(
+                        &voxel_data.brick_grid,
+                        brick_grid_offset(row, grid_size),
+                        BufferSize::new(region_bricks as u64 * 4),
+                    );
+                }
+            }
+        }
+
+        let (_buffer, stream_bind_group) =
+            create_stream_buffer(&render_device, &pipeline, &load_words);
+        dispatch_stream(
+            &mut command_encoder,
+            load_pipeline,
+            &create_bind_group(&render_device, &voxel_data),
+            &stream_bind_group,
+            load_bricks,
+        );
+    }
+    render_queue.submit([command_encoder.finish()]);
+}
+
+/// Regions copied out of the world for `StreamUpdate::readback`, waiting on the gpu.
+#[derive(Resource, Default)]
+struct StreamReadbacks(Vec<StreamReadback>);
+
+struct StreamReadback {
+    buffer: Buffer,
+    map_state: Option<Arc<AtomicU8>>,
+    region_size: u32,
+    regions: Arc<Mutex<Option<Vec<GH>>>>,
+}
+
+/// Starts mapping `buffer` for reading, the state moves from `MAP_PENDING` once it is done.
+fn map_buffer(buffer: &Buffer) -> Arc<AtomicU8> {
+    let map_state = Arc::new(AtomicU8::new(MAP_PENDING));
+    let callback_state = map_state.clone();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let state = match result {
+                Ok(()) => MAP_DONE,
+                Err(_) => MAP_FAILED,
+            };
+            callback_state.store(state, Ordering::Release);
+        });
+    map_state
+}
+
+fn read_stream_cleanup(
+    mut stream_readbacks: ResMut<StreamReadbacks>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    render_device: Res<RenderDevice>,
+) {
+    if stream_readbacks.0.is_empty() {
+        return;
+    }
+
+    render_device.poll(wgpu::Maintain::Poll);
+    stream_readbacks.0.retain_mut(|readback| {
+        let Some(map_state) = &readback.map_state else {
+            readback.map_state = Some(map_buffer(&readback.buffer));
+            return true;
+        };
+        let regions = match map_state.load(Ordering::Acquire) {
+            MAP_PENDING => return true,
+            MAP_FAILED => {
+                error!("Failed to read back the voxel world regions to save");
+                None
+            }
+            _ => {
+                let mut regions = unpack_stream_bricks(
+                    bytemuck::cast_slice(&readback.buffer.slice(..).get_mapped_range()),
+                    readback.region_size,
+                );
+                readback.buffer.unmap();
+                for gh in &mut regions {
+                    for i in 0..256 {
+                        gh.pallete[i] = voxel_uniforms.pallete[i].colour.to_array();
+                    }
+                    gh.materials = voxel_uniforms.material_properties;
+                }
+                Some(regions)
+            }
+        };
+        *readback.regions.lock().unwrap() = Some(regions.unwrap_or_default());
+        false
+    });
+}
+
+/// Reads back the `BRICK_GRID_COUNTERS` of the brick grid, without waiting on the gpu.
+#[derive(Resource)]
+struct BrickAtlasUsage {
+    buffer: Buffer,
+    map_state: Option<Arc<AtomicU8>>,
+    /// Slots in use when the counters were last read.
+    slots: u32,
+    /// Slots in use right after the atlas was last rebuilt or a world loaded, only slots used
+    /// since can be reclaimed by rebuilding it.
+    rebuilt_slots: u32,
+}
+
+impl BrickAtlasUsage {
+    fn rebuilt(&mut self, bricks: u32) {
+        self.slots = bricks;
+        self.rebuilt_slots = bricks;
+    }
+}
+
+/// Grows the brick atlas when it runs low on slots or the shaders dropped writes because it was
+/// full, and warns about the dropped writes.
+fn check_brick_atlas(
+    mut usage: ResMut<BrickAtlasUsage>,
+    mut voxel_data: ResMut<VoxelData>,
+    voxel_uniforms: Res<VoxelUniforms>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let counters = voxel_data.brick_grid.size() - BRICK_GRID_COUNTERS as u64 * 4;
+    let Some(map_state) = usage.map_state.take() else {
+        let mut command_encoder =
+            render_device.create_command_encoder(&CommandEncoderDescriptor::default());
+        command_encoder.copy_buffer_to_buffer(
+            &voxel_data.brick_grid,
+            counters,
+            &usage.buffer,
+            0,
+            BRICK_GRID_COUNTERS as u64 * 4,
+        );
+        render_queue.submit([command_encoder.finish()]);
+        usage.map_state = Some(map_buffer(&usage.buffer));
+        return;
+    };
+
+    render_device.poll(wgpu::Maintain::Poll);
+    match map_state.load(Ordering::Acquire) {
+        MAP_PENDING => {
+            usage.map_state = Some(map_state);
+            return;
+        }
+        MAP_FAILED => return,
+        _ => {}
+    }
+    let [slots, dropped]: [u32; BRICK_GRID_COUNTERS] =
+        bytemuck::cast_slice::<u8, u32>(&usage.buffer.slice(..).get_mapped_range())
+            .try_into()
+            .unwrap();
+    usage.buffer.unmap();
+    usage.slots = slots;
+
+    // the counters may be from a grid that has been replaced since, which at worst rebuilds the
+    // atlas once more than needed
+    if dropped > 0 {
+        warn!(
+            "{} voxel writes were dropped because the brick atlas was full",
+            dropped
+        );
+        render_queue.write_buffer(&voxel_data.brick_grid, counters + 4, &[0; 4]);
+    }
+    // a rebuild only helps once enough slots have been used since the last one, which keeps an
+    // atlas that can not grow any further from being rebuilt over and over
+    let capacity = voxel_data.atlas_size.pow(3);
+    let used = slots.saturating_sub(usage.rebuilt_slots);
+    let running_low = slots >= capacity / 4 * 3 && used >= capacity / 8;
+    if !(running_low || dropped > 0 && used > 0) {
+        return;
+    }
+
+    match rebuild_brick_atlas(
+        &mut voxel_data,
+        &voxel_uniforms,
+        &render_device,
+        &render_queue,
+        0,
+    ) {
+        Ok(bricks) => usage.rebuilt(bricks),
+        Err(error) => error!("Failed to grow the brick atlas: {}", error),
+    }
+}
+
+/// Reads the world back and uploads it into a new brick atlas sized for the bricks it has and
+/// `extra_bricks` more, giving how many bricks it has. This also frees the slots of bricks that
+/// were emptied again, lost to races between shaders or moved out of the world. Nothing runs on
+/// the gpu in between, so no changes are lost. The current atlas is kept if the world cannot be
+/// read back.
+fn rebuild_brick_atlas(
+    voxel_data: &mut VoxelData,
+    voxel_uniforms: &VoxelUniforms,
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+    extra_bricks: usize,
+) -> Result<u32, String> {
+    let gh = read_voxel_world(render_device, render_queue, voxel_data, voxel_uniforms)?;
+    let atlas_size = brick_atlas_size(
+        gh.brick_count().saturating_add(extra_bricks),
+        gh.brick_grid.len(),
+        max_atlas_size(render_device),
+    )
+    .map_err(|error| error.to_string())?;
+    let brick_atlas = render_device.create_texture(&brick_atlas_descriptor(atlas_size));
+    let brick_grid = create_brick_grid(render_device, &gh);
+    let rows = (gh.brick_count() as u32 + atlas_size - 1) / atlas_size;
+    for row in 0..rows {
+        write_brick_row(render_queue, &gh, &brick_atlas, atlas_size, row);
+    }
+    info!(
+        "Rebuilt the brick atlas for {} bricks with {} slots",
+        gh.brick_count(),
+        atlas_size.pow(3)
+    );
+
+    swap_voxel_world(
+        voxel_data,
+        render_device,
+        &brick_atlas,
+        atlas_size,
+        &brick_grid,
+        gh.texture_size,
+    );
+    Ok(gh.brick_count() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atlas_has_room_to_spare() {
+        let size = brick_atlas_size(1000, 1 << 18, 128).unwrap();
+        assert!(size.pow(3) >= 2000 + SPARE_BRICKS);
+        assert!((size - 1).pow(3) < 2000 + SPARE_BRICKS);
+    }
+
+    #[test]
+    fn atlas_never_outgrows_the_grid_or_device() {
+        // a 128 world has 512 bricks
+        let size = brick_atlas_size(10, 512, 128).unwrap();
+        assert!(size.pow(3) >= 512 && (size - 1).pow(3) < 512);
+
+        assert_eq!(brick_atlas_size(50, 1 << 24, 4).unwrap(), 4);
+        assert!(brick_atlas_size(usize::MAX, usize::MAX, 4).is_err());
+        assert!(brick_atlas_size(65, 1 << 24, 4).is_err());
+    }
+
+    #[test]
+    fn empty_worlds_always_fit() {
+        assert_eq!(brick_atlas_size(0, 0, 1).unwrap(), 1);
+        assert!(brick_atlas_size(0, 512, 1).is_ok());
+    }
+
+    #[test]
+    fn loaded_bricks_are_packed_with_their_position() {
+        let mut region = GH::empty(32);
+        region.set_voxel(UVec3::new(17, 3, 5), 7, 1);
+        let words = pack_stream_bricks(&[(UVec3::new(64, 0, 32), region)]);
+
+        assert_eq!(words[0], 1);
+        assert_eq!(words.len(), 4 + STREAM_BRICK_WORDS);
+        assert_eq!(words[4..7], [5, 0, 2]);
+        // the voxel is the second half of its word
+        let index = GH::brick_voxel_index(UVec3::new(17, 3, 5));
+        assert_eq!(words[7 + index / 2], (7 | 1 << 8) << 16);
+        assert_eq!(words[7..].iter().filter(|word| **word != 0).count(), 1);
+    }
+
+    #[test]
+    fn saved_bricks_unpack_into_regions() {
+        let mut world = GH::empty(64);
+        world.set_voxel(UVec3::new(1, 2, 3), 1, 0);
+        world.set_voxel(UVec3::new(40, 20, 10), 2, 3);
+        world.set_voxel(UVec3::new(63, 31, 31), 4, 0);
+        let corners = [UVec3::ZERO, UVec3::new(32, 0, 0)];
+        let mut words = save_stream_bricks(&corners, 32);
+        assert_eq!(words[0], 16);
+
+        // what stream.wgsl does for the save
+        for brick in words[4..].chunks_exact_mut(STREAM_BRICK_WORDS) {
+            let origin = UVec3::new(brick[0], brick[1], brick[2]) * BRICK_SIZE;
+            for (word, voxels) in brick[3..].iter_mut().enumerate() {
+                let voxel = |index: usize| {
+                    let pos = brick_position(index, BRICK_SIZE);
+                    let (material, flags) = world.get_voxel(origin + pos);
+                    material as u32 | (flags as u32) << 8
+                };
+                *voxels = voxel(word * 2) | voxel(word * 2 + 1) << 16;
+            }
+        }
+
+        let regions = unpack_stream_bricks(&words, 32);
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].brick_count(), 1);
+        assert_eq!(regions[1].brick_count(), 2);
+        for (region, corner) in regions.iter().zip(corners) {
+            for x in 0..32 {
+                for y in 0..32 {
+                    for z in 0..32 {
+                        let pos = UVec3::new(x, y, z);
+                        assert_eq!(region.get_voxel(pos), world.get_voxel(corner + pos));
+                    }
+                }
+            }
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use super::voxel_world::{VoxelData, VoxelUniforms};
-use crate::{Flags, RenderGraphSettings, VOXELS_PER_METER};
+use crate::{MaterialRegistry, RegisteredMaterial, RenderGraphSettings, VOXELS_PER_METER};
 use bevy::{
     asset::load_internal_asset,
     core_pipeline::{clear_color::ClearColorConfig, core_3d::Transparent3d},
@@ -182,10 +182,10 @@ pub struct VoxelizationMaterial {
 
 impl Default for VoxelizationMaterial {
     fn default() -> Self {
-        Self {
-            material: VoxelizationMaterialType::Material(10),
-            flags: Flags::ANIMATION_FLAG,
-        }
+        MaterialRegistry::default()
+            .get(MaterialRegistry::DEFAULT)
+            .map(RegisteredMaterial::voxelization_material)
+            .expect("Default material registry has a default material")
     }
 }
 